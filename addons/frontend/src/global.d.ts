@@ -2,5 +2,13 @@ interface Window {
   initTypstSvg(docRoot: SVGElement): void;
   handleTypstLocation(elem: Element, page: number, x: number, y: number);
   typstWebsocket: WebSocket;
+  typstDocumentInfo?: { fonts: string[]; packages: string[] };
+  typstSetBookmark(name: string): void;
+  typstJumpToBookmark(name: string): void;
+  typstDeleteBookmark(name: string): void;
+  typstAddComment(text: string, author?: string): void;
+  typstDeleteComment(id: string): void;
+  typstSetOverlays(overlays: { contentBounds?: boolean; baselineGrid?: boolean; boundingBoxes?: boolean }): void;
 }
 const acquireVsCodeApi: any;
+declare const __TYPST_PREVIEW_FRONTEND_BUILD__: string;
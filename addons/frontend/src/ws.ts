@@ -9,7 +9,7 @@ import renderModule from "@myriaddreamin/typst-ts-renderer/pkg/typst_ts_renderer
 // import { RenderSession as RenderSession2 } from "@myriaddreamin/typst-ts-renderer/pkg/wasm-pack-shim.mjs";
 import { RenderSession } from "@myriaddreamin/typst.ts/dist/esm/renderer.mjs";
 import { WebSocketSubject, webSocket } from 'rxjs/webSocket';
-import { Subject, Subscription, buffer, debounceTime, fromEvent, tap } from "rxjs";
+import { Subject, Subscription, buffer, debounceTime, fromEvent, merge, tap, throttleTime } from "rxjs";
 export { PreviewMode } from 'typst-dom/typst-doc.mjs';
 
 // for debug propose
@@ -21,6 +21,8 @@ const enc = new TextEncoder();
 const dec = new TextDecoder();
 const NOT_AVAIABLE = "current not avalible";
 const COMMA = enc.encode(",");
+// Identifies this client as an interactive webview to the preview server.
+const WEBVIEW_PROTOCOL = "typst-preview.webview.v1";
 export interface WsArgs {
     url: string;
     previewMode: PreviewMode;
@@ -71,6 +73,22 @@ export async function wsMain({ url, previewMode, isContentPreview }: WsArgs) {
                 subscribe(() => svgDoc.addViewportChange())
         );
 
+        // notify the editor when the range of visible pages changes
+        let lastVisiblePages = "";
+        subsribes.push(
+            merge(fromEvent(window, "scroll"), fromEvent(window, "resize")).
+                pipe(throttleTime(200, undefined, { leading: true, trailing: true })).
+                subscribe(() => {
+                    const { pages } = retrieveViewport(hookedElem);
+                    const visiblePages = JSON.stringify(pages);
+                    if (visiblePages === lastVisiblePages || !window.typstWebsocket) {
+                        return;
+                    }
+                    lastVisiblePages = visiblePages;
+                    window.typstWebsocket.send(`visible-pages,${visiblePages}`);
+                })
+        );
+
         if (!isContentPreview) {
             subsribes.push(
                 fromEvent(window, "scroll").
@@ -213,10 +231,61 @@ export async function wsMain({ url, previewMode, isContentPreview }: WsArgs) {
         return svgDoc;
     }
 
+    // bookmarks the reading position at the top of the viewport
+    window.typstSetBookmark = (name: string) => {
+        const { position } = retrieveViewport(document.getElementById("typst-app"));
+        if (!position) {
+            return;
+        }
+        window.typstWebsocket?.send(`bookmark,${JSON.stringify({ name, position })}`);
+    };
+    window.typstJumpToBookmark = (name: string) => {
+        window.typstWebsocket?.send(`bookmark-jump,${name}`);
+    };
+    window.typstDeleteBookmark = (name: string) => {
+        window.typstWebsocket?.send(`bookmark-delete,${name}`);
+    };
+
+    // comments on the reading position at the top of the viewport
+    window.typstAddComment = (text: string, author?: string) => {
+        const { position } = retrieveViewport(document.getElementById("typst-app"));
+        if (!position) {
+            return;
+        }
+        window.typstWebsocket?.send(`comment-add,${JSON.stringify({ text, author, position })}`);
+    };
+    window.typstDeleteComment = (id: string) => {
+        window.typstWebsocket?.send(`comment-delete,${id}`);
+    };
+
+    window.typstSetOverlays = (overlays) => {
+        window.typstWebsocket?.send(`overlays,${JSON.stringify(overlays)}`);
+    };
+
+    // Renders only the first viewport of the first document, and the rest of
+    // the document once the browser is idle, to show the preview sooner.
+    let firstDocument = true;
+    function renderFirstViewport(svgDoc: TypstDocument, change: [string, string]) {
+        const partialRendering = svgDoc.impl.partialRendering;
+        svgDoc.setPartialRendering(true);
+        svgDoc.addChangement(change);
+
+        const restore = () => {
+            svgDoc.setPartialRendering(partialRendering);
+            svgDoc.addViewportChange();
+        };
+        if (window.requestIdleCallback) {
+            window.requestIdleCallback(restore, { timeout: 1000 });
+        } else {
+            setTimeout(restore, 100);
+        }
+    }
+
     function setupSocket(svgDoc: TypstDocument): () => void {
         // todo: reconnect setTimeout(() => setupSocket(svgDoc), 1000);
         $ws = webSocket<ArrayBuffer>({
             url,
+            protocol: WEBVIEW_PROTOCOL,
             binaryType: "arraybuffer",
             serializer: t => t,
             deserializer: (event) => event.data,
@@ -226,7 +295,11 @@ export async function wsMain({ url, previewMode, isContentPreview }: WsArgs) {
                     console.log('WebSocket connection opened', sock);
                     window.typstWebsocket = sock as any;
                     svgDoc.reset();
+                    firstDocument = true;
+                    window.typstWebsocket.send(`frontend-version,${__TYPST_PREVIEW_FRONTEND_BUILD__}`);
                     window.typstWebsocket.send("current");
+                    window.typstWebsocket.send("comment-list");
+                    window.typstWebsocket.send("document-info");
                 }
             },
             closeObserver: {
@@ -254,8 +327,30 @@ export async function wsMain({ url, previewMode, isContentPreview }: WsArgs) {
         // window.typstWebsocket = new WebSocket("ws://127.0.0.1:23625");
 
 
+        // spilled updates are fetched before the messages after them are processed
+        let received = Promise.resolve();
+        // whether an update was lost, so that the deltas are dropped until the
+        // whole document comes again
+        let resyncing = false;
         $ws.subscribe({
-            next: (data) => batchMessageChannel.next(data), // Called whenever there is a message from the server.
+            next: (data) => { // Called whenever there is a message from the server.
+                received = received
+                    .then(() => fetchSpilled(data))
+                    .then(data => {
+                        if (resyncing && hasPrefix(data, DIFF_PREFIX)) {
+                            return;
+                        }
+                        if (hasPrefix(data, NEW_PREFIX)) {
+                            resyncing = false;
+                        }
+                        batchMessageChannel.next(data);
+                    })
+                    .catch(err => {
+                        console.error("failed to fetch a spilled update, requesting the whole document", err);
+                        resyncing = true;
+                        window.typstWebsocket.send("current");
+                    });
+            },
             error: err => console.log("WebSocket Error: ", err), // Called if at any point WebSocket API signals some kind of error.
             complete: () => console.log('complete') // Called when connection is closed (for whatever reason).
         });
@@ -352,10 +447,54 @@ export async function wsMain({ url, previewMode, isContentPreview }: WsArgs) {
                 console.log("cursor-paths", paths);
                 svgDoc.impl.setCursorPaths(paths);
                 return;
+            } else if (message[0] === "error-paths") {
+                const paths = JSON.parse(dec
+                    .decode((message[1] as any).buffer));
+                console.log("error-paths", paths);
+                svgDoc.impl.setErrorPaths(paths);
+                return;
+            } else if (message[0] === "finding-paths") {
+                const paths = JSON.parse(dec
+                    .decode((message[1] as any).buffer));
+                svgDoc.impl.setFindingPaths(paths);
+                return;
+            } else if (message[0] === "highlight") {
+                const rects = JSON.parse(dec
+                    .decode((message[1] as any).buffer));
+                svgDoc.impl.setHighlightRects(rects);
+                return;
+            } else if (message[0] === "overlay") {
+                const shapes = JSON.parse(dec
+                    .decode((message[1] as any).buffer));
+                svgDoc.impl.setOverlayShapes(shapes);
+                return;
+            } else if (message[0] === "comments") {
+                const comments = JSON.parse(dec
+                    .decode((message[1] as any).buffer));
+                svgDoc.impl.setComments(comments);
+                return;
+            } else if (message[0] === "document-info") {
+                // the fonts and packages of the latest document, for an info panel
+                window.typstDocumentInfo = JSON.parse(dec
+                    .decode((message[1] as any).buffer));
+                return;
+            } else if (message[0] === "query-viewport") {
+                const info = retrieveViewport(document.getElementById("typst-app"));
+                window.typstWebsocket.send(`viewport-info,${JSON.stringify(info)}`);
+                return;
             } else if (message[0] === "partial-rendering") {
                 console.log("Experimental feature: partial rendering enabled");
                 svgDoc.setPartialRendering(true);
                 return;
+            } else if (message[0] === "partial-document") {
+                // the document is left behind the sources due to a compile error
+                const partial = dec.decode((message[1] as any).buffer) === "true";
+                document.getElementById("typst-app")?.classList.toggle("partial-document", partial);
+                return;
+            } else if (message[0] === "safe-mode") {
+                const report = JSON.parse(dec.decode((message[1] as any).buffer));
+                console.warn(report.message.text, report);
+                return;
             } else if (message[0] === "invert-colors") {
                 const strategy = dec.decode((message[1] as any).buffer);
                 console.log("Experimental feature: invert colors strategy taken:", strategy);
@@ -364,6 +503,30 @@ export async function wsMain({ url, previewMode, isContentPreview }: WsArgs) {
             } else if (message[0] === "outline") {
                 console.log("Experimental feature: outline rendering");
                 return;
+            } else if (message[0] === "version-mismatch") {
+                const mismatch = JSON.parse(dec.decode((message[1] as any).buffer));
+                console.warn("frontend doesn't match the preview server", mismatch);
+                if (mismatch.frontendAvailable) {
+                    window.typstWebsocket.send("frontend");
+                }
+                return;
+            } else if (message[0] === "frontend") {
+                // replace this page by the frontend embedded in the server
+                const html = dec.decode((message[1] as any).buffer).replace(
+                    "preview-arg:previewMode:Doc",
+                    `preview-arg:previewMode:${PreviewMode[previewMode]}`
+                );
+                dispose();
+                document.open();
+                document.write(html);
+                document.close();
+                return;
+            }
+
+            if (firstDocument) {
+                firstDocument = false;
+                renderFirstViewport(svgDoc, message as any);
+                return;
             }
 
             svgDoc.addChangement(message as any);
@@ -393,6 +556,81 @@ export async function wsMain({ url, previewMode, isContentPreview }: WsArgs) {
         }));
 };
 
+const SPILLED_PREFIX = enc.encode("spilled,");
+const NEW_PREFIX = enc.encode("new,");
+const DIFF_PREFIX = enc.encode("diff-v1,");
+const SPILL_CHUNK_SIZE = 4 * 1024 * 1024;
+
+function hasPrefix(data: ArrayBuffer, prefix: Uint8Array): boolean {
+    if (!(data instanceof ArrayBuffer) || data.byteLength < prefix.length) {
+        return false;
+    }
+    const messageData = new Uint8Array(data, 0, prefix.length);
+    return prefix.every((c, i) => messageData[i] === c);
+}
+
+/// Fetches an update spilled to disk by the server in chunks, or returns the
+/// message as is if it is not a spilled update.
+async function fetchSpilled(data: ArrayBuffer): Promise<ArrayBuffer> {
+    if (!hasPrefix(data, SPILLED_PREFIX)) {
+        return data;
+    }
+
+    const messageData = new Uint8Array(data);
+    const { url, size } = JSON.parse(dec.decode(messageData.slice(SPILLED_PREFIX.length)));
+    const update = new Uint8Array(size);
+    for (let start = 0; start < size; start += SPILL_CHUNK_SIZE) {
+        const end = Math.min(start + SPILL_CHUNK_SIZE, size) - 1;
+        const res = await fetch(url, { headers: { Range: `bytes=${start}-${end}` } });
+        // a whole file or an error page would be copied at the wrong place
+        if (res.status !== 206) {
+            throw new Error(`failed to fetch ${url}: ${res.status} ${res.statusText}`);
+        }
+        const chunk = new Uint8Array(await res.arrayBuffer());
+        if (chunk.byteLength !== end - start + 1) {
+            throw new Error(`failed to fetch ${url}: got ${chunk.byteLength} bytes at ${start}`);
+        }
+        update.set(chunk, start);
+    }
+    return update.buffer;
+}
+
+/// Finds the visible pages (1-based) and the document position at the top
+/// left corner of the viewport.
+function retrieveViewport(root: HTMLElement | null) {
+    const docRoot = root?.firstElementChild;
+    if (!docRoot) {
+        return { pages: [], position: null };
+    }
+
+    const pageElems: Element[] = docRoot.getAttribute("data-render-mode") === "canvas"
+        ? Array.from(docRoot.querySelectorAll('.typst-page'))
+        : Array.from(docRoot.children).filter(e => e.tagName === "g");
+
+    const pages: number[] = [];
+    let position = null;
+    pageElems.forEach((page, idx) => {
+        const rect = page.getBoundingClientRect();
+        if (rect.bottom < 0 || rect.top > window.innerHeight) {
+            return;
+        }
+        pages.push(idx + 1);
+        if (!position) {
+            // converts the client distance to pt by the page height in pt
+            const pageHeight = page instanceof SVGGraphicsElement ? page.getBBox().height :
+                Number.parseFloat(page.firstElementChild?.getAttribute("data-page-height") || "0");
+            const scale = rect.height > 0 ? pageHeight / rect.height : 0;
+            position = {
+                page_no: idx + 1,
+                x: Math.max(0, -rect.left) * scale,
+                y: Math.max(0, -rect.top) * scale,
+            };
+        }
+    });
+
+    return { pages, position };
+}
+
 function ensureInvertColors(root: HTMLElement | null, strategy: string) {
     if (!root) {
         return;
@@ -1,8 +1,21 @@
 import { defineConfig } from "vite";
 import { viteSingleFile } from "vite-plugin-singlefile";
+import { createHash } from "crypto";
+
+// A unique id of this build, which the server extracts from the bundle to
+// detect webviews running a frontend that doesn't match the server.
+const buildId = createHash("sha256")
+  .update(`${Date.now()}-${Math.random()}`)
+  .digest("hex")
+  .slice(0, 16);
 
 export default defineConfig({
   plugins: [viteSingleFile()],
+  define: {
+    __TYPST_PREVIEW_FRONTEND_BUILD__: JSON.stringify(
+      `typst-preview-frontend-build:${buildId}`
+    ),
+  },
   build: {
     minify: false,
     rollupOptions: {
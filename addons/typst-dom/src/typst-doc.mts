@@ -64,6 +64,8 @@ export class TypstDocumentContext<O = any> {
   pageColor: string = "white";
   /// pixel per pt
   pixelPerPt: number = 3;
+  /// number of pages around the viewport to pre-render when idle
+  prefetchPages: number = 1;
   /// customized way to retrieving dom state
   retrieveDOMState: () => ContainerDOMState;
 
@@ -96,6 +98,10 @@ export class TypstDocumentContext<O = any> {
   sampledRenderTime: number = 0;
   /// page to partial render
   partialRenderPage: number = 0;
+  /// whether the pages adjacent to the viewport are rendered
+  prefetchReady: boolean = false;
+  /// pending idle callback to render the adjacent pages
+  prefetchHandle: any = undefined;
   /// outline data
   outline: any = undefined;
   /// cursor position in form of [page, x, y]
@@ -323,6 +329,7 @@ export class TypstDocumentContext<O = any> {
         if (eventName === "new") {
           this.reset();
         }
+        this.prefetchReady = false;
         this.kModule.manipulateData({
           action: "merge",
           data: svgUpdateEvent[1] as unknown as Uint8Array,
@@ -412,6 +419,7 @@ export class TypstDocumentContext<O = any> {
     // }
 
     this.r.postRender();
+    this.schedulePrefetch();
 
     // todo: abstract this
     if (this.previewMode === PreviewMode.Slide) {
@@ -421,6 +429,29 @@ export class TypstDocumentContext<O = any> {
     }
   }
 
+  /// Renders the pages adjacent to the viewport once the browser is idle, so
+  /// that scrolling to them doesn't wait for rendering.
+  private schedulePrefetch() {
+    if (
+      this.prefetchReady ||
+      this.prefetchHandle !== undefined ||
+      !this.partialRendering ||
+      this.prefetchPages <= 0 ||
+      this.previewMode !== PreviewMode.Doc
+    ) {
+      return;
+    }
+
+    const prefetch = () => {
+      this.prefetchHandle = undefined;
+      this.prefetchReady = true;
+      this.addViewportChange();
+    };
+    this.prefetchHandle = window.requestIdleCallback
+      ? window.requestIdleCallback(prefetch, { timeout: 1000 })
+      : setTimeout(prefetch, 100);
+  }
+
   addChangement(change: [string, string]) {
     if (change[0] === "new") {
       this.patchQueue.splice(0, this.patchQueue.length);
@@ -458,6 +489,7 @@ export interface TypstDocument<T> {
   addViewportChange(): void;
   setPageColor(color: string): void;
   setPartialRendering(partialRendering: boolean): void;
+  setPrefetchPages(pages: number): void;
   setCursor(page: number, x: number, y: number): void;
   setPartialPageNumber(page: number): boolean;
   getPartialPageNumber(): number;
@@ -515,6 +547,10 @@ export function provideDoc<T extends TypstDocumentContext>(
       this.impl.partialRendering = partialRendering;
     }
 
+    setPrefetchPages(pages: number) {
+      this.impl.prefetchPages = pages;
+    }
+
     setCursor(page: number, x: number, y: number) {
       this.impl.cursorPosition = [page, x, y];
     }
@@ -8,6 +8,30 @@ import { ElementPoint, resolveSourceLeaf } from "./typst-debug-info.mjs";
 
 export interface TypstSvgDocument {
   setCursorPaths(paths: ElementPoint[][]): void;
+  setErrorPaths(paths: ElementPoint[][]): void;
+  setFindingPaths(paths: ElementPoint[][]): void;
+  setHighlightRects(rects: PageRect[]): void;
+  setComments(comments: Comment[]): void;
+  setOverlayShapes(shapes: OverlayShape[]): void;
+}
+
+/// A shape of a layout overlay computed by the server
+export interface OverlayShape extends PageRect {
+  kind: "contentBounds" | "baseline" | "boundingBox";
+}
+
+/// A review comment rendered at its position in the document
+export interface Comment {
+  id: string;
+  text: string;
+  author?: string;
+  position?: { page_no: number; x: number; y: number };
+}
+
+/// A rectangle on a 1-based page, `[x, y, width, height]` in points
+export interface PageRect {
+  page: number;
+  rect: [number, number, number, number];
 }
 
 export function provideSvgDoc<
@@ -32,6 +56,41 @@ export function provideSvgDoc<
       this.addViewportChange();
     }
 
+    /// error paths are element paths of the spans failing the last compilation
+    errorPaths?: ElementPoint[][] = undefined;
+    setErrorPaths(paths: ElementPoint[][]) {
+      this.errorPaths = paths;
+      this.addViewportChange();
+    }
+
+    /// finding paths are element paths of the spans reported by the checkers
+    findingPaths?: ElementPoint[][] = undefined;
+    setFindingPaths(paths: ElementPoint[][]) {
+      this.findingPaths = paths;
+      this.addViewportChange();
+    }
+
+    /// highlight rects are page rectangles requested by the editor
+    highlightRects?: PageRect[] = undefined;
+    setHighlightRects(rects: PageRect[]) {
+      this.highlightRects = rects;
+      this.addViewportChange();
+    }
+
+    /// review comments shared by all clients
+    comments?: Comment[] = undefined;
+    setComments(comments: Comment[]) {
+      this.comments = comments;
+      this.addViewportChange();
+    }
+
+    /// layout overlays, e.g. content bounds and bounding boxes
+    overlayShapes?: OverlayShape[] = undefined;
+    setOverlayShapes(shapes: OverlayShape[]) {
+      this.overlayShapes = shapes;
+      this.addViewportChange();
+    }
+
     postRender$svg() {
       const docRoot = this.hookedElem.firstElementChild as SVGElement;
       if (docRoot) {
@@ -137,6 +196,118 @@ export function provideSvgDoc<
         }
       }
 
+      for (const c of document.querySelectorAll('.typst-svg-error')) {
+        c.remove();
+      }
+      // Draw error boxes around the elements failing the last compilation
+      for (const p of this.errorPaths || []) {
+        const leaf = resolveSourceLeaf(this.hookedElem, p);
+        if (!leaf) {
+          console.log('svg post check errorPaths leaf not found', p);
+          continue;
+        }
+
+        const g = leaf[0] as SVGGraphicsElement;
+        const bbox = g.getBBox();
+        const t = document.createElementNS("http://www.w3.org/2000/svg", "rect");
+        t.classList.add('typst-svg-error');
+        t.setAttribute('x', `${bbox.x}`);
+        t.setAttribute('y', `${bbox.y}`);
+        t.setAttribute('width', `${bbox.width}`);
+        t.setAttribute('height', `${bbox.height}`);
+        g.appendChild(t);
+      }
+
+      for (const c of document.querySelectorAll('.typst-svg-finding')) {
+        c.remove();
+      }
+      // Underline the elements reported by the checkers
+      for (const p of this.findingPaths || []) {
+        const leaf = resolveSourceLeaf(this.hookedElem, p);
+        if (!leaf) {
+          continue;
+        }
+
+        const g = leaf[0] as SVGGraphicsElement;
+        const bbox = g.getBBox();
+        const t = document.createElementNS("http://www.w3.org/2000/svg", "line");
+        t.classList.add('typst-svg-finding');
+        t.setAttribute('x1', `${bbox.x}`);
+        t.setAttribute('y1', `${bbox.y + bbox.height}`);
+        t.setAttribute('x2', `${bbox.x + bbox.width}`);
+        t.setAttribute('y2', `${bbox.y + bbox.height}`);
+        g.appendChild(t);
+      }
+
+      for (const c of document.querySelectorAll('.typst-svg-highlight')) {
+        c.remove();
+      }
+      // Draw the highlight overlay on the pages
+      const docRoot = this.hookedElem.firstElementChild;
+      const pages = docRoot ? Array.from(docRoot.children).filter(e => e.tagName === 'g') : [];
+      for (const { page, rect } of this.highlightRects || []) {
+        const pageElem = pages[page - 1];
+        if (!pageElem) {
+          continue;
+        }
+
+        const t = document.createElementNS("http://www.w3.org/2000/svg", "rect");
+        t.classList.add('typst-svg-highlight');
+        t.setAttribute('x', `${rect[0]}`);
+        t.setAttribute('y', `${rect[1]}`);
+        t.setAttribute('width', `${rect[2]}`);
+        t.setAttribute('height', `${rect[3]}`);
+        pageElem.appendChild(t);
+      }
+
+      for (const c of document.querySelectorAll('.typst-svg-comment')) {
+        c.remove();
+      }
+      // Draw a marker for each comment, showing the comment on hover
+      for (const { id, text, author, position } of this.comments || []) {
+        const pageElem = position && pages[position.page_no - 1];
+        if (!pageElem) {
+          continue;
+        }
+
+        const t = document.createElementNS("http://www.w3.org/2000/svg", "circle");
+        t.classList.add('typst-svg-comment');
+        t.setAttribute('data-comment-id', id);
+        t.setAttribute('cx', `${position.x}`);
+        t.setAttribute('cy', `${position.y}`);
+        t.setAttribute('r', '4');
+        const title = document.createElementNS("http://www.w3.org/2000/svg", "title");
+        title.textContent = author ? `${author}: ${text}` : text;
+        t.appendChild(title);
+        pageElem.appendChild(t);
+      }
+
+      for (const c of document.querySelectorAll('.typst-svg-overlay')) {
+        c.remove();
+      }
+      // Draw the layout overlays on the pages
+      for (const { kind, page, rect } of this.overlayShapes || []) {
+        const pageElem = pages[page - 1];
+        if (!pageElem) {
+          continue;
+        }
+
+        const t = document.createElementNS("http://www.w3.org/2000/svg", kind === 'baseline' ? "line" : "rect");
+        t.classList.add('typst-svg-overlay', `typst-svg-overlay-${kind}`);
+        if (kind === 'baseline') {
+          t.setAttribute('x1', `${rect[0]}`);
+          t.setAttribute('y1', `${rect[1]}`);
+          t.setAttribute('x2', `${rect[0] + rect[2]}`);
+          t.setAttribute('y2', `${rect[1]}`);
+        } else {
+          t.setAttribute('x', `${rect[0]}`);
+          t.setAttribute('y', `${rect[1]}`);
+          t.setAttribute('width', `${rect[2]}`);
+          t.setAttribute('height', `${rect[3]}`);
+        }
+        pageElem.appendChild(t);
+      }
+
       return [t2, t3];
     }
 
@@ -195,11 +366,11 @@ export function provideSvgDoc<
           const pages = Array.from(ch).filter((x) =>
             x.classList.contains("typst-page")
           );
-          let minTop = 1e33,
-            maxBottom = -1e33,
-            accumulatedHeight = 0;
+          let firstVisible = pages.length,
+            lastVisible = -1;
+          const pageHeights: number[] = [];
           const translateRegex = /translate\(([-0-9.]+), ([-0-9.]+)\)/;
-          for (const page of pages) {
+          for (const [idx, page] of pages.entries()) {
             const pageHeight = Number.parseFloat(
               page.getAttribute("data-page-height")!
             );
@@ -207,12 +378,32 @@ export function provideSvgDoc<
             const translateMatch = translate.match(translateRegex)!;
             const translateY = Number.parseFloat(translateMatch[2]);
             if (translateY + pageHeight > topEstimate) {
-              minTop = Math.min(minTop, accumulatedHeight);
+              firstVisible = Math.min(firstVisible, idx);
             }
             if (translateY < bottomEstimate) {
-              maxBottom = Math.max(maxBottom, accumulatedHeight + pageHeight);
+              lastVisible = Math.max(lastVisible, idx);
+            }
+            pageHeights.push(pageHeight);
+          }
+
+          // pages adjacent to the viewport are also rendered once the browser
+          // has been idle after the latest update
+          if (this.prefetchReady) {
+            firstVisible = Math.max(firstVisible - this.prefetchPages, 0);
+            lastVisible = Math.min(lastVisible + this.prefetchPages, pages.length - 1);
+          }
+
+          let minTop = 1e33,
+            maxBottom = -1e33,
+            accumulatedHeight = 0;
+          for (const [idx, pageHeight] of pageHeights.entries()) {
+            if (idx === firstVisible) {
+              minTop = accumulatedHeight;
             }
             accumulatedHeight += pageHeight;
+            if (idx === lastVisible) {
+              maxBottom = accumulatedHeight;
+            }
           }
 
           if (pages.length != 0) {
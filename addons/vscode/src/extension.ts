@@ -33,14 +33,14 @@ async function loadHTMLFile(context: vscode.ExtensionContext, relativePath: stri
 	return fileContents;
 }
 
-function statusBarItemProcess(event: "Compiling" | "CompileSuccess" | "CompileError") {
+function statusBarItemProcess(event: "Compiling" | "CompileSuccess" | "CompileError" | "Rendering" | "Uploaded", text?: string) {
 	const style = vscode.workspace.getConfiguration().get<string>('typst-preview.statusBarIndicator') || "compact";
 	if (statusBarItem) {
 		if (event === "Compiling") {
 			if (style === "compact") {
 				statusBarItem.text = "$(sync~spin)";
 			} else if (style === "full") {
-				statusBarItem.text = "$(sync~spin) Compiling";
+				statusBarItem.text = `$(sync~spin) ${text ?? "Compiling"}`;
 			}
 			statusBarItem.backgroundColor = new vscode.ThemeColor("statusBarItem.prominentBackground");
 			statusBarItem.show();
@@ -48,7 +48,7 @@ function statusBarItemProcess(event: "Compiling" | "CompileSuccess" | "CompileEr
 			if (style === "compact") {
 				statusBarItem.text = "$(typst-guy)";
 			} else if (style === "full") {
-				statusBarItem.text = "$(typst-guy) Compile Success";
+				statusBarItem.text = `$(typst-guy) ${text ?? "Compile Success"}`;
 			}
 			statusBarItem.backgroundColor = new vscode.ThemeColor("statusBarItem.prominentBackground");
 			statusBarItem.show();
@@ -56,7 +56,7 @@ function statusBarItemProcess(event: "Compiling" | "CompileSuccess" | "CompileEr
 			if (style === "compact") {
 				statusBarItem.text = "$(typst-guy)";
 			} else if (style === "full") {
-				statusBarItem.text = "$(typst-guy) Compile Error";
+				statusBarItem.text = `$(typst-guy) ${text ?? "Compile Error"}`;
 			}
 			statusBarItem.backgroundColor = new vscode.ThemeColor("statusBarItem.errorBackground");
 			statusBarItem.show();
@@ -345,14 +345,14 @@ const launchPreview = async (task: LaunchInBrowserTask | LaunchInWebViewTask) =>
 	await watchEditorFiles();
 	const { serverProcess, controlPlanePort, dataPlanePort } = await launchCli(task.kind === 'browser');
 
-	const addonΠserver = new WebSocket(`ws://127.0.0.1:${controlPlanePort}`);
+	const addonΠserver = new WebSocket(`ws://127.0.0.1:${controlPlanePort}`, "typst-preview.editor.v1");
 	addonΠserver.addEventListener("message", async (message) => {
 		const data = JSON.parse(message.data as string);
 		switch (data.event) {
 			case "editorScrollTo": return await editorScrollTo(activeEditor, data /* JumpInfo */);
 			case "syncEditorChanges": return syncEditorChanges(addonΠserver);
 			case "compileStatus": {
-				statusBarItemProcess(data.kind);
+				statusBarItemProcess(data.kind, data.message?.text);
 				break;
 			}
 			case "outline": {
@@ -360,6 +360,11 @@ const launchPreview = async (task: LaunchInBrowserTask | LaunchInWebViewTask) =>
 				outlineProvider.then((p) => p.postOutlineItem(data /* Outline */));
 				break;
 			}
+			case "frontendVersionMismatch": {
+				vscode.window.showWarningMessage(data.message?.text ??
+					`The preview frontend (${data.reported}) doesn't match the typst-preview server (${data.embedded}), please reinstall the extension or the server.`);
+				break;
+			}
 			default: {
 				console.warn("unknown message", data);
 				break;
@@ -73,6 +73,30 @@ Example:
 }
 ```
 
+When the preview seems to be out of sync with the editor, the editor can send a `resync` message, e.g. from a "fix the preview" command. The preview server drops all of the memory files and the pending edits, reads the files from disk again and recompiles the document. The editor then receives the compile status, the diagnostics and the full outline, even in delta mode, and the webviews receive the whole document. The editor should send a `syncMemoryFiles` message afterwards if it has dirty files.
+
+```json
+{
+  "event": "resync"
+}
+```
+
+== Document inputs
+
+The values of `sys.inputs` are given at startup with `--input key=value`, which can be repeated. The editor can replace them at runtime with a `setInputs` message, where the `inputs` field is a map from key to string value. The document is compiled again with the new inputs, and the ones not in the map are removed.
+
+Example:
+
+```json
+{
+  "event": "setInputs",
+  "inputs": {
+    "customer": "acme",
+    "month": "2024-01"
+  }
+}
+```
+
 == Source to preview jumping
 
 To implement source to preview jumping, the editor extension should send the `SrcToDocJump` message to the preview server. The `event` field should be set to `panelScrollTo`. The `filepath` field is the absolute path of the file. The `line` field is the line number of the file. The `character` field is the character number of the file. The line number and the character number are 0-based.
@@ -118,16 +142,79 @@ To implement compile status reporting, the editor extension act on `compileStatu
 - `Compiling`
 - `CompileSuccess`
 - `CompileError`
+- `Rendering`: the compiled document is being encoded for a webview.
+- `Uploaded`: the encoded document has been sent to a webview.
+
+The `timestamp` field is the time the status was reached, in milliseconds since the unix epoch, so that the latency of each phase can be measured.
+
+The `message` field is the status to show to the user. Its `text` is in the language given by the `--locale` option of the server (`en`, `de`, `es`, `fr` or `zh`, English by default), and its `code` is one of `compile.compiling`, `compile.success`, `compile.error`, `render.rendering` and `render.uploaded`, which doesn't depend on the language. Editors with their own translations should match the code rather than the text. The `frontendVersionMismatch` event has a `message` too, whose code is `frontend.versionMismatch`.
 
 Example:
 
 ```json
 {
   "event": "compileStatus",
-  "kind": "Compiling"
+  "kind": "Compiling",
+  "timestamp": 1700000000000,
+  "message": {
+    "code": "compile.compiling",
+    "text": "Compiling"
+  }
+}
+```
+
+== Diagnostics
+
+After each compilation, the preview server sends the `diagnostics` event with the diagnostics of the compilation grouped by file. The list replaces the previous one, and files which are not listed have no diagnostics. Each file carries a stable `id` (its path relative to the root, prefixed by the package for package files), its `relative_path`, and its absolute `path`. Memory-only files that don't exist on disk are identified in the same way, so editors managing several projects can route the diagnostics to the right buffers.
+
+```json
+{
+  "event": "diagnostics",
+  "files": [
+    {
+      "id": "chapters/intro.typ",
+      "relative_path": "chapters/intro.typ",
+      "path": "/home/user/thesis/chapters/intro.typ",
+      "readonly": false,
+      "diagnostics": [
+        {
+          "severity": "error",
+          "message": "unknown variable: foo",
+          "hints": [],
+          "start": [3, 2],
+          "end": [3, 5],
+          "fixes": []
+        }
+      ]
+    }
+  ]
+}
+```
+
+Hints which end with the code to write, such as "did you mean \`\#emph\`?" or "try adding spaces around the minus sign: \`a - b\`", are also sent as quick fixes in `fixes` when the code replaces the diagnosed node. A fix replaces the range of its diagnostic from `start` to `end` by `replacement`, and its `title` is the hint, so editors can offer it as a one-click code action.
+
+```json
+{
+  "severity": "error",
+  "message": "unknown variable: a-b",
+  "hints": [
+    "if you meant to use subtraction, try adding spaces around the minus sign: `a - b`"
+  ],
+  "start": [7, 4],
+  "end": [7, 7],
+  "fixes": [
+    {
+      "title": "if you meant to use subtraction, try adding spaces around the minus sign: `a - b`",
+      "start": [7, 4],
+      "end": [7, 7],
+      "replacement": "a - b"
+    }
+  ]
 }
 ```
 
+Embedders can register checkers, such as a spell checker, with `register_checker`. Their findings are reported after the diagnostics of the compiler as warnings, with the name of the checker in `source` and the precise range of the finding, and they are underlined in the webview where the syntax node at the start of the finding is rendered.
+
 == Outline
 
 To implement outline reporting, the editor extension should listen to the `outline` event from the preview server. The `event` field should be `outline`. A typical outline looks like this. Note that `page_no` is 1-based.
@@ -172,6 +259,129 @@ To implement outline reporting, the editor extension should listen to the `outli
 ```
 
 
+To render a breadcrumb bar following the cursor, the editor extension can send a `queryBreadcrumbs` message with a 0-based source position, like `changeCursorPosition`. The preview server resolves the position in the latest document and replies with the `breadcrumbs` event, listing the outline items enclosing it from the top level down. An item encloses everything from its heading up to the next heading of the same or a higher level. The `id` and `span` fields are the ones of the outline of the given `revision`, so an editor in delta mode can look the items up in its own outline, and the spans can be resolved with `resolveSpans`. The `items` are empty if the position can't be resolved, e.g. in a file which is not part of the document.
+
+```json
+{
+  "event": "breadcrumbs",
+  "filepath": "/home/mgt/proj/typst-preview/docs/dev.typ",
+  "line": 42,
+  "character": 3,
+  "revision": 7,
+  "items": [
+    {
+      "id": "8c0d4e2f6a1b9d37",
+      "title": "My fabulous talk",
+      "span": "79e192e29ce5",
+      "position": { "page_no": 9, "x": 252.39397, "y": 283.81598 }
+    },
+    {
+      "id": "3f71a0c5d2e84b96",
+      "title": "A quiz",
+      "span": "7d0905e0183a",
+      "position": { "page_no": 10, "x": 70.86625, "y": 70.86625 }
+    }
+  ]
+}
+```
+
+== Session Summary
+
+On exit, the preview server sends a `sessionSummary` message, which is a `session` event, and writes the same summary as JSON to the file given by `--session-summary`. The editor can also ask for the summary so far with a `querySessionSummary` message.
+
+```json
+{
+  "event": "sessionSummary",
+  "startedAt": 1700000000000,
+  "previewSecs": 3600.5,
+  "compiles": 412,
+  "errors": 37,
+  "averageCompileMs": 48.2,
+  "mostEditedFiles": [
+    { "path": "/home/mgt/proj/thesis/chapter2.typ", "edits": 1250 }
+  ]
+}
+```
+
+== Safe Mode
+
+The preview server keeps track of the previews of each document which crashed, or in which a panic escaped the actor that raised it. The panics an actor recovers from don't count. After three of them in a row, or with the `--safe-mode` option, it starts in safe mode: it discards the span cache and ignores partial rendering, inverted colors, error placeholders, the renderer backend and the mailbox settings. A preview exiting normally without a panic resets the count, so the next one starts normally again.
+
+In safe mode, the server sends a `safeMode` message once the editor connects. `crashes` is the number of crashes in a row, `forced` tells whether safe mode was asked for, and `disabled` lists what safe mode disables. The message code is `preview.safeMode`.
+
+```json
+{
+  "event": "safeMode",
+  "crashes": 3,
+  "forced": false,
+  "disabled": ["spanCache", "partialRendering", "customConfig"],
+  "message": {
+    "code": "preview.safeMode",
+    "text": "The preview runs in safe mode after repeated crashes, without caches, partial rendering and custom settings."
+  }
+}
+```
+
+== Profiles
+
+The profiles of the configuration file of the project set the compile delay (`maxCompileDelay`), the error placeholders (`errorPlaceholders`) and the layout overlays (`overlays`). The editor can switch profiles at runtime with a `setProfile` message, whose `name` is the name of a profile, or `null` to go back to the settings of the command line. The compiler and the running webviews are reconfigured without a restart.
+
+```json
+{
+  "event": "setProfile",
+  "name": "review"
+}
+```
+
+The server replies with a `profiles` message listing the profiles in `items` and the `active` one, which the editor can also ask for with a `listProfiles` message. An unknown profile leaves the active one unchanged.
+
+```json
+{
+  "event": "profiles",
+  "items": ["draft", "review"],
+  "active": "review"
+}
+```
+
+== Screenshots
+
+The editor can rasterize a page of the document as PNG with a `screenshot` message, e.g. to paste an excerpt into a chat. `page` is the 1-based page number, which defaults to the first page visible in the webview. `rect` is the `[x, y, width, height]` in points of the part of the page to take, which defaults to the whole page. `ppi` is the resolution, 144 by default. The PNG is written to `path` if given, and otherwise returned in base64 for the editor to put on the clipboard.
+
+```json
+{
+  "event": "screenshot",
+  "rect": [72, 100, 300, 200],
+  "ppi": 192
+}
+```
+
+The server replies with a `screenshot` message with the `page`, the `width` and `height` in pixels, and either the `path` or the `data` of the PNG. If the page couldn't be rasterized, e.g. because it would be larger than 40 million pixels at the requested resolution, it has an `error` instead.
+
+```json
+{
+  "event": "screenshot",
+  "page": 3,
+  "width": 800,
+  "height": 533,
+  "data": "iVBORw0KGgoAAAANSUhEUgAA..."
+}
+```
+
+== Mailboxes
+
+The actors of the preview server exchange messages through bounded mailboxes, configured with `--mailbox NAME=CAPACITY[:POLICY]`. When a mailbox is full, `coalesce` merges the message into the newest queued message of the same kind, `drop-oldest` drops the oldest queued message, and `block` holds the sender up until the receiver catches up. The `webview` mailbox carries deltas of the document, so it can't use `drop-oldest`. The compiler never waits for the `editor` mailbox: its events are dropped when the mailbox is full, and while no editor is connected.
+
+The editor can query the mailboxes with a `queryMailboxes` message. The server replies with a `mailboxes` message, whose `items` field lists each mailbox with its `name`, `capacity`, `policy`, current `depth`, `maxDepth`, and the number of messages `sent`, `coalesced` and `dropped`, and how many times a sender was `blocked`.
+
+```json
+{
+  "event": "mailboxes",
+  "items": [
+    { "name": "typst", "capacity": 1024, "policy": "coalesce", "depth": 0, "maxDepth": 3, "sent": 120, "coalesced": 0, "dropped": 0, "blocked": 0 }
+  ]
+}
+```
+
 == References
 
 Messages sent from the editor extension to the preview server, defined in `src/actor/editor.rs`.
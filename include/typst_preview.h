@@ -0,0 +1,37 @@
+/* The C ABI of typst-preview, built with the `ffi` feature:
+ *
+ *   cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * Messages and events are the JSON of the control plane, see
+ * docs/editor.typ. */
+
+#ifndef TYPST_PREVIEW_H
+#define TYPST_PREVIEW_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct TypstPreview TypstPreview;
+
+/* Starts a preview with the arguments of the command line, without the
+ * program name. Returns NULL on failure. */
+TypstPreview *typst_preview_start(int argc, const char *const *argv);
+
+/* Sends a JSON message to the preview. Returns 0 on success, -1 on failure. */
+int typst_preview_send_message(TypstPreview *preview, const char *message);
+
+/* Takes the next JSON event without waiting, or returns NULL if there is
+ * none. The event is freed with typst_preview_free_string. */
+char *typst_preview_poll_event(TypstPreview *preview);
+
+void typst_preview_free_string(char *event);
+
+/* Stops the preview and frees it. */
+void typst_preview_stop(TypstPreview *preview);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TYPST_PREVIEW_H */
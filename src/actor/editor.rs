@@ -1,54 +1,317 @@
-use futures::{SinkExt, StreamExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::{Sink, SinkExt, Stream, StreamExt};
 use log::{debug, info, trace, warn};
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
-use tokio::{net::TcpStream, sync::broadcast};
-use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
-use typst_ts_core::debug_loc::DocumentPosition;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_tungstenite::tungstenite::{self, Message};
+use typst_ts_core::debug_loc::{DocumentPosition, SourceSpanOffset};
 
+use crate::bookmark::Bookmark;
+use crate::comment::{Comment, NewComment};
+use crate::config::ProfileList;
 use crate::debug_loc::{InternQuery, SpanInterner};
-use crate::outline::Outline;
+use crate::dependencies::Dependencies;
+use crate::diagnostics::FileDiagnostics;
+use crate::mailbox::{mailbox_stats, Coalesce, MailboxReceiver, MailboxSender, MailboxStats};
+use crate::messages::{Locale, Localized, MessageCode, StatusMessage};
+use crate::outline::{outline_delta, Breadcrumb, Outline, OutlineChildren, OutlineDelta};
+use crate::overlay::Overlays;
+use crate::protocol::{EventKind, Subscriptions};
+use crate::safe_mode::SafeModeReport;
+use crate::screenshot::{Screenshot, ScreenshotRequest};
+use crate::session::{Session, SessionSummary};
+use crate::structure::DocumentStructure;
 use crate::{
-    actor::typst::TypstActorRequest, ChangeCursorPositionRequest, DocToSrcJumpInfo, MemoryFiles,
-    MemoryFilesShort, SrcToDocJumpRequest,
+    actor::typst::TypstActorRequest, ChangeCursorPositionRequest, DocToSrcJumpInfo, HighlightRange,
+    Inputs, MemoryFiles, MemoryFilesShort, SrcToDocJumpRequest,
 };
 
-use super::webview::WebviewActorRequest;
+use super::webview::{FrontendVersionMismatch, UpdateStats, ViewportInfo, WebviewActorRequest};
 #[derive(Debug, Deserialize)]
 pub struct DocToSrcJumpResolveRequest {
     /// Span id in hex-format.
     pub span: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct DocToSrcJumpBatchResolveRequest {
+    /// Span ids in hex-format.
+    pub spans: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolvedSpan {
+    /// Span id in hex-format.
+    pub span: String,
+    /// The resolved source range, or `None` if the span id is out of date or
+    /// can't be resolved.
+    pub info: Option<DocToSrcJumpInfo>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct PanelScrollByPositionRequest {
     position: DocumentPosition,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Deserialize)]
+pub struct OutlineDeltaModeRequest {
+    /// Whether to send outline deltas instead of full outlines.
+    enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OutlineChildrenRequest {
+    /// The id of the outline item, or `None` for the top-level items.
+    id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HighlightRequest {
+    /// Source ranges to highlight.
+    #[serde(default)]
+    ranges: Vec<HighlightRange>,
+    /// Span ids in hex-format to highlight.
+    #[serde(default)]
+    spans: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JumpToBookmarkRequest {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteBookmarkRequest {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetProfileRequest {
+    /// The name of the profile, or `None` for the settings of the command
+    /// line.
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteCommentRequest {
+    id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "kind", content = "data")]
 pub enum CompileStatus {
     Compiling,
     CompileSuccess,
     CompileError,
+    /// The compiled document is being encoded for a webview.
+    Rendering,
+    /// The encoded document has been sent to a webview.
+    Uploaded,
+}
+
+/// A compile status with the time it was reached, so that editors can tell
+/// how long each phase took.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompileStatusEvent {
+    #[serde(flatten)]
+    pub status: CompileStatus,
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u64,
+}
+
+impl CompileStatusEvent {
+    pub fn now(status: CompileStatus) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        Self { status, timestamp }
+    }
+
+    fn message_code(&self) -> MessageCode {
+        match self.status {
+            CompileStatus::Compiling => MessageCode::Compiling,
+            CompileStatus::CompileSuccess => MessageCode::CompileSuccess,
+            CompileStatus::CompileError => MessageCode::CompileError,
+            CompileStatus::Rendering => MessageCode::Rendering,
+            CompileStatus::Uploaded => MessageCode::Uploaded,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum EditorActorRequest {
     DocToSrcJumpResolve(DocToSrcJumpResolveRequest),
     DocToSrcJump(DocToSrcJumpInfo),
+    ResolvedSpans(Vec<ResolvedSpan>),
     Outline(Outline),
-    CompileStatus(CompileStatus),
+    CompileStatus(CompileStatusEvent),
+    /// The diagnostics of the latest compilation, replacing the previous ones.
+    Diagnostics(Vec<FileDiagnostics>),
+    FrontendVersionMismatch(FrontendVersionMismatch),
+    Viewport(ViewportInfo),
+    VisiblePages(Vec<usize>),
+    Bookmarks(Vec<Bookmark>),
+    SetBookmark(Bookmark),
+    JumpToBookmark(String),
+    DeleteBookmark(String),
+    Comments(Vec<Comment>),
+    AddComment(NewComment),
+    DeleteComment(String),
+    ListComments,
+    Structure(DocumentStructure),
+    /// A cursor position of the editor and where it is in the latest
+    /// document, if it could be resolved.
+    Breadcrumbs(ChangeCursorPositionRequest, Option<DocumentPosition>),
+    Dependencies(Dependencies),
+    UpdateStats(UpdateStats),
+    /// The summary of the session on exit, acknowledged once it is sent.
+    SessionSummary(SessionSummary, Option<oneshot::Sender<()>>),
+    /// Sent once the editor connects if the preview runs in safe mode.
+    SafeMode(SafeModeReport),
+    Screenshot(Screenshot),
+    Profiles(ProfileList),
+    Thumbnails(Vec<Screenshot>),
+}
+
+/// Events carrying the latest state of something replace the queued event
+/// of the same kind, since the editor only needs the latest one.
+impl Coalesce for EditorActorRequest {
+    fn coalesce(&mut self, newer: Self) -> Option<Self> {
+        match (&*self, &newer) {
+            (Self::Outline(_), Self::Outline(_))
+            | (Self::CompileStatus(_), Self::CompileStatus(_))
+            | (Self::Diagnostics(_), Self::Diagnostics(_))
+            | (Self::Viewport(_), Self::Viewport(_))
+            | (Self::VisiblePages(_), Self::VisiblePages(_))
+            | (Self::Bookmarks(_), Self::Bookmarks(_))
+            | (Self::Comments(_), Self::Comments(_))
+            | (Self::Structure(_), Self::Structure(_))
+            | (Self::Breadcrumbs(..), Self::Breadcrumbs(..))
+            | (Self::Dependencies(_), Self::Dependencies(_))
+            | (Self::Profiles(_), Self::Profiles(_))
+            | (Self::Thumbnails(_), Self::Thumbnails(_)) => {
+                *self = newer;
+                None
+            }
+            _ => Some(newer),
+        }
+    }
+}
+
+impl EditorActorRequest {
+    /// The kind of the event sent to the editor without being asked for, if
+    /// any, which is only sent if the editor subscribed to it.
+    fn event_kind(&self) -> Option<EventKind> {
+        match self {
+            Self::DocToSrcJump(_) => Some(EventKind::Jump),
+            Self::Outline(_) => Some(EventKind::Outline),
+            Self::CompileStatus(_) => Some(EventKind::Diagnostics),
+            Self::Diagnostics(_) => Some(EventKind::Diagnostics),
+            Self::VisiblePages(_) => Some(EventKind::VisiblePages),
+            Self::Comments(_) => Some(EventKind::Comments),
+            Self::UpdateStats(_) => Some(EventKind::Stats),
+            Self::SessionSummary(..) => Some(EventKind::Session),
+            Self::Thumbnails(_) => Some(EventKind::Thumbnails),
+            _ => None,
+        }
+    }
+}
+
+/// The connection to an editor, which is a websocket of the control plane
+/// or an [`EmbeddedConn`].
+pub trait EditorConn:
+    Stream<Item = Result<Message, tungstenite::Error>>
+    + Sink<Message, Error = tungstenite::Error>
+    + Unpin
+    + Send
+{
+}
+
+impl<T> EditorConn for T where
+    T: Stream<Item = Result<Message, tungstenite::Error>>
+        + Sink<Message, Error = tungstenite::Error>
+        + Unpin
+        + Send
+{
+}
+
+/// The connection to an editor embedded in the process, which exchanges the
+/// JSON messages of the control plane over channels.
+pub struct EmbeddedConn {
+    messages: mpsc::UnboundedReceiver<String>,
+    events: mpsc::UnboundedSender<String>,
+}
+
+impl EmbeddedConn {
+    pub fn new(
+        messages: mpsc::UnboundedReceiver<String>,
+        events: mpsc::UnboundedSender<String>,
+    ) -> Self {
+        Self { messages, events }
+    }
+}
+
+impl Stream for EmbeddedConn {
+    type Item = Result<Message, tungstenite::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.messages
+            .poll_recv(cx)
+            .map(|msg| msg.map(|msg| Ok(Message::Text(msg))))
+    }
+}
+
+impl Sink<Message> for EmbeddedConn {
+    type Error = tungstenite::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, msg: Message) -> Result<(), Self::Error> {
+        let Message::Text(msg) = msg else {
+            return Ok(());
+        };
+        self.events
+            .send(msg)
+            .map_err(|_| tungstenite::Error::ConnectionClosed)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
 }
 
 pub struct EditorActor {
-    mailbox: mpsc::UnboundedReceiver<EditorActorRequest>,
-    editor_websocket_conn: WebSocketStream<TcpStream>,
+    mailbox: MailboxReceiver<EditorActorRequest>,
+    editor_conn: Box<dyn EditorConn>,
 
-    world_sender: mpsc::UnboundedSender<TypstActorRequest>,
+    world_sender: MailboxSender<TypstActorRequest>,
     webview_sender: broadcast::Sender<WebviewActorRequest>,
 
     span_interner: SpanInterner,
+
+    /// Whether the editor asked for outline deltas.
+    outline_delta_mode: bool,
+    /// The latest outline sent to the editor.
+    outline: Option<Outline>,
+    outline_revision: u64,
+    /// The maximum number of outline items sent at once.
+    outline_limit: Option<usize>,
+    subscriptions: Subscriptions,
+    session: Session,
+    /// The language of the status messages.
+    locale: Locale,
+    /// The pages visible in the webview, whether or not the editor
+    /// subscribed to them.
+    visible_pages: Vec<usize>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -62,12 +325,64 @@ enum ControlPlaneMessage {
     PanelScrollByPosition(PanelScrollByPositionRequest),
     #[serde(rename = "sourceScrollBySpan")]
     DocToSrcJumpResolve(DocToSrcJumpResolveRequest),
+    #[serde(rename = "resolveSpans")]
+    DocToSrcJumpBatchResolve(DocToSrcJumpBatchResolveRequest),
     #[serde(rename = "syncMemoryFiles")]
     SyncMemoryFiles(MemoryFiles),
     #[serde(rename = "updateMemoryFiles")]
     UpdateMemoryFiles(MemoryFiles),
     #[serde(rename = "removeMemoryFiles")]
     RemoveMemoryFiles(MemoryFilesShort),
+    #[serde(rename = "setInputs")]
+    SetInputs(Inputs),
+    #[serde(rename = "queryViewport")]
+    QueryViewport,
+    #[serde(rename = "highlight")]
+    Highlight(HighlightRequest),
+    #[serde(rename = "setOverlays")]
+    SetOverlays(Overlays),
+    #[serde(rename = "queryStructure")]
+    QueryStructure,
+    #[serde(rename = "queryBreadcrumbs")]
+    QueryBreadcrumbs(ChangeCursorPositionRequest),
+    #[serde(rename = "queryDependencies")]
+    QueryDependencies,
+    #[serde(rename = "queryMailboxes")]
+    QueryMailboxes,
+    #[serde(rename = "querySessionSummary")]
+    QuerySessionSummary,
+    #[serde(rename = "setProfile")]
+    SetProfile(SetProfileRequest),
+    #[serde(rename = "screenshot")]
+    Screenshot(ScreenshotRequest),
+    #[serde(rename = "listProfiles")]
+    ListProfiles,
+    #[serde(rename = "setBookmark")]
+    SetBookmark(Bookmark),
+    #[serde(rename = "listBookmarks")]
+    ListBookmarks,
+    #[serde(rename = "jumpToBookmark")]
+    JumpToBookmark(JumpToBookmarkRequest),
+    #[serde(rename = "deleteBookmark")]
+    DeleteBookmark(DeleteBookmarkRequest),
+    #[serde(rename = "addComment")]
+    AddComment(NewComment),
+    #[serde(rename = "deleteComment")]
+    DeleteComment(DeleteCommentRequest),
+    #[serde(rename = "listComments")]
+    ListComments,
+    #[serde(rename = "navigateBack")]
+    NavigateBack,
+    #[serde(rename = "navigateForward")]
+    NavigateForward,
+    #[serde(rename = "outlineDeltaMode")]
+    OutlineDeltaMode(OutlineDeltaModeRequest),
+    #[serde(rename = "outlineResync")]
+    OutlineResync,
+    #[serde(rename = "outlineChildren")]
+    OutlineChildren(OutlineChildrenRequest),
+    #[serde(rename = "resync")]
+    Resync,
 }
 
 #[derive(Debug, Serialize)]
@@ -75,34 +390,128 @@ enum ControlPlaneMessage {
 enum ControlPlaneResponse {
     #[serde(rename = "editorScrollTo")]
     EditorScrollTo(DocToSrcJumpInfo),
+    #[serde(rename = "resolvedSpans")]
+    ResolvedSpans { items: Vec<ResolvedSpan> },
     #[serde(rename = "syncEditorChanges")]
     SyncEditorChanges(()),
     #[serde(rename = "compileStatus")]
-    CompileStatus(CompileStatus),
+    CompileStatus(Localized<CompileStatusEvent>),
+    #[serde(rename = "diagnostics")]
+    Diagnostics(DiagnosticList),
     #[serde(rename = "outline")]
     Outline(Outline),
+    #[serde(rename = "outlineDelta")]
+    OutlineDelta(OutlineDelta),
+    #[serde(rename = "outlineChildren")]
+    OutlineChildren(OutlineChildren),
+    #[serde(rename = "frontendVersionMismatch")]
+    FrontendVersionMismatch(Localized<FrontendVersionMismatch>),
+    #[serde(rename = "viewport")]
+    Viewport(ViewportInfo),
+    #[serde(rename = "visiblePages")]
+    VisiblePages(VisiblePages),
+    #[serde(rename = "bookmarks")]
+    Bookmarks(BookmarkList),
+    #[serde(rename = "comments")]
+    Comments(CommentList),
+    #[serde(rename = "structure")]
+    Structure(DocumentStructure),
+    #[serde(rename = "breadcrumbs")]
+    Breadcrumbs(BreadcrumbList),
+    #[serde(rename = "dependencies")]
+    Dependencies(Dependencies),
+    #[serde(rename = "updateStats")]
+    UpdateStats(UpdateStats),
+    #[serde(rename = "mailboxes")]
+    Mailboxes(MailboxList),
+    #[serde(rename = "sessionSummary")]
+    SessionSummary(SessionSummary),
+    #[serde(rename = "safeMode")]
+    SafeMode(Localized<SafeModeReport>),
+    #[serde(rename = "profiles")]
+    Profiles(ProfileList),
+    #[serde(rename = "screenshot")]
+    Screenshot(Screenshot),
+    #[serde(rename = "thumbnails")]
+    Thumbnails(ThumbnailList),
+}
+
+/// The diagnostics of the latest compilation by file. Files which are not
+/// listed have no diagnostics.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct DiagnosticList {
+    pub files: Vec<FileDiagnostics>,
+}
+
+/// The outline items enclosing a cursor position, from the top level down.
+/// The ids are the ones of the outline of the given revision.
+#[derive(Debug, Serialize)]
+struct BreadcrumbList {
+    #[serde(flatten)]
+    cursor: ChangeCursorPositionRequest,
+    revision: u64,
+    items: Vec<Breadcrumb>,
+}
+
+#[derive(Debug, Serialize)]
+struct CommentList {
+    items: Vec<Comment>,
+}
+
+#[derive(Debug, Serialize)]
+struct MailboxList {
+    items: Vec<MailboxStats>,
+}
+
+#[derive(Debug, Serialize)]
+struct BookmarkList {
+    items: Vec<Bookmark>,
+}
+
+#[derive(Debug, Serialize)]
+struct ThumbnailList {
+    items: Vec<Screenshot>,
+}
+
+/// The 1-based numbers of the pages visible in the webview.
+#[derive(Debug, Serialize)]
+struct VisiblePages {
+    pages: Vec<usize>,
 }
 
 impl EditorActor {
     pub fn new(
-        mailbox: mpsc::UnboundedReceiver<EditorActorRequest>,
-        editor_websocket_conn: WebSocketStream<TcpStream>,
-        world_sender: mpsc::UnboundedSender<TypstActorRequest>,
+        mailbox: MailboxReceiver<EditorActorRequest>,
+        editor_conn: Box<dyn EditorConn>,
+        world_sender: MailboxSender<TypstActorRequest>,
         webview_sender: broadcast::Sender<WebviewActorRequest>,
         span_interner: SpanInterner,
+        outline_limit: Option<usize>,
+        subscriptions: Subscriptions,
+        session: Session,
+        locale: Locale,
     ) -> Self {
         Self {
             mailbox,
-            editor_websocket_conn,
+            editor_conn,
             world_sender,
             webview_sender,
 
             span_interner,
+
+            outline_delta_mode: false,
+            outline: None,
+            outline_revision: 0,
+            outline_limit,
+            subscriptions,
+            session,
+            locale,
+            visible_pages: vec![],
         }
     }
 
-    pub async fn run(mut self) {
-        self.editor_websocket_conn
+    pub async fn run(&mut self) {
+        self.editor_conn
             .send(Message::Text(
                 serde_json::to_string(&ControlPlaneResponse::SyncEditorChanges(())).unwrap(),
             ))
@@ -112,9 +521,16 @@ impl EditorActor {
             tokio::select! {
                 Some(msg) = self.mailbox.recv() => {
                     trace!("EditorActor: received message from mailbox: {:?}", msg);
+                    if let EditorActorRequest::VisiblePages(pages) = &msg {
+                        self.visible_pages = pages.clone();
+                    }
+                    if msg.event_kind().is_some_and(|kind| !self.subscriptions.contains(kind)) {
+                        trace!("EditorActor: skipping unsubscribed event");
+                        continue;
+                    }
                     match msg {
                         EditorActorRequest::DocToSrcJump(jump_info) => {
-                            let Ok(_) = self.editor_websocket_conn.send(Message::Text(
+                            let Ok(_) = self.editor_conn.send(Message::Text(
                                 serde_json::to_string(&ControlPlaneResponse::EditorScrollTo(jump_info)).unwrap(),
                             )).await else {
                                 warn!("EditorActor: failed to send DocToSrcJump message to editor");
@@ -124,25 +540,194 @@ impl EditorActor {
                         EditorActorRequest::DocToSrcJumpResolve(req) => {
                             self.source_scroll_by_span(req.span).await;
                         },
+                        EditorActorRequest::ResolvedSpans(items) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::ResolvedSpans { items }).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send ResolvedSpans message to editor");
+                                break;
+                            };
+                        },
                         EditorActorRequest::CompileStatus(status) => {
-                            let Ok(_) = self.editor_websocket_conn.send(Message::Text(
-                                serde_json::to_string(&ControlPlaneResponse::CompileStatus(status)).unwrap(),
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::CompileStatus(Localized {
+                                    message: StatusMessage::new(status.message_code(), self.locale),
+                                    event: status,
+                                })).unwrap(),
                             )).await else {
                                 warn!("EditorActor: failed to send CompileStatus message to editor");
                                 break;
                             };
                         },
-                        EditorActorRequest::Outline(outline) => {
-                            let Ok(_) = self.editor_websocket_conn.send(Message::Text(
-                                serde_json::to_string(&ControlPlaneResponse::Outline(outline)).unwrap(),
+                        EditorActorRequest::Diagnostics(files) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::Diagnostics(DiagnosticList { files })).unwrap(),
                             )).await else {
+                                warn!("EditorActor: failed to send Diagnostics message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::Outline(outline) => {
+                            let Ok(_) = self.update_outline(outline).await else {
                                 warn!("EditorActor: failed to send Outline message to editor");
                                 break;
                             };
                         }
+                        EditorActorRequest::FrontendVersionMismatch(mismatch) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::FrontendVersionMismatch(Localized {
+                                    event: mismatch,
+                                    message: StatusMessage::new(MessageCode::FrontendVersionMismatch, self.locale),
+                                })).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send FrontendVersionMismatch message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::Viewport(info) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::Viewport(info)).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send Viewport message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::VisiblePages(pages) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::VisiblePages(VisiblePages { pages })).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send VisiblePages message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::SetBookmark(bookmark) => {
+                            self.world_sender.send(TypstActorRequest::SetBookmark(bookmark)).unwrap();
+                        }
+                        EditorActorRequest::JumpToBookmark(name) => {
+                            self.world_sender.send(TypstActorRequest::JumpToBookmark(name)).unwrap();
+                        }
+                        EditorActorRequest::DeleteBookmark(name) => {
+                            self.world_sender.send(TypstActorRequest::DeleteBookmark(name)).unwrap();
+                        }
+                        EditorActorRequest::Bookmarks(items) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::Bookmarks(BookmarkList { items })).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send Bookmarks message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::AddComment(comment) => {
+                            self.add_comment(comment).await;
+                        }
+                        EditorActorRequest::DeleteComment(id) => {
+                            self.world_sender.send(TypstActorRequest::DeleteComment(id)).unwrap();
+                        }
+                        EditorActorRequest::ListComments => {
+                            self.world_sender.send(TypstActorRequest::ListComments).unwrap();
+                        }
+                        EditorActorRequest::Comments(items) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::Comments(CommentList { items })).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send Comments message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::Structure(structure) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::Structure(structure)).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send Structure message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::Breadcrumbs(cursor, position) => {
+                            let items = match (&self.outline, position) {
+                                (Some(outline), Some(position)) => outline.breadcrumbs(&position),
+                                _ => vec![],
+                            };
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::Breadcrumbs(BreadcrumbList {
+                                    cursor,
+                                    revision: self.outline_revision,
+                                    items,
+                                })).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send Breadcrumbs message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::SessionSummary(summary, sent) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::SessionSummary(summary)).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send SessionSummary message to editor");
+                                break;
+                            };
+                            if let Some(sent) = sent {
+                                let _ = sent.send(());
+                            }
+                        }
+                        EditorActorRequest::SafeMode(report) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::SafeMode(Localized {
+                                    event: report,
+                                    message: StatusMessage::new(MessageCode::SafeMode, self.locale),
+                                })).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send SafeMode message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::UpdateStats(stats) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::UpdateStats(stats)).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send UpdateStats message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::Dependencies(dependencies) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::Dependencies(dependencies)).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send Dependencies message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::Screenshot(screenshot) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::Screenshot(screenshot)).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send Screenshot message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::Profiles(profiles) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::Profiles(profiles)).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send Profiles message to editor");
+                                break;
+                            };
+                        }
+                        EditorActorRequest::Thumbnails(items) => {
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::Thumbnails(ThumbnailList { items })).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send Thumbnails message to editor");
+                                break;
+                            };
+                        }
                     }
                 }
-                Some(Ok(Message::Text(msg))) = self.editor_websocket_conn.next() => {
+                msg = self.editor_conn.next() => {
+                    let msg = match msg {
+                        Some(Ok(Message::Text(msg))) => msg,
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => continue,
+                    };
                     let Ok(msg) = serde_json::from_str::<ControlPlaneMessage>(&msg) else {
                         warn!("failed to parse jump request: {:?}", msg);
                         continue;
@@ -165,6 +750,21 @@ impl EditorActor {
 
                             self.source_scroll_by_span(jump_info.span).await;
                         }
+                        ControlPlaneMessage::DocToSrcJumpBatchResolve(req) => {
+                            debug!("EditorActor: received message from editor: resolving {} spans", req.spans.len());
+                            let queries = self.span_interner.spans_by_str(&req.spans).await;
+                            let spans = req.spans.into_iter().zip(queries).map(|(id, query)| {
+                                let span = match query {
+                                    InternQuery::Ok(s) => s.map(SourceSpanOffset::from),
+                                    InternQuery::UseAfterFree => {
+                                        warn!("EditorActor: out of date span id: {}", id);
+                                        None
+                                    }
+                                };
+                                (id, span)
+                            }).collect();
+                            self.world_sender.send(TypstActorRequest::DocToSrcJumpBatchResolve(spans)).unwrap();
+                        }
                         ControlPlaneMessage::SyncMemoryFiles(memory_files) => {
                             debug!("EditorActor: received message from editor: SyncMemoryFiles {:?}", memory_files.files.keys().collect::<Vec<_>>());
                             self.world_sender.send(TypstActorRequest::SyncMemoryFiles(memory_files)).unwrap();
@@ -177,12 +777,222 @@ impl EditorActor {
                             debug!("EditorActor: received message from editor: RemoveMemoryFiles {:?}", &memory_files.files);
                             self.world_sender.send(TypstActorRequest::RemoveMemoryFiles(memory_files)).unwrap();
                         }
+                        ControlPlaneMessage::QueryViewport => {
+                            debug!("EditorActor: received message from editor: QueryViewport");
+                            // it is ok if no webview is connected
+                            let _ = self.webview_sender.send(WebviewActorRequest::QueryViewport);
+                        }
+                        ControlPlaneMessage::Highlight(req) => {
+                            debug!("EditorActor: received message from editor: Highlight {} ranges, {} spans", req.ranges.len(), req.spans.len());
+                            let spans = self
+                                .span_interner
+                                .spans_by_str(&req.spans)
+                                .await
+                                .into_iter()
+                                .filter_map(|query| match query {
+                                    InternQuery::Ok(span) => span,
+                                    InternQuery::UseAfterFree => None,
+                                })
+                                .collect();
+                            self.world_sender.send(TypstActorRequest::Highlight(req.ranges, spans)).unwrap();
+                        }
+                        ControlPlaneMessage::SetOverlays(overlays) => {
+                            debug!("EditorActor: received message from editor: SetOverlays {:?}", overlays);
+                            self.world_sender.send(TypstActorRequest::SetOverlays(overlays)).unwrap();
+                        }
+                        ControlPlaneMessage::QueryStructure => {
+                            debug!("EditorActor: received message from editor: QueryStructure");
+                            self.world_sender.send(TypstActorRequest::QueryStructure).unwrap();
+                        }
+                        ControlPlaneMessage::SetInputs(inputs) => {
+                            debug!("EditorActor: received message from editor: SetInputs {:?}", inputs);
+                            self.world_sender.send(TypstActorRequest::SetInputs(inputs)).unwrap();
+                        }
+                        ControlPlaneMessage::SetProfile(req) => {
+                            debug!("EditorActor: received message from editor: SetProfile {:?}", req.name);
+                            self.world_sender.send(TypstActorRequest::SetProfile(req.name)).unwrap();
+                        }
+                        ControlPlaneMessage::Screenshot(req) => {
+                            debug!("EditorActor: received message from editor: Screenshot {:?}", req);
+                            // the page viewed by the user, which the compiler doesn't know
+                            let page = req.page.or(self.visible_pages.first().copied()).unwrap_or(1);
+                            self.world_sender.send(TypstActorRequest::Screenshot(page, req)).unwrap();
+                        }
+                        ControlPlaneMessage::ListProfiles => {
+                            debug!("EditorActor: received message from editor: ListProfiles");
+                            self.world_sender.send(TypstActorRequest::ListProfiles).unwrap();
+                        }
+                        ControlPlaneMessage::QuerySessionSummary => {
+                            debug!("EditorActor: received message from editor: QuerySessionSummary");
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::SessionSummary(self.session.summary())).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send SessionSummary message to editor");
+                                break;
+                            };
+                        }
+                        ControlPlaneMessage::QueryMailboxes => {
+                            debug!("EditorActor: received message from editor: QueryMailboxes");
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::Mailboxes(MailboxList { items: mailbox_stats() })).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send Mailboxes message to editor");
+                                break;
+                            };
+                        }
+                        ControlPlaneMessage::QueryBreadcrumbs(cursor_info) => {
+                            debug!("EditorActor: received message from editor: QueryBreadcrumbs {:?}", cursor_info);
+                            self.world_sender.send(TypstActorRequest::QueryBreadcrumbs(cursor_info)).unwrap();
+                        }
+                        ControlPlaneMessage::QueryDependencies => {
+                            debug!("EditorActor: received message from editor: QueryDependencies");
+                            self.world_sender.send(TypstActorRequest::QueryDependencies).unwrap();
+                        }
+                        ControlPlaneMessage::SetBookmark(bookmark) => {
+                            debug!("EditorActor: received message from editor: SetBookmark {:?}", bookmark.name);
+                            self.world_sender.send(TypstActorRequest::SetBookmark(bookmark)).unwrap();
+                        }
+                        ControlPlaneMessage::ListBookmarks => {
+                            debug!("EditorActor: received message from editor: ListBookmarks");
+                            self.world_sender.send(TypstActorRequest::ListBookmarks).unwrap();
+                        }
+                        ControlPlaneMessage::JumpToBookmark(req) => {
+                            debug!("EditorActor: received message from editor: JumpToBookmark {:?}", req.name);
+                            self.world_sender.send(TypstActorRequest::JumpToBookmark(req.name)).unwrap();
+                        }
+                        ControlPlaneMessage::DeleteBookmark(req) => {
+                            debug!("EditorActor: received message from editor: DeleteBookmark {:?}", req.name);
+                            self.world_sender.send(TypstActorRequest::DeleteBookmark(req.name)).unwrap();
+                        }
+                        ControlPlaneMessage::AddComment(comment) => {
+                            debug!("EditorActor: received message from editor: AddComment");
+                            self.add_comment(comment).await;
+                        }
+                        ControlPlaneMessage::DeleteComment(req) => {
+                            debug!("EditorActor: received message from editor: DeleteComment {:?}", req.id);
+                            self.world_sender.send(TypstActorRequest::DeleteComment(req.id)).unwrap();
+                        }
+                        ControlPlaneMessage::ListComments => {
+                            debug!("EditorActor: received message from editor: ListComments");
+                            self.world_sender.send(TypstActorRequest::ListComments).unwrap();
+                        }
+                        ControlPlaneMessage::NavigateBack => {
+                            debug!("EditorActor: received message from editor: NavigateBack");
+                            self.world_sender.send(TypstActorRequest::NavigateBack).unwrap();
+                        }
+                        ControlPlaneMessage::NavigateForward => {
+                            debug!("EditorActor: received message from editor: NavigateForward");
+                            self.world_sender.send(TypstActorRequest::NavigateForward).unwrap();
+                        }
+                        ControlPlaneMessage::OutlineDeltaMode(req) => {
+                            debug!("EditorActor: received message from editor: {:?}", req);
+                            self.outline_delta_mode = req.enabled;
+                        }
+                        ControlPlaneMessage::OutlineResync => {
+                            debug!("EditorActor: received message from editor: OutlineResync");
+                            let Some(outline) = &self.outline else {
+                                continue;
+                            };
+                            let resp = self.full_outline(outline);
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&resp).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send Outline message to editor");
+                                break;
+                            };
+                        }
+                        ControlPlaneMessage::Resync => {
+                            debug!("EditorActor: received message from editor: Resync");
+                            // the outline of the recompiled document is sent
+                            // in full rather than as a delta
+                            self.outline = None;
+                            self.world_sender.send(TypstActorRequest::Resync).unwrap();
+                        }
+                        ControlPlaneMessage::OutlineChildren(req) => {
+                            debug!("EditorActor: received message from editor: {:?}", req);
+                            let Some(children) = self.outline.as_ref().and_then(|outline| {
+                                outline.children(req.id.as_deref(), self.outline_limit)
+                            }) else {
+                                warn!("EditorActor: unknown outline item: {:?}", req.id);
+                                continue;
+                            };
+                            let Ok(_) = self.editor_conn.send(Message::Text(
+                                serde_json::to_string(&ControlPlaneResponse::OutlineChildren(children)).unwrap(),
+                            )).await else {
+                                warn!("EditorActor: failed to send OutlineChildren message to editor");
+                                break;
+                            };
+                        }
                     };
                 }
             }
         }
-        info!("EditorActor: ws disconnected, shutting down whole program");
-        std::process::exit(0);
+        info!("EditorActor: ws disconnected");
+        self.span_interner.save().await;
+    }
+
+    /// Gives back the mailbox, for the next editor to take over.
+    pub fn into_mailbox(self) -> MailboxReceiver<EditorActorRequest> {
+        self.mailbox
+    }
+
+    /// Sends the outline to the editor, as a delta against the last sent
+    /// outline if the editor asked for it.
+    async fn update_outline(
+        &mut self,
+        mut outline: Outline,
+    ) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+        self.outline_revision += 1;
+        outline.set_revision(self.outline_revision);
+        let prev = self.outline.replace(outline.clone());
+
+        let resp = match prev {
+            Some(prev) if self.outline_delta_mode => {
+                // the editor only got the items within the limit
+                let delta = match self.outline_limit {
+                    Some(limit) => outline_delta(&prev.truncated(limit), &outline.truncated(limit)),
+                    None => outline_delta(&prev, &outline),
+                };
+                if delta.is_empty() {
+                    // keep the revision the editor knows about, but serve the
+                    // items beyond the limit from the new outline
+                    self.outline_revision -= 1;
+                    outline.set_revision(self.outline_revision);
+                    self.outline = Some(outline);
+                    return Ok(());
+                }
+                ControlPlaneResponse::OutlineDelta(delta)
+            }
+            _ => self.full_outline(&outline),
+        };
+
+        self.editor_conn
+            .send(Message::Text(serde_json::to_string(&resp).unwrap()))
+            .await
+    }
+
+    fn full_outline(&self, outline: &Outline) -> ControlPlaneResponse {
+        ControlPlaneResponse::Outline(match self.outline_limit {
+            Some(limit) => outline.truncated(limit),
+            None => outline.clone(),
+        })
+    }
+
+    /// Resolves the span id of a new comment before adding it.
+    async fn add_comment(&mut self, comment: NewComment) {
+        let span = match &comment.span {
+            Some(span) => match self.span_interner.span_by_str(span).await {
+                InternQuery::Ok(s) => s,
+                InternQuery::UseAfterFree => {
+                    warn!("EditorActor: out of date span id: {}", span);
+                    None
+                }
+            },
+            None => None,
+        };
+        self.world_sender
+            .send(TypstActorRequest::AddComment(comment, span))
+            .unwrap();
     }
 
     async fn source_scroll_by_span(&mut self, span: String) {
@@ -196,12 +1006,11 @@ impl EditorActor {
             }
         };
         if let Some(span) = jump_info {
-            let span_and_offset = span.into();
+            let span_and_offset = SourceSpanOffset::from(span);
             self.world_sender
-                .send(TypstActorRequest::DocToSrcJumpResolve((
-                    span_and_offset,
-                    span_and_offset,
-                )))
+                .send(TypstActorRequest::DocToSrcJumpResolve(
+                    span_and_offset.into(),
+                ))
                 .unwrap();
         };
     }
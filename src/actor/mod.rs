@@ -1,4 +1,5 @@
 pub mod editor;
 pub mod render;
+pub mod supervisor;
 pub mod typst;
 pub mod webview;
@@ -1,15 +1,27 @@
 use std::sync::Arc;
+use std::time::Instant;
 
 use log::{debug, info, trace};
-use tokio::sync::{broadcast, mpsc, watch};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 use typst::model::Document;
 use typst_ts_core::debug_loc::{ElementPoint, SourceSpanOffset};
 use typst_ts_core::TypstDocument;
-use typst_ts_svg_exporter::IncrSvgDocServer;
 
+use crate::mailbox::MailboxSender;
+use crate::overlay::{overlay, Overlays};
+use crate::postprocess::{Artifact, ArtifactKind, PostProcessors};
+use crate::renderer::{page_hash, Renderer};
+use crate::spill::SpillStore;
 use crate::{debug_loc::SpanInterner, outline::Outline};
 
-use super::{editor::EditorActorRequest, typst::TypstActorRequest, webview::WebviewActorRequest};
+use super::editor::{CompileStatus, CompileStatusEvent, EditorActorRequest};
+use super::supervisor::{Supervised, Supervisor};
+use super::typst::{DocToSrcJumpSpans, TypstActorRequest};
+use super::webview::{position_req, UpdateStats, WebviewActorRequest};
+
+/// The number of pages of the first update sent to a webview which has
+/// nothing to show yet. The following updates double the number of pages.
+const FIRST_UPDATE_PAGES: usize = 4;
 
 #[derive(Debug, Clone)]
 pub struct ResolveSpanRequest(pub Vec<ElementPoint>);
@@ -20,6 +32,18 @@ pub enum RenderActorRequest {
     RenderIncremental,
     ResolveSpan(ResolveSpanRequest),
     ChangeCursorPosition(SourceSpanOffset),
+    /// Whether the latest compilation failed, so that the rendered document
+    /// is left behind the sources.
+    Partial(bool),
+    /// Spans of the errors of the latest compilation, which are empty if
+    /// the compilation succeeded.
+    CompileErrors(Vec<SourceSpanOffset>),
+    /// Spans of the findings of the checkers on the latest compilation.
+    CheckFindings(Vec<SourceSpanOffset>),
+    /// The layout overlays to show on the rendered document.
+    SetOverlays(Overlays),
+    /// Whether to show errors as placeholders in the rendered document.
+    SetErrorPlaceholders(bool),
 }
 
 impl RenderActorRequest {
@@ -29,37 +53,96 @@ impl RenderActorRequest {
             Self::RenderIncremental => false,
             Self::ResolveSpan(_) => false,
             Self::ChangeCursorPosition(_) => false,
+            Self::Partial(_) => false,
+            Self::CompileErrors(_) => false,
+            Self::CheckFindings(_) => false,
+            Self::SetOverlays(_) => false,
+            Self::SetErrorPlaceholders(_) => false,
         }
     }
 }
 
 pub struct RenderActor {
     mailbox: broadcast::Receiver<RenderActorRequest>,
+    /// Requests of the webview of this actor for the whole document.
+    full_render: mpsc::UnboundedReceiver<()>,
     document: watch::Receiver<Option<Arc<Document>>>,
-    renderer: IncrSvgDocServer,
-    resolve_sender: mpsc::UnboundedSender<TypstActorRequest>,
-    svg_sender: mpsc::UnboundedSender<Vec<u8>>,
+    renderer: Box<dyn Renderer>,
+    /// Creates a fresh renderer when the actor is restarted after a panic.
+    create_renderer: Box<dyn Fn() -> Box<dyn Renderer> + Send>,
+    resolve_sender: MailboxSender<TypstActorRequest>,
+    svg_sender: MailboxSender<Vec<u8>>,
     webview_sender: broadcast::Sender<WebviewActorRequest>,
+    editor_sender: MailboxSender<EditorActorRequest>,
+    /// Where to report the statistics of the updates, if enabled.
+    stats_sender: Option<mpsc::UnboundedSender<UpdateStats>>,
+    /// The content hashes of the pages of the latest update, to count the
+    /// changed pages for the statistics.
+    page_hashes: Vec<u128>,
+    partial: bool,
+    /// Whether to show errors as placeholders in the rendered document.
+    error_placeholders: bool,
+    overlays: Overlays,
+    /// Whether the overlays changed since they were last sent.
+    overlays_changed: bool,
+    /// Spans of the findings of the checkers, which are shown once the
+    /// document they were found in is rendered.
+    findings: Vec<SourceSpanOffset>,
+    findings_changed: bool,
+    /// Whether no document has been sent to the webview yet.
+    first_render: bool,
+    /// Whether the renderer has packed no document yet, so that the webview
+    /// has nothing to show.
+    fresh: bool,
+    /// Whether to send the whole document without waiting for a request,
+    /// after a restart.
+    resync: bool,
+    /// Where the updates of huge documents are written, if enabled.
+    spill: Option<Arc<SpillStore>>,
+    post_processors: PostProcessors,
 }
 
 impl RenderActor {
     pub fn new(
         mailbox: broadcast::Receiver<RenderActorRequest>,
+        full_render: mpsc::UnboundedReceiver<()>,
         document: watch::Receiver<Option<Arc<Document>>>,
-        resolve_sender: mpsc::UnboundedSender<TypstActorRequest>,
-        svg_sender: mpsc::UnboundedSender<Vec<u8>>,
+        resolve_sender: MailboxSender<TypstActorRequest>,
+        svg_sender: MailboxSender<Vec<u8>>,
         webview_sender: broadcast::Sender<WebviewActorRequest>,
+        editor_sender: MailboxSender<EditorActorRequest>,
+        stats_sender: Option<mpsc::UnboundedSender<UpdateStats>>,
+        create_renderer: Box<dyn Fn() -> Box<dyn Renderer> + Send>,
+        partial: bool,
+        error_placeholders: bool,
+        overlays: Overlays,
+        spill: Option<Arc<SpillStore>>,
+        post_processors: PostProcessors,
     ) -> Self {
-        let mut res = Self {
+        Self {
             mailbox,
+            full_render,
             document,
-            renderer: IncrSvgDocServer::default(),
+            renderer: create_renderer(),
+            create_renderer,
             resolve_sender,
             svg_sender,
             webview_sender,
-        };
-        res.renderer.set_should_attach_debug_info(true);
-        res
+            editor_sender,
+            stats_sender,
+            page_hashes: vec![],
+            partial,
+            error_placeholders,
+            overlays,
+            overlays_changed: false,
+            findings: vec![],
+            findings_changed: false,
+            first_render: true,
+            fresh: true,
+            resync: false,
+            spill,
+            post_processors,
+        }
     }
 
     pub fn spawn(self) {
@@ -75,22 +158,37 @@ impl RenderActor {
         let res = msg.is_full_render();
 
         match msg {
-            RenderActorRequest::ResolveSpan(ResolveSpanRequest(element_path)) => {
+            RenderActorRequest::ResolveSpan(ResolveSpanRequest(mut element_path)) => {
                 info!("RenderActor: resolving span: {:?}", element_path);
-                let spans = match self.renderer.resolve_span_by_element_path(&element_path) {
-                    Ok(spans) => spans,
-                    Err(e) => {
-                        info!("RenderActor: failed to resolve span: {}", e);
-                        return false;
+                // Elements generated by show rules may have no usable span, in
+                // which case the closest ancestor having one is resolved.
+                let mut approximate = false;
+                let spans = loop {
+                    match self.renderer.resolve_span_by_element_path(&element_path) {
+                        Ok(Some(spans)) if !spans.1.span.is_detached() => break Some(spans),
+                        Ok(_) => {}
+                        Err(e) => {
+                            info!("RenderActor: failed to resolve span: {}", e);
+                        }
+                    }
+                    if element_path.pop().is_none() || element_path.is_empty() {
+                        break None;
                     }
+                    approximate = true;
                 };
 
-                info!("RenderActor: resolved span: {:?}", spans);
+                info!(
+                    "RenderActor: resolved span: {:?}, approximate: {}",
+                    spans, approximate
+                );
                 // end position is used
-                if let Some(spans) = spans {
+                if let Some(span_range) = spans {
                     let Ok(_) = self
                         .resolve_sender
-                        .send(TypstActorRequest::DocToSrcJumpResolve(spans))
+                        .send(TypstActorRequest::DocToSrcJumpResolve(DocToSrcJumpSpans {
+                            span_range,
+                            approximate,
+                        }))
                     else {
                         info!("RenderActor: resolve_sender is dropped");
                         return false;
@@ -109,64 +207,278 @@ impl RenderActor {
                         .send(WebviewActorRequest::CursorPaths(info));
                 }
             }
+            RenderActorRequest::Partial(partial) => {
+                // only report changes, full renders report the flag again
+                if self.partial != partial {
+                    self.partial = partial;
+                    self.send_partial();
+                }
+            }
+            RenderActorRequest::CompileErrors(spans) => {
+                if !self.error_placeholders {
+                    return res;
+                }
+
+                // errors can only be located in the last successfully compiled
+                // document, so they are shown where the failing expressions
+                // were rendered last time.
+                let paths = spans
+                    .into_iter()
+                    .filter_map(|span| self.renderer.resolve_element_paths_by_span(span).ok())
+                    .flatten()
+                    .collect::<Vec<_>>();
+                debug!("RenderActor: resolved error paths: {:?}", paths);
+                let json = serde_json::to_string(&paths).unwrap();
+                let msg = format!("error-paths,{json}");
+                let _ = self.svg_sender.send(msg.into_bytes());
+            }
+            RenderActorRequest::CheckFindings(spans) => {
+                self.findings_changed |= self.findings != spans;
+                self.findings = spans;
+            }
+            RenderActorRequest::SetOverlays(overlays) => {
+                self.overlays_changed |= self.overlays != overlays;
+                self.overlays = overlays;
+            }
+            RenderActorRequest::SetErrorPlaceholders(enabled) => {
+                // clears the placeholders shown so far
+                if self.error_placeholders && !enabled {
+                    let _ = self.svg_sender.send(b"error-paths,[]".to_vec());
+                }
+                self.error_placeholders = enabled;
+            }
             RenderActorRequest::RenderFullLatest | RenderActorRequest::RenderIncremental => {}
         }
 
         res
     }
 
+    /// Sends the overlays computed for the document, which clears the
+    /// overlays if none is enabled.
+    fn send_overlay(&self, document: &TypstDocument) {
+        let shapes = overlay(document, self.overlays);
+        let json = serde_json::to_string(&shapes).unwrap();
+        let msg = format!("overlay,{json}");
+        let _ = self.svg_sender.send(msg.into_bytes());
+    }
+
+    /// Sends the paths of the elements rendered from the findings of the
+    /// checkers, which clears them if there is none.
+    fn send_finding_paths(&mut self) {
+        let paths = self
+            .findings
+            .iter()
+            .filter_map(|span| self.renderer.resolve_element_paths_by_span(*span).ok())
+            .flatten()
+            .collect::<Vec<_>>();
+        debug!("RenderActor: resolved finding paths: {:?}", paths);
+        let json = serde_json::to_string(&paths).unwrap();
+        let msg = format!("finding-paths,{json}");
+        let _ = self.svg_sender.send(msg.into_bytes());
+    }
+
+    /// Sends the statistics of an update ahead of the update, which the
+    /// webview completes with the transfer time.
+    fn send_stats(&mut self, document: &TypstDocument, full: bool, size: usize, start: Instant) {
+        // hashing the pages is not part of the encoding
+        let encode_ms = start.elapsed().as_secs_f64() * 1000.;
+        let hashes = document.pages.iter().map(page_hash).collect::<Vec<_>>();
+        let changed_pages = if full {
+            hashes.len()
+        } else {
+            hashes
+                .iter()
+                .enumerate()
+                .filter(|(idx, hash)| self.page_hashes.get(*idx) != Some(hash))
+                .count()
+        };
+        let stats = UpdateStats {
+            size,
+            pages: hashes.len(),
+            changed_pages,
+            encode_ms,
+            transfer_ms: 0.,
+        };
+        self.page_hashes = hashes;
+        if let Some(sender) = &self.stats_sender {
+            let _ = sender.send(stats);
+        }
+    }
+
+    /// Packs a document for a webview which has nothing to show yet, sending
+    /// the updates of growing prefixes of its pages as soon as they are
+    /// packed, so that the first pages are shown before the whole document
+    /// is encoded. Returns the update completing the document, which is left
+    /// to send, and the size of the updates sent, or `None` if the webview
+    /// is gone.
+    fn pack_by_pages(&mut self, document: &Arc<Document>) -> Option<(Vec<u8>, usize)> {
+        let mut sent = 0;
+        let mut pages = FIRST_UPDATE_PAGES;
+        while pages < document.pages.len() {
+            let mut prefix = (**document).clone();
+            prefix.pages.truncate(pages);
+            let data = self.renderer.pack_delta(Arc::new(prefix));
+            let data = self.post_process(data);
+            sent += data.len();
+            if !self.send_update(data) {
+                return None;
+            }
+            pages *= 2;
+        }
+        Some((self.renderer.pack_delta(document.clone()), sent))
+    }
+
+    fn post_process(&self, data: Vec<u8>) -> Vec<u8> {
+        self.post_processors.run(
+            Artifact {
+                kind: ArtifactKind::Webview,
+                path: None,
+            },
+            data,
+        )
+    }
+
+    /// Sends an update to the webview, spilling it if it is huge. Returns
+    /// whether the webview is still there.
+    fn send_update(&self, data: Vec<u8>) -> bool {
+        let data = match &self.spill {
+            Some(spill) => spill.spill(data),
+            None => data,
+        };
+        let Ok(_) = self.svg_sender.send(data) else {
+            info!("RenderActor: svg_sender is dropped");
+            return false;
+        };
+        true
+    }
+
+    fn send_partial(&self) {
+        let msg = format!("partial-document,{}", self.partial);
+        let _ = self.svg_sender.send(msg.into_bytes());
+    }
+
     #[tokio::main(flavor = "current_thread")]
     async fn run(mut self) {
+        let mut supervisor = Supervisor::new("RenderActor");
         loop {
-            let mut has_full_render = false;
+            match supervisor.supervise(self.step()).await {
+                Supervised::Done(true) => {}
+                Supervised::Done(false) | Supervised::GiveUp => break,
+                Supervised::Restart => self.restart(),
+            }
+        }
+        info!("RenderActor: exiting")
+    }
+
+    /// Replaces the renderer, whose state is unknown after a panic, and
+    /// sends the whole document again.
+    fn restart(&mut self) {
+        self.renderer = (self.create_renderer)();
+        self.page_hashes.clear();
+        self.fresh = true;
+        self.resync = true;
+    }
+
+    /// Waits for the next requests and renders the latest document for them.
+    /// Returns whether the actor should carry on.
+    async fn step(&mut self) -> bool {
+        let mut has_full_render = std::mem::take(&mut self.resync);
+        if !has_full_render {
             debug!("RenderActor: waiting for message");
-            match self.mailbox.recv().await {
-                Ok(msg) => {
-                    has_full_render |= self.process_message(msg).await;
-                }
-                Err(broadcast::error::RecvError::Closed) => {
-                    info!("RenderActor: no more messages");
-                    break;
-                }
-                Err(broadcast::error::RecvError::Lagged(_)) => {
-                    info!("RenderActor: lagged message. Some events are dropped");
+            tokio::select! {
+                msg = self.mailbox.recv() => match msg {
+                    Ok(msg) => {
+                        has_full_render |= self.process_message(msg).await;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        info!("RenderActor: no more messages");
+                        return false;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        info!("RenderActor: lagged message. Some events are dropped");
+                    }
+                },
+                Some(()) = self.full_render.recv() => {
+                    has_full_render = true;
                 }
             }
-            // read the queue to empty
-            while let Ok(msg) = self.mailbox.try_recv() {
-                has_full_render |= self.process_message(msg).await;
-            }
-            // if a full render is requested, we render the latest document
-            // otherwise, we render the incremental changes for only once
-            let has_full_render = has_full_render;
-            debug!("RenderActor: has_full_render: {}", has_full_render);
-            let Some(document) = self.document.borrow().clone() else {
-                info!("RenderActor: document is not ready");
-                continue;
-            };
-            let data = if has_full_render {
-                if let Some(data) = self.renderer.pack_current() {
-                    data
-                } else {
-                    self.renderer.pack_delta(document)
+        }
+        // read the queue to empty
+        while let Ok(msg) = self.mailbox.try_recv() {
+            has_full_render |= self.process_message(msg).await;
+        }
+        while self.full_render.try_recv().is_ok() {
+            has_full_render = true;
+        }
+        // if a full render is requested, we render the latest document
+        // otherwise, we render the incremental changes for only once
+        let has_full_render = has_full_render;
+        debug!("RenderActor: has_full_render: {}", has_full_render);
+        let Some(document) = self.document.borrow().clone() else {
+            info!("RenderActor: document is not ready");
+            return true;
+        };
+        if has_full_render && self.partial {
+            self.send_partial();
+        }
+        let _ =
+            self.editor_sender
+                .send(EditorActorRequest::CompileStatus(CompileStatusEvent::now(
+                    CompileStatus::Rendering,
+                )));
+        let start = Instant::now();
+        let current = if has_full_render {
+            self.renderer.pack_current()
+        } else {
+            None
+        };
+        // the size of the updates sent ahead of the last one
+        let (data, sent) = match current {
+            Some(data) => (data, 0),
+            None if std::mem::take(&mut self.fresh) => match self.pack_by_pages(&document) {
+                Some(packed) => packed,
+                None => return false,
+            },
+            None => (self.renderer.pack_delta(document.clone()), 0),
+        };
+        comemo::evict(30);
+        let data = self.post_process(data);
+        if self.stats_sender.is_some() {
+            self.send_stats(&document, has_full_render, sent + data.len(), start);
+        }
+        if !self.send_update(data) {
+            return false;
+        }
+        // starts the webview at the page being edited
+        if std::mem::take(&mut self.first_render) {
+            let (reply, position) = oneshot::channel();
+            let _ = self
+                .resolve_sender
+                .send(TypstActorRequest::RevealCursor(reply));
+            let svg_sender = self.svg_sender.clone();
+            tokio::spawn(async move {
+                if let Ok(position) = position.await {
+                    let _ = svg_sender.send(position_req("viewport", position).into_bytes());
                 }
-            } else {
-                self.renderer.pack_delta(document)
-            };
-            comemo::evict(30);
-            let Ok(_) = self.svg_sender.send(data) else {
-                info!("RenderActor: svg_sender is dropped");
-                break;
-            };
+            });
         }
-        info!("RenderActor: exiting")
+        // overlays follow the layout of the latest document
+        if self.overlays_changed || !self.overlays.is_empty() {
+            self.overlays_changed = false;
+            self.send_overlay(&document);
+        }
+        if self.findings_changed || !self.findings.is_empty() {
+            self.findings_changed = false;
+            self.send_finding_paths();
+        }
+        true
     }
 }
 
 pub struct OutlineRenderActor {
     signal: broadcast::Receiver<RenderActorRequest>,
     document: watch::Receiver<Option<Arc<Document>>>,
-    editor_tx: mpsc::UnboundedSender<EditorActorRequest>,
+    editor_tx: MailboxSender<EditorActorRequest>,
 
     span_interner: SpanInterner,
 }
@@ -175,7 +487,7 @@ impl OutlineRenderActor {
     pub fn new(
         signal: broadcast::Receiver<RenderActorRequest>,
         document: watch::Receiver<Option<Arc<Document>>>,
-        editor_tx: mpsc::UnboundedSender<EditorActorRequest>,
+        editor_tx: MailboxSender<EditorActorRequest>,
         span_interner: SpanInterner,
     ) -> Self {
         Self {
@@ -195,6 +507,7 @@ impl OutlineRenderActor {
 
     #[tokio::main(flavor = "current_thread")]
     async fn run(mut self) {
+        let mut supervisor = Supervisor::new("OutlineRenderActor");
         loop {
             debug!("OutlineRenderActor: waiting for message");
             match self.signal.recv().await {
@@ -217,7 +530,12 @@ impl OutlineRenderActor {
                 info!("OutlineRenderActor: document is not ready");
                 continue;
             };
-            let data = self.outline(&document).await;
+            // the next document gets a fresh outline anyway
+            let data = match supervisor.supervise(self.outline(&document)).await {
+                Supervised::Done(data) => data,
+                Supervised::Restart => continue,
+                Supervised::GiveUp => break,
+            };
             comemo::evict(30);
             debug!("OutlineRenderActor: sending outline");
             let Ok(_) = self.editor_tx.send(EditorActorRequest::Outline(data)) else {
@@ -231,7 +549,7 @@ impl OutlineRenderActor {
     async fn outline(&self, document: &TypstDocument) -> Outline {
         self.span_interner
             .with_writer(|interner| {
-                interner.reset();
+                interner.reset_if_full();
                 crate::outline::outline(interner, document)
             })
             .await
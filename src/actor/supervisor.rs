@@ -0,0 +1,91 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
+
+use futures::FutureExt;
+use log::error;
+
+use crate::safe_mode::supervised;
+
+/// The number of panics within [`PANIC_WINDOW`] after which an actor is not
+/// restarted anymore, since it is most likely panicking on its own state.
+const MAX_PANICS: usize = 5;
+const PANIC_WINDOW: Duration = Duration::from_secs(60);
+
+/// What became of a step of a supervised actor.
+pub enum Supervised<T> {
+    Done(T),
+    /// The step panicked, and the actor should resynchronize with its peers
+    /// before carrying on.
+    Restart,
+    /// The actor panicked too often and should stop.
+    GiveUp,
+}
+
+/// Catches the panics of an actor, so that a single panicking message
+/// neither kills the task nor leaves its peers waiting for it.
+pub struct Supervisor {
+    name: &'static str,
+    panics: VecDeque<Instant>,
+}
+
+impl Supervisor {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            panics: VecDeque::new(),
+        }
+    }
+
+    /// Runs a step of the actor. The actor state borrowed by the step is
+    /// kept after a panic, so the actor is responsible for bringing it back
+    /// to a consistent state on [`Supervised::Restart`].
+    pub async fn supervise<F: Future>(&mut self, step: F) -> Supervised<F::Output> {
+        let mut step = std::pin::pin!(AssertUnwindSafe(step).catch_unwind());
+        // the panics of the step don't count as crashes of the preview
+        let step = std::future::poll_fn(|cx| supervised(|| step.as_mut().poll(cx)));
+        let payload = match step.await {
+            Ok(output) => return Supervised::Done(output),
+            Err(payload) => payload,
+        };
+
+        let now = Instant::now();
+        while self
+            .panics
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > PANIC_WINDOW)
+        {
+            self.panics.pop_front();
+        }
+        self.panics.push_back(now);
+
+        if self.panics.len() >= MAX_PANICS {
+            error!(
+                "{}: panicked {} times in {:?}, giving up: {}",
+                self.name,
+                self.panics.len(),
+                PANIC_WINDOW,
+                panic_message(&*payload)
+            );
+            return Supervised::GiveUp;
+        }
+        error!(
+            "{}: panicked, restarting: {}",
+            self.name,
+            panic_message(&*payload)
+        );
+        Supervised::Restart
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "unknown panic"
+    }
+}
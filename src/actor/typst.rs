@@ -1,32 +1,149 @@
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::{ChangeCursorPositionRequest, MemoryFiles, MemoryFilesShort, SrcToDocJumpRequest};
-use log::{debug, error, info};
-use tokio::sync::{broadcast, mpsc, watch};
+use crate::bookmark::{bookmarks_path, Bookmark, BookmarkSource, Bookmarks};
+use crate::cadence::TypingCadence;
+use crate::checker::check;
+use crate::comment::{comments_path, CommentAnchor, Comments, NewComment};
+use crate::config::{ProfileSettings, Profiles};
+use crate::dependencies::{document_fonts, loaded_packages, Dependencies, DocumentInfo};
+use crate::diagnostics::file_diagnostics_with_findings;
+use crate::history::{NavigationEntry, NavigationHistory};
+use crate::mailbox::{mailbox, Coalesce, MailboxConfigs, MailboxReceiver, MailboxSender};
+use crate::overlay::Overlays;
+use crate::postprocess::PostProcessors;
+use crate::renderer::SvgFilesWriter;
+use crate::screenshot::{screenshot, ScreenshotRequest};
+use crate::session::Session;
+use crate::snapshot::{resolve_range, CompileSnapshot};
+use crate::source_map::{export_source_map, rects_of, span_at, PageRect};
+use crate::structure::{export_structure, structure};
+use crate::{
+    typst_inputs, ChangeCursorPositionRequest, DocToSrcJumpInfo, HighlightRange, Inputs,
+    MemoryFiles, MemoryFilesShort, SrcToDocJumpRequest,
+};
+use comemo::Prehashed;
+use log::{debug, error, info, warn};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::time::Instant;
 use typst::diag::SourceResult;
-use typst::syntax::Span;
+use typst::syntax::{ast, FileId, LinkedNode, Source, Span, VirtualPath};
 use typst::{model::Document, World};
 use typst_ts_compiler::service::{
-    CompileActor, CompileClient as TsCompileClient, CompileExporter, Compiler, DocToSrcJumpInfo,
-    WorldExporter,
+    CompileActor, CompileClient as TsCompileClient, CompileExporter, Compiler, WorldExporter,
 };
 use typst_ts_compiler::service::{CompileDriver, CompileMiddleware};
 use typst_ts_compiler::vfs::notify::{FileChangeSet, MemoryEvent};
-use typst_ts_core::debug_loc::{CharPosition, SourceLocation, SourceSpanOffset};
+use typst_ts_core::debug_loc::{CharPosition, DocumentPosition, SourceLocation, SourceSpanOffset};
 
-use super::editor::CompileStatus;
+use super::editor::{CompileStatus, CompileStatusEvent, ResolvedSpan};
 use super::render::RenderActorRequest;
+use super::supervisor::{Supervised, Supervisor};
 use super::{editor::EditorActorRequest, webview::WebviewActorRequest};
 
+/// The spans of a clicked document element to resolve.
+#[derive(Debug)]
+pub struct DocToSrcJumpSpans {
+    /// The start and the end of the clicked range.
+    pub span_range: (SourceSpanOffset, SourceSpanOffset),
+    /// Whether the spans are taken from an ancestor of the clicked element,
+    /// because the element itself has no usable span.
+    pub approximate: bool,
+}
+
+impl From<SourceSpanOffset> for DocToSrcJumpSpans {
+    fn from(span: SourceSpanOffset) -> Self {
+        Self {
+            span_range: (span, span),
+            approximate: false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum TypstActorRequest {
-    DocToSrcJumpResolve((SourceSpanOffset, SourceSpanOffset)),
+    DocToSrcJumpResolve(DocToSrcJumpSpans),
+    /// Resolves span ids (in hex-format) in one go, replying with
+    /// [`EditorActorRequest::ResolvedSpans`].
+    DocToSrcJumpBatchResolve(Vec<(String, Option<SourceSpanOffset>)>),
     ChangeCursorPosition(ChangeCursorPositionRequest),
+    /// Resolves the latest editor cursor in the document, replying only if
+    /// it could be resolved, so that a new webview starts rendering at the
+    /// page being edited.
+    RevealCursor(oneshot::Sender<DocumentPosition>),
     SrcToDocJumpResolve(SrcToDocJumpRequest),
+    /// Highlights source ranges and spans in the webviews, replacing the
+    /// previous highlights.
+    Highlight(Vec<HighlightRange>, Vec<Span>),
+    SetOverlays(Overlays),
+    QueryStructure,
+    /// Resolves a source position to the document, replying with
+    /// [`EditorActorRequest::Breadcrumbs`] so that the editor can find the
+    /// outline items enclosing it.
+    QueryBreadcrumbs(ChangeCursorPositionRequest),
+    /// Lists the files read by the latest compilation.
+    QueryDependencies,
+    /// Replies with the fonts and the packages of the latest document, to
+    /// the webview which asked for them.
+    QueryDocumentInfo(mpsc::UnboundedSender<DocumentInfo>),
+
+    NavigateBack,
+    NavigateForward,
+
+    /// Adds a bookmark, resolving whichever of its document position and
+    /// source location is missing.
+    SetBookmark(Bookmark),
+    ListBookmarks,
+    JumpToBookmark(String),
+    DeleteBookmark(String),
+
+    /// Adds a comment anchored to the span if given, or else to the anchor or
+    /// the position of the comment.
+    AddComment(NewComment, Option<Span>),
+    DeleteComment(String),
+    ListComments,
 
     SyncMemoryFiles(MemoryFiles),
     UpdateMemoryFiles(MemoryFiles),
     RemoveMemoryFiles(MemoryFilesShort),
+
+    /// Replaces the `sys.inputs` of the document and compiles it again.
+    SetInputs(Inputs),
+    /// Drops the memory files and the cached files, and compiles the files
+    /// on disk again, sending the whole document to the webviews.
+    Resync,
+
+    /// Switches to the profile of the given name, or back to the settings of
+    /// the command line.
+    SetProfile(Option<String>),
+    ListProfiles,
+
+    /// Rasterizes the given 1-based page of the latest document.
+    Screenshot(usize, ScreenshotRequest),
+}
+
+/// Edits and requests superseded by newer ones are merged when the mailbox is
+/// full.
+impl Coalesce for TypstActorRequest {
+    fn coalesce(&mut self, newer: Self) -> Option<Self> {
+        match (self, newer) {
+            (Self::UpdateMemoryFiles(queued), Self::UpdateMemoryFiles(newer)) => {
+                queued.files.extend(newer.files);
+                None
+            }
+            (queued @ Self::ChangeCursorPosition(_), newer @ Self::ChangeCursorPosition(_))
+            | (queued @ Self::QueryBreadcrumbs(_), newer @ Self::QueryBreadcrumbs(_))
+            | (queued @ Self::Highlight(..), newer @ Self::Highlight(..))
+            | (queued @ Self::SetOverlays(_), newer @ Self::SetOverlays(_))
+            | (queued @ Self::SetInputs(_), newer @ Self::SetInputs(_))
+            | (queued @ Self::SetProfile(_), newer @ Self::SetProfile(_)) => {
+                *queued = newer;
+                None
+            }
+            (_, newer) => Some(newer),
+        }
+    }
 }
 
 pub type CompileService = CompileActor<Reporter<CompileExporter<CompileDriver>>>;
@@ -37,21 +154,31 @@ pub struct TypstActor {
     client: TypstClient,
 }
 
-type MpScChannel<T> = (mpsc::UnboundedSender<T>, mpsc::UnboundedReceiver<T>);
+type MailboxChannel<T> = (MailboxSender<T>, MailboxReceiver<T>);
 type WatchChannel<T> = (watch::Sender<T>, watch::Receiver<T>);
 type BroadcastChannel<T> = (broadcast::Sender<T>, broadcast::Receiver<T>);
 
 pub struct Channels {
-    pub typst_mailbox: MpScChannel<TypstActorRequest>,
+    pub typst_mailbox: MailboxChannel<TypstActorRequest>,
     pub doc_watch: WatchChannel<Option<Arc<Document>>>,
+    /// Whether the latest compilation failed, for the webviews connecting
+    /// afterwards.
+    pub partial_watch: WatchChannel<bool>,
     pub renderer_mailbox: BroadcastChannel<RenderActorRequest>,
-    pub editor_conn: MpScChannel<EditorActorRequest>,
+    pub editor_conn: MailboxChannel<EditorActorRequest>,
     pub webview_conn: BroadcastChannel<WebviewActorRequest>,
 }
 
 pub struct Reporter<C> {
     inner: C,
-    sender: mpsc::UnboundedSender<EditorActorRequest>,
+    root: PathBuf,
+    /// The events of the compilations, which are dropped rather than holding
+    /// up the compiler when the editor falls behind.
+    sender: MailboxSender<EditorActorRequest>,
+    renderer_sender: broadcast::Sender<RenderActorRequest>,
+    webview_sender: broadcast::Sender<WebviewActorRequest>,
+    partial_sender: watch::Sender<bool>,
+    session: Session,
 }
 
 impl<C: Compiler> CompileMiddleware for Reporter<C> {
@@ -71,39 +198,180 @@ impl<C: Compiler> CompileMiddleware for Reporter<C> {
     ) -> SourceResult<Arc<Document>> {
         let _ = self
             .sender
-            .send(EditorActorRequest::CompileStatus(CompileStatus::Compiling));
+            .try_send(EditorActorRequest::CompileStatus(CompileStatusEvent::now(
+                CompileStatus::Compiling,
+            )));
+        let start = std::time::Instant::now();
         let doc = self.inner_mut().compile(env);
+        self.session.record_compile(start.elapsed(), doc.is_ok());
+        // Typst doesn't produce a document for a failed compilation, so the
+        // webview keeps the last successfully compiled document, which is
+        // marked as partial until the next successful compilation.
+        self.partial_sender.send_replace(doc.is_err());
+        let _ = self
+            .renderer_sender
+            .send(RenderActorRequest::Partial(doc.is_err()));
+        let error_spans = match &doc {
+            Ok(_) => vec![],
+            Err(errors) => errors
+                .iter()
+                .filter(|diag| !diag.span.is_detached())
+                .map(|diag| SourceSpanOffset::from(diag.span))
+                .collect(),
+        };
+        let _ = self
+            .renderer_sender
+            .send(RenderActorRequest::CompileErrors(error_spans));
+        let findings = check(&self.project_sources());
+        let _ = self.renderer_sender.send(RenderActorRequest::CheckFindings(
+            findings
+                .iter()
+                .map(|finding| SourceSpanOffset::from(finding.span))
+                .collect(),
+        ));
+        let errors = doc.as_ref().err().map_or(&[][..], |errors| &errors[..]);
+        let diagnostics =
+            file_diagnostics_with_findings(self.inner.world(), &self.root, errors, &findings);
+        let _ = self
+            .sender
+            .try_send(EditorActorRequest::Diagnostics(diagnostics));
+        if let Ok(doc) = &doc {
+            let _ = self
+                .webview_sender
+                .send(WebviewActorRequest::DocumentInfo(self.document_info(doc)));
+        }
         if let Err(err) = &doc {
-            let _ = self.sender.send(EditorActorRequest::CompileStatus(
-                CompileStatus::CompileError,
-            ));
+            let _ =
+                self.sender
+                    .try_send(EditorActorRequest::CompileStatus(CompileStatusEvent::now(
+                        CompileStatus::CompileError,
+                    )));
             log::error!("TypstActor: compile error: {:?}", err);
         } else {
-            let _ = self.sender.send(EditorActorRequest::CompileStatus(
-                CompileStatus::CompileSuccess,
-            ));
+            let _ =
+                self.sender
+                    .try_send(EditorActorRequest::CompileStatus(CompileStatusEvent::now(
+                        CompileStatus::CompileSuccess,
+                    )));
         }
 
         doc
     }
 }
 
+impl<C: Compiler> Reporter<C> {
+    /// The Typst sources of the project read by the latest compilation,
+    /// leaving out the sources of packages.
+    fn project_sources(&self) -> Vec<Source> {
+        let world = self.inner.world();
+        let mut sources = vec![];
+        self.inner.iter_dependencies(&mut |path, _| {
+            if path.extension().map_or(true, |ext| ext != "typ") {
+                return;
+            }
+            let Some(vpath) = VirtualPath::within_root(path, &self.root) else {
+                return;
+            };
+            if let Ok(source) = world.source(FileId::new(None, vpath)) {
+                sources.push(source);
+            }
+        });
+        sources
+    }
+
+    /// The fonts and the packages of a document compiled just now.
+    fn document_info(&self, document: &Document) -> DocumentInfo {
+        let mut files = vec![];
+        self.inner
+            .iter_dependencies(&mut |path, _| files.push(path.to_path_buf()));
+        DocumentInfo {
+            fonts: document_fonts(document),
+            packages: loaded_packages(&files),
+        }
+    }
+}
+
 impl<C: Compiler + WorldExporter> WorldExporter for Reporter<C> {
     fn export(&mut self, output: Arc<typst::model::Document>) -> SourceResult<()> {
         self.inner.export(output)
     }
 }
 
+/// Functions loading a file given by their first positional argument.
+const FILE_LOADERS: &[&str] = &[
+    "bibliography",
+    "image",
+    "read",
+    "json",
+    "yaml",
+    "toml",
+    "csv",
+    "xml",
+    "cbor",
+    "plugin",
+];
+
+/// Finds the path of the file loaded by the closest enclosing call to a file
+/// loading function.
+fn loaded_file_path(node: &LinkedNode) -> Option<String> {
+    let mut node = node.clone();
+    loop {
+        if let Some(call) = node.cast::<ast::FuncCall>() {
+            if let ast::Expr::Ident(callee) = call.callee() {
+                if FILE_LOADERS.contains(&callee.as_str()) {
+                    return call.args().items().find_map(|arg| match arg {
+                        ast::Arg::Pos(ast::Expr::Str(path)) => Some(path.get().to_string()),
+                        _ => None,
+                    });
+                }
+            }
+        }
+        node = node.parent()?.clone();
+    }
+}
+
+fn comment_anchor(info: DocToSrcJumpInfo) -> Option<CommentAnchor> {
+    let (line, character) = info.start?;
+    Some(CommentAnchor {
+        filepath: info.filepath,
+        line,
+        character,
+    })
+}
+
+/// Resolves a file to the file system, where files in packages are resolved
+/// into the package cache and considered readonly.
+pub(crate) fn resolve_file_path(root: &Path, id: FileId) -> Option<(PathBuf, bool)> {
+    let Some(package) = id.package() else {
+        return Some((id.vpath().resolve(root)?, false));
+    };
+
+    // See https://github.com/typst/packages#local-packages
+    let base = if package.namespace == "preview" {
+        dirs::cache_dir()?
+    } else {
+        dirs::data_dir()?
+    };
+    let package_dir = base
+        .join("typst/packages")
+        .join(package.namespace.as_str())
+        .join(package.name.as_str())
+        .join(package.version.to_string());
+    Some((id.vpath().resolve(&package_dir)?, true))
+}
+
 impl TypstActor {
-    pub fn set_up_channels() -> Channels {
-        let typst_mailbox = mpsc::unbounded_channel();
+    pub fn set_up_channels(mailboxes: &MailboxConfigs) -> Channels {
+        let typst_mailbox = mailbox("typst", mailboxes.typst);
         let doc_watch = watch::channel(None);
+        let partial_watch = watch::channel(false);
         let renderer_mailbox = broadcast::channel(1024);
-        let editor_conn = mpsc::unbounded_channel();
+        let editor_conn = mailbox("editor", mailboxes.editor);
         let webview_conn = broadcast::channel(32);
         Channels {
             typst_mailbox,
             doc_watch,
+            partial_watch,
             renderer_mailbox,
             editor_conn,
             webview_conn,
@@ -112,17 +380,45 @@ impl TypstActor {
 
     pub fn new(
         compiler_driver: CompileDriver,
-        mailbox: mpsc::UnboundedReceiver<TypstActorRequest>,
+        mailbox: MailboxReceiver<TypstActorRequest>,
         doc_sender: watch::Sender<Option<Arc<Document>>>,
+        partial_sender: watch::Sender<bool>,
         renderer_sender: broadcast::Sender<RenderActorRequest>,
-        editor_conn_sender: mpsc::UnboundedSender<EditorActorRequest>,
+        editor_conn_sender: MailboxSender<EditorActorRequest>,
         webview_conn_sender: broadcast::Sender<WebviewActorRequest>,
+        source_map_path: Option<PathBuf>,
+        structure_path: Option<PathBuf>,
+        session: Session,
+        profiles: Profiles,
+        profile_sender: watch::Sender<ProfileSettings>,
+        svg_files: Option<SvgFilesWriter>,
+        post_processors: PostProcessors,
     ) -> Self {
         // CompileExporter + DynamicLayoutCompiler + WatchDriver
         let root = compiler_driver.world.root.clone();
+        let bookmarks = bookmarks_path(&root)
+            .map(Bookmarks::load)
+            .unwrap_or_default();
+        let comments = Comments::load(comments_path(&root));
+        let (snapshot_sender, snapshot) = watch::channel(None);
         let r = renderer_sender.clone();
         let driver = CompileExporter::new(compiler_driver).with_exporter(
-            move |_world: &dyn World, doc: Arc<Document>| {
+            move |world: &dyn World, doc: Arc<Document>| {
+                if let Some(path) = &source_map_path {
+                    if let Err(err) = export_source_map(world, &doc, path) {
+                        error!("TypstActor: failed to export source map: {}", err);
+                    }
+                }
+                if let Some(path) = &structure_path {
+                    if let Err(err) = export_structure(&doc, path) {
+                        error!("TypstActor: failed to export structure: {}", err);
+                    }
+                }
+                if let Some(svg_files) = &svg_files {
+                    svg_files.write(doc.clone());
+                }
+                let _ =
+                    snapshot_sender.send(Some(Arc::new(CompileSnapshot::new(world, doc.clone()))));
                 let _ = doc_sender.send(Some(doc)); // it is ok to ignore the error here
                 let _ = r.send(RenderActorRequest::RenderIncremental);
                 Ok(())
@@ -130,7 +426,12 @@ impl TypstActor {
         );
         let driver = Reporter {
             inner: driver,
+            root: root.as_ref().to_owned(),
             sender: editor_conn_sender.clone(),
+            renderer_sender: renderer_sender.clone(),
+            webview_sender: webview_conn_sender.clone(),
+            partial_sender,
+            session: session.clone(),
         };
         let inner = CompileActor::new(driver, root.as_ref().to_owned()).with_watch(true);
 
@@ -138,6 +439,19 @@ impl TypstActor {
             inner,
             client: TypstClient {
                 inner: once_cell::sync::OnceCell::new(),
+                root: root.as_ref().to_owned(),
+                history: NavigationHistory::default(),
+                cursor: None,
+                bookmarks,
+                comments,
+                snapshot,
+                cadence: TypingCadence::new(profiles.settings().max_compile_delay),
+                session,
+                profiles,
+                profile_sender,
+                post_processors,
+                pending_files: None,
+                compile_deadline: None,
                 mailbox,
                 editor_conn_sender,
                 webview_conn_sender,
@@ -146,7 +460,29 @@ impl TypstActor {
         }
     }
 
-    pub async fn run(self) {
+    /// Runs the actor on a dedicated runtime with `threads` worker threads, so
+    /// that compilations never stall the connections served by the main
+    /// runtime.
+    pub fn spawn(self, threads: usize) -> TypstActorHandle {
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let thread = std::thread::Builder::new()
+            .name("TypstActor".to_owned())
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(threads.max(1))
+                    .thread_name("typst-compile")
+                    .enable_all()
+                    .build()
+                    .unwrap();
+                runtime.block_on(self.run(shutdown_rx));
+                // the compiler and the watcher run as tasks of the runtime
+                runtime.shutdown_timeout(Duration::from_secs(1));
+            })
+            .unwrap();
+        TypstActorHandle { shutdown, thread }
+    }
+
+    async fn run(self, mut shutdown: oneshot::Receiver<()>) {
         let (server, client) = self.inner.split();
         server.spawn().await;
 
@@ -155,21 +491,75 @@ impl TypstActor {
         }
 
         let mut client = self.client;
+        let mut supervisor = Supervisor::new("TypstActor");
 
-        debug!("TypstActor: waiting for message");
-        while let Some(mail) = client.mailbox.recv().await {
-            client.process_mail(mail).await;
+        loop {
+            debug!("TypstActor: waiting for message");
+            let delayed = client.compile_deadline.is_some();
+            let deadline = client.compile_deadline.unwrap_or_else(Instant::now);
+            tokio::select! {
+                mail = client.mailbox.recv() => {
+                    let Some(mail) = mail else {
+                        break;
+                    };
+                    match supervisor.supervise(client.process_mail(mail)).await {
+                        Supervised::Done(()) => {}
+                        Supervised::Restart => client.resync(),
+                        Supervised::GiveUp => break,
+                    }
+                }
+                _ = tokio::time::sleep_until(deadline), if delayed => {
+                    client.flush_memory_files();
+                }
+                // the actor keeps running if the handle is dropped instead
+                Ok(()) = &mut shutdown => break,
+            }
         }
         info!("TypstActor: exiting");
     }
 }
 
+/// Stops the actor spawned by [`TypstActor::spawn`].
+pub struct TypstActorHandle {
+    shutdown: oneshot::Sender<()>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl TypstActorHandle {
+    /// Stops the actor and waits for its thread to exit.
+    pub fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        if self.thread.join().is_err() {
+            error!("TypstActor: the thread panicked");
+        }
+    }
+}
+
 struct TypstClient {
     inner: once_cell::sync::OnceCell<CompileClient>,
+    root: PathBuf,
+    history: NavigationHistory,
+    /// The latest cursor position reported by the editor.
+    cursor: Option<ChangeCursorPositionRequest>,
+    bookmarks: Bookmarks,
+    comments: Comments,
+    /// The latest compilation, which read-only requests use instead of the
+    /// compiler so that they neither wait for nor hold up compilations.
+    snapshot: watch::Receiver<Option<Arc<CompileSnapshot>>>,
+    cadence: TypingCadence,
+    session: Session,
+    profiles: Profiles,
+    /// The settings of the active profile, which new webviews start with.
+    profile_sender: watch::Sender<ProfileSettings>,
+    /// The post-processors of the screenshots.
+    post_processors: PostProcessors,
+    /// The edits waiting for the typing to pause before being compiled.
+    pending_files: Option<MemoryFiles>,
+    compile_deadline: Option<Instant>,
 
-    mailbox: mpsc::UnboundedReceiver<TypstActorRequest>,
+    mailbox: MailboxReceiver<TypstActorRequest>,
 
-    editor_conn_sender: mpsc::UnboundedSender<EditorActorRequest>,
+    editor_conn_sender: MailboxSender<EditorActorRequest>,
     webview_conn_sender: broadcast::Sender<WebviewActorRequest>,
     renderer_sender: broadcast::Sender<RenderActorRequest>,
 }
@@ -179,20 +569,56 @@ impl TypstClient {
         self.inner.get_mut().unwrap()
     }
 
+    fn snapshot(&self) -> Option<Arc<CompileSnapshot>> {
+        self.snapshot.borrow().clone()
+    }
+
     async fn process_mail(&mut self, mail: TypstActorRequest) {
         match mail {
-            TypstActorRequest::DocToSrcJumpResolve(span_range) => {
-                debug!("TypstActor: processing doc2src: {:?}", span_range);
-                let res = self.resolve_span_range(span_range).await;
+            TypstActorRequest::DocToSrcJumpResolve(spans) => {
+                debug!("TypstActor: processing doc2src: {:?}", spans);
+                let span_range = spans.span_range;
+                let res = match self.resolve_data_file(span_range.1.span).await {
+                    Some(info) => Some(info),
+                    None => self.resolve_span_range(span_range).await,
+                };
 
-                if let Some(info) = res {
+                if let Some(mut info) = res {
+                    info.approximate |= spans.approximate;
+                    if let Some((line, character)) = info.start {
+                        self.history.push(NavigationEntry {
+                            filepath: info.filepath.clone(),
+                            line,
+                            character,
+                            position: None,
+                        });
+                    }
                     let _ = self
                         .editor_conn_sender
-                        .send(EditorActorRequest::DocToSrcJump(info));
+                        .try_send(EditorActorRequest::DocToSrcJump(info));
                 }
             }
+            TypstActorRequest::DocToSrcJumpBatchResolve(spans) => {
+                debug!(
+                    "TypstActor: processing doc2src batch: {} spans",
+                    spans.len()
+                );
+                let mut items = Vec::with_capacity(spans.len());
+                for (span, span_offset) in spans {
+                    let info = match span_offset {
+                        Some(s) => self.resolve_span_range((s, s)).await,
+                        None => None,
+                    };
+                    items.push(ResolvedSpan { span, info });
+                }
+
+                let _ = self
+                    .editor_conn_sender
+                    .try_send(EditorActorRequest::ResolvedSpans(items));
+            }
             TypstActorRequest::ChangeCursorPosition(req) => {
                 debug!("TypstActor: processing src2doc: {:?}", req);
+                self.cursor = Some(req.clone());
 
                 let res = self
                     .inner()
@@ -216,31 +642,226 @@ impl TypstClient {
                         .send(RenderActorRequest::ChangeCursorPosition(info));
                 }
             }
+            TypstActorRequest::RevealCursor(reply) => {
+                let Some(cursor) = self.cursor.clone() else {
+                    return;
+                };
+                debug!("TypstActor: processing reveal cursor: {:?}", cursor);
+                let res = self
+                    .resolve_src_to_doc_jump(cursor.filepath, cursor.line, cursor.character)
+                    .await;
+                if let Some(position) = res {
+                    let _ = reply.send(position);
+                }
+            }
             TypstActorRequest::SrcToDocJumpResolve(req) => {
                 debug!("TypstActor: processing src2doc: {:?}", req);
 
-                // todo: change name to resolve resolve src position
+                let filepath = req.filepath.to_string_lossy().to_string();
                 let res = self
-                    .inner()
                     .resolve_src_to_doc_jump(req.filepath, req.line, req.character)
-                    .await
-                    .map_err(|err| {
-                        error!("TypstActor: failed to resolve src to doc jump: {:#}", err);
-                    })
-                    .ok()
-                    .flatten();
+                    .await;
 
-                if let Some(info) = res {
+                if let Some(position) = res {
+                    self.history.push(NavigationEntry {
+                        filepath,
+                        line: req.line,
+                        character: req.character,
+                        position: Some(position),
+                    });
                     let _ = self
                         .webview_conn_sender
-                        .send(WebviewActorRequest::SrcToDocJump(info.into()));
+                        .send(WebviewActorRequest::SrcToDocJump(position));
+                }
+            }
+            TypstActorRequest::Highlight(ranges, spans) => {
+                debug!(
+                    "TypstActor: processing highlight: {} ranges, {} spans",
+                    ranges.len(),
+                    spans.len()
+                );
+                let rects = self.resolve_highlight_rects(ranges, spans);
+                let _ = self
+                    .webview_conn_sender
+                    .send(WebviewActorRequest::Highlight(rects));
+            }
+            TypstActorRequest::SetOverlays(overlays) => {
+                debug!("TypstActor: processing set overlays: {:?}", overlays);
+                let _ = self
+                    .renderer_sender
+                    .send(RenderActorRequest::SetOverlays(overlays));
+            }
+            TypstActorRequest::SetProfile(name) => {
+                debug!("TypstActor: processing set profile: {:?}", name);
+                match self.profiles.activate(name.as_deref()) {
+                    Ok(settings) => self.apply_profile(settings),
+                    Err(err) => warn!("TypstActor: failed to set profile: {}", err),
+                }
+                let _ = self
+                    .editor_conn_sender
+                    .try_send(EditorActorRequest::Profiles(self.profiles.list()));
+            }
+            TypstActorRequest::Screenshot(page, req) => {
+                debug!("TypstActor: processing screenshot of page {}", page);
+                let Some(snapshot) = self.snapshot() else {
+                    return;
+                };
+                // rasterizing takes a while, so it doesn't hold up compilations
+                let document = snapshot.document.clone();
+                let post_processors = self.post_processors.clone();
+                let Ok(screenshot) = tokio::task::spawn_blocking(move || {
+                    screenshot(&document, page, &req, &post_processors)
+                })
+                .await
+                else {
+                    return;
+                };
+                let _ = self
+                    .editor_conn_sender
+                    .try_send(EditorActorRequest::Screenshot(screenshot));
+            }
+            TypstActorRequest::ListProfiles => {
+                debug!("TypstActor: processing list profiles");
+                let _ = self
+                    .editor_conn_sender
+                    .try_send(EditorActorRequest::Profiles(self.profiles.list()));
+            }
+            TypstActorRequest::QueryStructure => {
+                debug!("TypstActor: processing query structure");
+                let Some(snapshot) = self.snapshot() else {
+                    return;
+                };
+                let _ = self
+                    .editor_conn_sender
+                    .try_send(EditorActorRequest::Structure(structure(&snapshot.document)));
+            }
+            TypstActorRequest::QueryBreadcrumbs(req) => {
+                debug!("TypstActor: processing query breadcrumbs: {:?}", req);
+                let position = self
+                    .resolve_src_to_doc_jump(req.filepath.clone(), req.line, req.character)
+                    .await;
+                let _ = self
+                    .editor_conn_sender
+                    .try_send(EditorActorRequest::Breadcrumbs(req, position));
+            }
+            TypstActorRequest::QueryDependencies => {
+                debug!("TypstActor: processing query dependencies");
+                let dependencies = self.dependencies().await;
+                let _ = self
+                    .editor_conn_sender
+                    .try_send(EditorActorRequest::Dependencies(dependencies));
+            }
+            TypstActorRequest::QueryDocumentInfo(reply) => {
+                debug!("TypstActor: processing query document info");
+                let Dependencies { files, fonts } = self.dependencies().await;
+                let info = DocumentInfo {
+                    fonts,
+                    packages: loaded_packages(&files),
+                };
+                let _ = reply.send(info);
+            }
+            TypstActorRequest::NavigateBack => {
+                debug!("TypstActor: processing navigate back");
+                let entry = self.history.back().cloned();
+                self.replay_navigation(entry).await;
+            }
+            TypstActorRequest::NavigateForward => {
+                debug!("TypstActor: processing navigate forward");
+                let entry = self.history.forward().cloned();
+                self.replay_navigation(entry).await;
+            }
+            TypstActorRequest::SetBookmark(mut bookmark) => {
+                debug!("TypstActor: processing set bookmark: {:?}", bookmark);
+                if bookmark.source.is_none() {
+                    if let Some(position) = &bookmark.position {
+                        bookmark.source =
+                            self.resolve_doc_position(position).await.and_then(|info| {
+                                let (line, character) = info.start?;
+                                Some(BookmarkSource {
+                                    filepath: info.filepath,
+                                    line,
+                                    character,
+                                })
+                            });
+                    }
+                }
+                if bookmark.position.is_none() {
+                    if let Some(source) = &bookmark.source {
+                        bookmark.position = self
+                            .resolve_src_to_doc_jump(
+                                source.filepath.clone().into(),
+                                source.line,
+                                source.character,
+                            )
+                            .await;
+                    }
+                }
+                if bookmark.position.is_none() && bookmark.source.is_none() {
+                    warn!("TypstActor: bookmark {:?} has no location", bookmark.name);
+                    return;
+                }
+
+                self.bookmarks.set(bookmark);
+                self.send_bookmarks();
+            }
+            TypstActorRequest::ListBookmarks => {
+                debug!("TypstActor: processing list bookmarks");
+                self.send_bookmarks();
+            }
+            TypstActorRequest::JumpToBookmark(name) => {
+                debug!("TypstActor: processing jump to bookmark: {:?}", name);
+                let Some(bookmark) = self.bookmarks.get(&name).cloned() else {
+                    warn!("TypstActor: no bookmark named {:?}", name);
+                    return;
+                };
+                self.jump_to_bookmark(bookmark).await;
+            }
+            TypstActorRequest::DeleteBookmark(name) => {
+                debug!("TypstActor: processing delete bookmark: {:?}", name);
+                if self.bookmarks.delete(&name) {
+                    self.send_bookmarks();
                 }
             }
+            TypstActorRequest::AddComment(mut comment, span) => {
+                debug!("TypstActor: processing add comment: {:?}", comment);
+                let anchor = match span {
+                    Some(span) => self.resolve_span(span, None).await.and_then(comment_anchor),
+                    None => None,
+                };
+                let anchor = match (anchor.or(comment.anchor.take()), comment.position) {
+                    (Some(anchor), _) => Some(anchor),
+                    (None, Some(position)) => self
+                        .resolve_doc_position(&position)
+                        .await
+                        .and_then(comment_anchor),
+                    (None, None) => None,
+                };
+                let Some(anchor) = anchor else {
+                    warn!("TypstActor: comment has no location");
+                    return;
+                };
+
+                self.comments.add(comment.into_comment(anchor));
+                self.broadcast_comments().await;
+            }
+            TypstActorRequest::DeleteComment(id) => {
+                debug!("TypstActor: processing delete comment: {:?}", id);
+                if self.comments.delete(&id) {
+                    self.broadcast_comments().await;
+                }
+            }
+            TypstActorRequest::ListComments => {
+                debug!("TypstActor: processing list comments");
+                self.broadcast_comments().await;
+            }
             TypstActorRequest::SyncMemoryFiles(m) => {
                 debug!(
                     "TypstActor: processing SYNC memory files: {:?}",
                     m.files.keys().collect::<Vec<_>>()
                 );
+                // the synced files supersede the pending edits
+                self.pending_files = None;
+                self.compile_deadline = None;
                 self.update_memory_files(m, true);
             }
             TypstActorRequest::UpdateMemoryFiles(m) => {
@@ -248,16 +869,238 @@ impl TypstClient {
                     "TypstActor: processing UPDATE memory files: {:?}",
                     m.files.keys().collect::<Vec<_>>()
                 );
-                self.update_memory_files(m, false);
+                self.session.record_edits(m.files.keys());
+                let delay = self.cadence.edit();
+                match &mut self.pending_files {
+                    Some(pending) => pending.files.extend(m.files),
+                    None => self.pending_files = Some(m),
+                }
+                if delay.is_zero() {
+                    self.flush_memory_files();
+                } else {
+                    debug!("TypstActor: delaying compilation by {:?}", delay);
+                    self.compile_deadline = Some(Instant::now() + delay);
+                }
             }
             TypstActorRequest::RemoveMemoryFiles(m) => {
                 debug!("TypstActor: processing REMOVE memory files: {:?}", m.files);
+                self.flush_memory_files();
                 self.remove_shadow_files(m);
             }
+            TypstActorRequest::SetInputs(inputs) => {
+                debug!("TypstActor: processing set inputs: {:?}", inputs);
+                self.set_inputs(inputs).await;
+            }
+            TypstActorRequest::Resync => {
+                debug!("TypstActor: processing resync");
+                self.resync_from_disk().await;
+            }
         }
     }
 
+    async fn resolve_src_to_doc_jump(
+        &mut self,
+        filepath: PathBuf,
+        line: usize,
+        character: usize,
+    ) -> Option<DocumentPosition> {
+        // todo: change name to resolve resolve src position
+        self.inner()
+            .resolve_src_to_doc_jump(filepath, line, character)
+            .await
+            .map_err(|err| {
+                error!("TypstActor: failed to resolve src to doc jump: {:#}", err);
+            })
+            .ok()
+            .flatten()
+            .map(From::from)
+    }
+
+    /// Moves both the editor and the webviews to a position in the history.
+    async fn replay_navigation(&mut self, entry: Option<NavigationEntry>) {
+        let Some(entry) = entry else {
+            debug!("TypstActor: no more navigation history");
+            return;
+        };
+
+        let location = Some((entry.line, entry.character));
+        let _ = self
+            .editor_conn_sender
+            .try_send(EditorActorRequest::DocToSrcJump(DocToSrcJumpInfo {
+                filepath: entry.filepath.clone(),
+                start: location,
+                end: location,
+                readonly: false,
+                approximate: false,
+            }));
+
+        let position = match entry.position {
+            Some(position) => Some(position),
+            None => {
+                self.resolve_src_to_doc_jump(entry.filepath.into(), entry.line, entry.character)
+                    .await
+            }
+        };
+        if let Some(position) = position {
+            let _ = self
+                .webview_conn_sender
+                .send(WebviewActorRequest::SrcToDocJump(position));
+        }
+    }
+
+    /// Maps source ranges and spans to the rectangles of the rendered elements
+    /// in the latest document.
+    fn resolve_highlight_rects(
+        &self,
+        ranges: Vec<HighlightRange>,
+        spans: Vec<Span>,
+    ) -> Vec<PageRect> {
+        if ranges.is_empty() && spans.is_empty() {
+            return vec![];
+        }
+        let Some(snapshot) = self.snapshot() else {
+            return vec![];
+        };
+        // files without rendered elements are not in the snapshot, and there
+        // is nothing to highlight in them anyway
+        let ranges = ranges
+            .iter()
+            .filter_map(|range| {
+                let vpath = VirtualPath::within_root(&range.filepath, &self.root)?;
+                let source = snapshot.source(FileId::new(None, vpath))?;
+                let start = source.line_column_to_byte(range.start.0, range.start.1)?;
+                let end = source.line_column_to_byte(range.end.0, range.end.1)?;
+                // an empty range highlights the element at the position
+                Some((source, start..end.max(start + 1)))
+            })
+            .collect::<Vec<_>>();
+
+        rects_of(&snapshot.document, |span| {
+            spans.contains(&span)
+                || ranges.iter().any(|(source, range)| {
+                    span.id() == Some(source.id())
+                        && source
+                            .range(span)
+                            .is_some_and(|r| r.start < range.end && range.start < r.end)
+                })
+        })
+    }
+
+    async fn dependencies(&mut self) -> Dependencies {
+        let files = self
+            .inner()
+            .steal_async(|this, _| {
+                let mut files = vec![];
+                this.compiler.iter_dependencies(&mut |path, _| {
+                    files.push(path.to_string_lossy().to_string());
+                });
+                files.sort();
+                files
+            })
+            .await
+            .map_err(|err| {
+                error!("TypstActor: failed to list dependencies: {:#}", err);
+            })
+            .unwrap_or_default();
+        let fonts = self
+            .snapshot()
+            .map(|snapshot| document_fonts(&snapshot.document))
+            .unwrap_or_default();
+
+        Dependencies { files, fonts }
+    }
+
+    /// Jumps to a bookmark, preferring its source location, which survives
+    /// edits better than the document position.
+    async fn jump_to_bookmark(&mut self, bookmark: Bookmark) {
+        let Some(source) = bookmark.source else {
+            if let Some(position) = bookmark.position {
+                let _ = self
+                    .webview_conn_sender
+                    .send(WebviewActorRequest::SrcToDocJump(position));
+            }
+            return;
+        };
+
+        let position = self
+            .resolve_src_to_doc_jump(
+                source.filepath.clone().into(),
+                source.line,
+                source.character,
+            )
+            .await
+            .or(bookmark.position);
+        let entry = NavigationEntry {
+            filepath: source.filepath,
+            line: source.line,
+            character: source.character,
+            position,
+        };
+        self.history.push(entry.clone());
+        self.replay_navigation(Some(entry)).await;
+    }
+
+    fn send_bookmarks(&self) {
+        let _ = self
+            .editor_conn_sender
+            .try_send(EditorActorRequest::Bookmarks(
+                self.bookmarks.items().to_vec(),
+            ));
+    }
+
+    /// Sends the comments to the editor and the webviews, moving them to
+    /// where their anchors are rendered in the latest document.
+    async fn broadcast_comments(&mut self) {
+        for idx in 0..self.comments.items().len() {
+            let anchor = self.comments.items()[idx].anchor.clone();
+            let position = self
+                .resolve_src_to_doc_jump(anchor.filepath.into(), anchor.line, anchor.character)
+                .await;
+            if position.is_some() {
+                self.comments.items_mut()[idx].position = position;
+            }
+        }
+
+        let items = self.comments.items().to_vec();
+        let _ = self
+            .editor_conn_sender
+            .try_send(EditorActorRequest::Comments(items.clone()));
+        let _ = self
+            .webview_conn_sender
+            .send(WebviewActorRequest::Comments(items));
+    }
+
+    /// Resolves the source location of the element closest to a position in
+    /// the latest document.
+    async fn resolve_doc_position(
+        &mut self,
+        position: &DocumentPosition,
+    ) -> Option<DocToSrcJumpInfo> {
+        let snapshot = self.snapshot()?;
+        let span = span_at(&snapshot.document, position)?;
+        self.resolve_span(span, None).await
+    }
+
     async fn resolve_span(&mut self, s: Span, offset: Option<usize>) -> Option<DocToSrcJumpInfo> {
+        // spans of the latest document are resolved without the compiler
+        let id = s.id()?;
+        if let Some((start, end)) = self
+            .snapshot()
+            .and_then(|snapshot| snapshot.resolve(s, offset))
+        {
+            let (path, readonly) = resolve_file_path(&self.root, id)?;
+            return Some(DocToSrcJumpInfo {
+                filepath: path.to_string_lossy().to_string(),
+                start,
+                end,
+                readonly,
+                approximate: false,
+            });
+        }
+        if id.package().is_some() {
+            return self.resolve_package_span(s, offset).await;
+        }
+
         self.inner()
             .resolve_span_and_offset(s, offset)
             .await
@@ -266,6 +1109,72 @@ impl TypstClient {
             })
             .ok()
             .flatten()
+            .map(From::from)
+    }
+
+    /// Resolves a span in a package source to the downloaded package.
+    async fn resolve_package_span(
+        &mut self,
+        s: Span,
+        offset: Option<usize>,
+    ) -> Option<DocToSrcJumpInfo> {
+        let id = s.id()?;
+        let (path, readonly) = resolve_file_path(&self.root, id)?;
+        let (start, end) = self
+            .inner()
+            .steal_async(move |this, _| {
+                let source = this.compiler.world().source(id).ok()?;
+                resolve_range(&source, s, offset)
+            })
+            .await
+            .map_err(|err| {
+                error!("TypstActor: failed to resolve package span: {:#}", err);
+            })
+            .ok()
+            .flatten()?;
+
+        Some(DocToSrcJumpInfo {
+            filepath: path.to_string_lossy().to_string(),
+            start,
+            end,
+            readonly,
+            approximate: false,
+        })
+    }
+
+    /// Resolves the file loaded by the call producing the element, e.g.
+    /// `bibliography("refs.bib")` or `image("figure.png")`, so that the
+    /// editor can open the file instead of the typst source.
+    async fn resolve_data_file(&mut self, s: Span) -> Option<DocToSrcJumpInfo> {
+        let id = s.id()?;
+        let snapshot = self.snapshot();
+        let path = match snapshot.as_ref().and_then(|snapshot| snapshot.source(id)) {
+            Some(source) => loaded_file_path(&LinkedNode::new(source.root()).find(s)?),
+            None => self
+                .inner()
+                .steal_async(move |this, _| {
+                    let source = this.compiler.world().source(id).ok()?;
+                    let node = LinkedNode::new(source.root()).find(s)?;
+                    loaded_file_path(&node)
+                })
+                .await
+                .map_err(|err| {
+                    error!("TypstActor: failed to resolve data file: {:#}", err);
+                })
+                .ok()
+                .flatten(),
+        }?;
+        let id = FileId::new(id.package().cloned(), id.vpath().join(path));
+        let (path, readonly) = resolve_file_path(&self.root, id)?;
+
+        // Positions in data files are unknown, so the editor just opens them.
+        Some(DocToSrcJumpInfo {
+            filepath: path.to_string_lossy().to_string(),
+            start: None,
+            end: None,
+            readonly,
+            approximate: false,
+        })
     }
 
     async fn resolve_span_offset(&mut self, s: SourceSpanOffset) -> Option<DocToSrcJumpInfo> {
@@ -291,6 +1200,8 @@ impl TypstClient {
                         filepath: st.filepath,
                         start: st.start,
                         end: ed.start,
+                        readonly: st.readonly,
+                        approximate: st.approximate,
                     })
                 } else {
                     Some(ed)
@@ -341,6 +1252,74 @@ impl TypstClient {
         }
     }
 
+    async fn set_inputs(&mut self, inputs: Inputs) {
+        let inputs = Arc::new(Prehashed::new(typst_inputs(inputs.inputs)));
+        let res = self
+            .inner()
+            .steal_async(move |this, _| this.compiler.world_mut().set_inputs(inputs))
+            .await;
+        if let Err(err) = res {
+            error!("TypstActor: failed to set inputs: {:#}", err);
+            return;
+        }
+        // the inputs are not a file, so an empty change set makes the
+        // compiler pick them up
+        self.flush_memory_files();
+        self.inner()
+            .add_memory_changes(MemoryEvent::Update(FileChangeSet::default()));
+    }
+
+    /// Reconfigures the compilation and the running renderers.
+    fn apply_profile(&mut self, settings: ProfileSettings) {
+        self.cadence = TypingCadence::new(settings.max_compile_delay);
+        let _ = self
+            .renderer_sender
+            .send(RenderActorRequest::SetErrorPlaceholders(
+                settings.error_placeholders,
+            ));
+        let _ = self
+            .renderer_sender
+            .send(RenderActorRequest::SetOverlays(settings.overlays));
+        let _ = self.profile_sender.send(settings);
+    }
+
+    /// Brings the webviews back in line after a request panicked, since the
+    /// messages it sent before panicking are unknown.
+    fn resync(&mut self) {
+        let _ = self
+            .renderer_sender
+            .send(RenderActorRequest::RenderFullLatest);
+    }
+
+    /// Starts over from the files on disk, for when the editor and the
+    /// preview drifted apart.
+    async fn resync_from_disk(&mut self) {
+        // the pending edits are dropped along with the memory files
+        self.pending_files = None;
+        self.compile_deadline = None;
+        let res = self
+            .inner()
+            .steal_async(|this, _| this.compiler.reset())
+            .await;
+        match res {
+            Ok(Err(err)) => error!("TypstActor: failed to reset the compiler: {:?}", err),
+            Err(err) => error!("TypstActor: failed to reset the compiler: {:#}", err),
+            Ok(Ok(())) => {}
+        }
+        // syncing no file removes all of the memory files and compiles again
+        self.inner()
+            .add_memory_changes(MemoryEvent::Sync(FileChangeSet::default()));
+        self.resync();
+    }
+
+    /// Hands the pending edits to the compiler.
+    fn flush_memory_files(&mut self) {
+        self.compile_deadline = None;
+        if let Some(files) = self.pending_files.take() {
+            self.update_memory_files(files, false);
+        }
+    }
+
     fn update_memory_files(&mut self, files: MemoryFiles, reset_shadow: bool) {
         // todo: is it safe to believe that the path is normalized?
         let now = std::time::SystemTime::now();
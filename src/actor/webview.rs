@@ -1,5 +1,8 @@
+use std::time::Instant;
+
 use futures::{SinkExt, StreamExt};
-use log::{info, trace};
+use log::{info, trace, warn};
+use serde::{Deserialize, Serialize};
 use tokio::{
     net::TcpStream,
     sync::{broadcast, mpsc},
@@ -8,8 +11,16 @@ use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 use typst_ts_core::debug_loc::{DocumentPosition, ElementPoint};
 
 use crate::actor::{editor::DocToSrcJumpResolveRequest, render::ResolveSpanRequest};
+use crate::bookmark::Bookmark;
+use crate::comment::{Comment, NewComment};
+use crate::dependencies::DocumentInfo;
+use crate::mailbox::{mailbox, MailboxConfig, MailboxReceiver, MailboxSender};
+use crate::overlay::Overlays;
+use crate::source_map::PageRect;
 
-use super::{editor::EditorActorRequest, render::RenderActorRequest};
+use super::editor::{CompileStatus, CompileStatusEvent, EditorActorRequest};
+use super::render::RenderActorRequest;
+use super::typst::TypstActorRequest;
 
 // pub type CursorPosition = DocumentPosition;
 pub type SrcToDocJumpInfo = DocumentPosition;
@@ -20,9 +31,55 @@ pub enum WebviewActorRequest {
     SrcToDocJump(SrcToDocJumpInfo),
     // CursorPosition(CursorPosition),
     CursorPaths(Vec<Vec<ElementPoint>>),
+    /// Asks the webview which part of the document is visible.
+    QueryViewport,
+    /// Replaces the highlight overlay of the webview.
+    Highlight(Vec<PageRect>),
+    Comments(Vec<Comment>),
+    /// The fonts and the packages of the latest document.
+    DocumentInfo(DocumentInfo),
+}
+
+/// The part of the document visible in a webview.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViewportInfo {
+    /// The 1-based numbers of the visible pages.
+    pages: Vec<usize>,
+    /// The position at the top left corner of the viewport.
+    position: Option<DocumentPosition>,
+}
+
+/// Statistics of a document update sent to a webview.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateStats {
+    /// The size of the encoded update in bytes.
+    pub size: usize,
+    /// The number of pages of the document.
+    pub pages: usize,
+    /// The number of pages changed since the previous update, which is all
+    /// pages for a full update.
+    pub changed_pages: usize,
+    /// Milliseconds spent encoding the update.
+    pub encode_ms: f64,
+    /// Milliseconds spent writing the update to the websocket.
+    pub transfer_ms: f64,
 }
 
-fn position_req(
+/// Reported when a webview runs a frontend built differently from the one
+/// embedded in this server, which usually means a partial upgrade.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrontendVersionMismatch {
+    /// The build id of the frontend embedded in the server.
+    embedded: Option<String>,
+    /// The build id reported by the webview.
+    reported: String,
+    /// Whether the webview could request the embedded frontend instead.
+    frontend_available: bool,
+}
+
+pub(crate) fn position_req(
     event: &'static str,
     DocumentPosition { page_no, x, y }: DocumentPosition,
 ) -> String {
@@ -31,46 +88,98 @@ fn position_req(
 
 pub struct WebviewActor {
     webview_websocket_conn: WebSocketStream<TcpStream>,
-    svg_receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+    svg_receiver: MailboxReceiver<Vec<u8>>,
+    /// The statistics of the updates, which the renderer sends ahead of each
+    /// update when they are enabled.
+    stats_receiver: mpsc::UnboundedReceiver<UpdateStats>,
     mailbox: broadcast::Receiver<WebviewActorRequest>,
 
     broadcast_sender: broadcast::Sender<WebviewActorRequest>,
-    editor_sender: mpsc::UnboundedSender<EditorActorRequest>,
+    editor_sender: MailboxSender<EditorActorRequest>,
     render_sender: broadcast::Sender<RenderActorRequest>,
+    /// Asks the renderer of this webview, and only this one, for the whole
+    /// document.
+    full_render_sender: mpsc::UnboundedSender<()>,
+    typst_sender: MailboxSender<TypstActorRequest>,
+    /// The replies to the document info queries of this webview.
+    document_info: (
+        mpsc::UnboundedSender<DocumentInfo>,
+        mpsc::UnboundedReceiver<DocumentInfo>,
+    ),
+
+    /// The url of the data plane the frontend requested by the webview
+    /// connects to.
+    data_plane_url: String,
+    /// Whether the webview may drive the editor and other webviews, i.e. it
+    /// is not a viewer-only client.
+    interactive: bool,
 }
 
 pub struct Channels {
-    pub svg: (
-        mpsc::UnboundedSender<Vec<u8>>,
-        mpsc::UnboundedReceiver<Vec<u8>>,
+    pub svg: (MailboxSender<Vec<u8>>, MailboxReceiver<Vec<u8>>),
+    pub stats: (
+        mpsc::UnboundedSender<UpdateStats>,
+        mpsc::UnboundedReceiver<UpdateStats>,
     ),
+    pub full_render: (mpsc::UnboundedSender<()>, mpsc::UnboundedReceiver<()>),
 }
 
 impl WebviewActor {
-    pub fn set_up_channels() -> Channels {
+    pub fn set_up_channels(svg: MailboxConfig) -> Channels {
         Channels {
-            svg: mpsc::unbounded_channel(),
+            svg: mailbox("webview", svg),
+            stats: mpsc::unbounded_channel(),
+            full_render: mpsc::unbounded_channel(),
         }
     }
     pub fn new(
         websocket_conn: WebSocketStream<TcpStream>,
-        svg_receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+        svg_receiver: MailboxReceiver<Vec<u8>>,
+        stats_receiver: mpsc::UnboundedReceiver<UpdateStats>,
         broadcast_sender: broadcast::Sender<WebviewActorRequest>,
         mailbox: broadcast::Receiver<WebviewActorRequest>,
-        editor_sender: mpsc::UnboundedSender<EditorActorRequest>,
+        editor_sender: MailboxSender<EditorActorRequest>,
         render_sender: broadcast::Sender<RenderActorRequest>,
+        full_render_sender: mpsc::UnboundedSender<()>,
+        typst_sender: MailboxSender<TypstActorRequest>,
+        data_plane_url: String,
+        interactive: bool,
     ) -> Self {
         Self {
             webview_websocket_conn: websocket_conn,
             svg_receiver,
+            stats_receiver,
             mailbox,
             broadcast_sender,
             editor_sender,
             render_sender,
+            full_render_sender,
+            typst_sender,
+            document_info: mpsc::unbounded_channel(),
+            data_plane_url,
+            interactive,
         }
     }
 
-    pub async fn run(mut self) {
+    /// Asks the renderer of this webview for the whole document again after
+    /// a restart, since the webview may have missed updates.
+    pub fn resync(&self) {
+        let _ = self.full_render_sender.send(());
+    }
+
+    /// Sends a message to the webview, returning whether it is still
+    /// connected.
+    async fn send(&mut self, msg: Vec<u8>) -> bool {
+        match self.webview_websocket_conn.send(Message::Binary(msg)).await {
+            Ok(()) => true,
+            Err(err) => {
+                info!("WebviewActor: failed to send to webview: {}", err);
+                false
+            }
+        }
+    }
+
+    pub async fn run(&mut self) {
         loop {
             tokio::select! {
                 Ok(msg) = self.mailbox.recv() => {
@@ -78,26 +187,80 @@ impl WebviewActor {
                     match msg {
                         WebviewActorRequest::SrcToDocJump(jump_info) => {
                             let msg = position_req("jump", jump_info);
-                            self.webview_websocket_conn.send(Message::Binary(msg.into_bytes())).await.unwrap();
+                            if !self.send(msg.into_bytes()).await {
+                                break;
+                            }
                         }
                         WebviewActorRequest::ViewportPosition(jump_info) => {
                             let msg = position_req("viewport", jump_info);
-                            self.webview_websocket_conn.send(Message::Binary(msg.into_bytes())).await.unwrap();
+                            if !self.send(msg.into_bytes()).await {
+                                break;
+                            }
                         }
                         // WebviewActorRequest::CursorPosition(jump_info) => {
                         //     let msg = position_req("cursor", jump_info);
                         //     self.webview_websocket_conn.send(Message::Binary(msg.into_bytes())).await.unwrap();
                         // }
+                        WebviewActorRequest::QueryViewport => {
+                            if !self.send("query-viewport,".into()).await {
+                                break;
+                            }
+                        }
+                        WebviewActorRequest::Highlight(rects) => {
+                            let json = serde_json::to_string(&rects).unwrap();
+                            let msg = format!("highlight,{json}");
+                            if !self.send(msg.into_bytes()).await {
+                                break;
+                            }
+                        }
+                        WebviewActorRequest::DocumentInfo(info) => {
+                            let json = serde_json::to_string(&info).unwrap();
+                            let msg = format!("document-info,{json}");
+                            if !self.send(msg.into_bytes()).await {
+                                break;
+                            }
+                        }
+                        WebviewActorRequest::Comments(comments) => {
+                            let json = serde_json::to_string(&comments).unwrap();
+                            let msg = format!("comments,{json}");
+                            if !self.send(msg.into_bytes()).await {
+                                break;
+                            }
+                        }
                         WebviewActorRequest::CursorPaths(jump_info) => {
                             let json = serde_json::to_string(&jump_info).unwrap();
                             let msg = format!("cursor-paths,{json}");
-                            self.webview_websocket_conn.send(Message::Binary(msg.into_bytes())).await.unwrap();
+                            if !self.send(msg.into_bytes()).await {
+                                break;
+                            }
                         }
                     }
                 }
+                Some(info) = self.document_info.1.recv() => {
+                    let json = serde_json::to_string(&info).unwrap();
+                    let msg = format!("document-info,{json}");
+                    if !self.send(msg.into_bytes()).await {
+                        break;
+                    }
+                }
                 Some(svg) = self.svg_receiver.recv() => {
                     trace!("WebviewActor: received svg from renderer");
-                    self.webview_websocket_conn.send(Message::Binary(svg)).await.unwrap();
+                    let is_document = svg.starts_with(b"new,")
+                        || svg.starts_with(b"diff-v1,")
+                        || svg.starts_with(b"spilled,");
+                    let start = Instant::now();
+                    if !self.send(svg).await {
+                        break;
+                    }
+                    if is_document {
+                        let _ = self.editor_sender.send(EditorActorRequest::CompileStatus(
+                            CompileStatusEvent::now(CompileStatus::Uploaded),
+                        ));
+                        if let Ok(mut stats) = self.stats_receiver.try_recv() {
+                            stats.transfer_ms = start.elapsed().as_secs_f64() * 1000.;
+                            let _ = self.editor_sender.send(EditorActorRequest::UpdateStats(stats));
+                        }
+                    }
                 }
                 Some(msg) = self.webview_websocket_conn.next() => {
                     trace!("WebviewActor: received message from websocket: {:?}", msg);
@@ -111,14 +274,64 @@ impl WebviewActor {
                         break;
                     };
                     if msg == "current" {
-                        self.render_sender.send(RenderActorRequest::RenderFullLatest).unwrap();
+                        let _ = self.full_render_sender.send(());
+                    } else if let Some(reported) = msg.strip_prefix("frontend-version,") {
+                        self.check_frontend_version(reported).await;
+                    } else if msg == "frontend" {
+                        let msg = format!("frontend,{}", crate::frontend_html(&self.data_plane_url));
+                        if !self.send(msg.into_bytes()).await {
+                            break;
+                        }
+                    } else if let Some(info) = msg.strip_prefix("viewport-info,") {
+                        let Ok(info) = serde_json::from_str::<ViewportInfo>(info) else {
+                            info!("WebviewActor: received invalid viewport info: {}", info);
+                            continue;
+                        };
+                        let _ = self.editor_sender.send(EditorActorRequest::Viewport(info));
+                    } else if let Some(overlays) = msg.strip_prefix("overlays,") {
+                        let Ok(overlays) = serde_json::from_str::<Overlays>(overlays) else {
+                            info!("WebviewActor: received invalid overlays: {}", overlays);
+                            continue;
+                        };
+                        let _ = self.render_sender.send(RenderActorRequest::SetOverlays(overlays));
+                    } else if msg == "comment-list" {
+                        let _ = self.editor_sender.send(EditorActorRequest::ListComments);
+                    } else if msg == "document-info" {
+                        let reply = self.document_info.0.clone();
+                        let _ = self.typst_sender.send(TypstActorRequest::QueryDocumentInfo(reply));
+                    } else if !self.interactive {
+                        trace!("WebviewActor: ignoring message from viewer-only client: {}", msg);
+                    } else if let Some(pages) = msg.strip_prefix("visible-pages,") {
+                        let Ok(pages) = serde_json::from_str::<Vec<usize>>(pages) else {
+                            info!("WebviewActor: received invalid visible pages: {}", pages);
+                            continue;
+                        };
+                        let _ = self.editor_sender.send(EditorActorRequest::VisiblePages(pages));
+                    } else if let Some(bookmark) = msg.strip_prefix("bookmark,") {
+                        let Ok(bookmark) = serde_json::from_str::<Bookmark>(bookmark) else {
+                            info!("WebviewActor: received invalid bookmark: {}", bookmark);
+                            continue;
+                        };
+                        let _ = self.editor_sender.send(EditorActorRequest::SetBookmark(bookmark));
+                    } else if let Some(name) = msg.strip_prefix("bookmark-jump,") {
+                        let _ = self.editor_sender.send(EditorActorRequest::JumpToBookmark(name.to_owned()));
+                    } else if let Some(name) = msg.strip_prefix("bookmark-delete,") {
+                        let _ = self.editor_sender.send(EditorActorRequest::DeleteBookmark(name.to_owned()));
+                    } else if let Some(comment) = msg.strip_prefix("comment-add,") {
+                        let Ok(comment) = serde_json::from_str::<NewComment>(comment) else {
+                            info!("WebviewActor: received invalid comment: {}", comment);
+                            continue;
+                        };
+                        let _ = self.editor_sender.send(EditorActorRequest::AddComment(comment));
+                    } else if let Some(id) = msg.strip_prefix("comment-delete,") {
+                        let _ = self.editor_sender.send(EditorActorRequest::DeleteComment(id.to_owned()));
                     } else if msg.starts_with("srclocation") {
                         let location = msg.split(' ').nth(1).unwrap();
-                        self.editor_sender.send(EditorActorRequest::DocToSrcJumpResolve(
+                        let _ = self.editor_sender.send(EditorActorRequest::DocToSrcJumpResolve(
                             DocToSrcJumpResolveRequest {
                                 span: location.trim().to_owned(),
                             },
-                        )).unwrap();
+                        ));
                     } else if msg.starts_with("outline-sync") {
                         let location = msg.split(',').nth(1).unwrap();
                         let location = location.split(' ').collect::<Vec::<&str>>();
@@ -127,18 +340,18 @@ impl WebviewActor {
                         let y = location.get(2).map(|s| s.parse().unwrap()).unwrap_or(0.);
                         let pos = DocumentPosition { page_no, x, y };
 
-                        self.broadcast_sender.send(WebviewActorRequest::ViewportPosition(pos)).unwrap();
+                        let _ = self.broadcast_sender.send(WebviewActorRequest::ViewportPosition(pos));
                     } else if msg.starts_with("srcpath") {
                         let path = msg.split(' ').nth(1).unwrap();
                         let path = serde_json::from_str(path);
                         if let Ok(path) = path {
                             let path: Vec<(u32, u32, String)> = path;
                             let path = path.into_iter().map(ElementPoint::from).collect::<Vec<_>>();
-                            self.render_sender.send(RenderActorRequest::ResolveSpan(ResolveSpanRequest(path))).unwrap();
+                            let _ = self.render_sender.send(RenderActorRequest::ResolveSpan(ResolveSpanRequest(path)));
                         };
                     } else {
                         info!("WebviewActor: received unknown message from websocket: {}", msg);
-                        self.webview_websocket_conn.send(Message::Text(format!("error, received unknown message: {}", msg))).await.unwrap();
+                        let _ = self.webview_websocket_conn.send(Message::Text(format!("error, received unknown message: {}", msg))).await;
                         break;
                     }
                 }
@@ -149,4 +362,30 @@ impl WebviewActor {
         }
         info!("WebviewActor: exiting");
     }
+
+    async fn check_frontend_version(&mut self, reported: &str) {
+        let embedded = crate::frontend_build();
+        if embedded == Some(reported) {
+            return;
+        }
+        warn!(
+            "WebviewActor: frontend version mismatch, embedded: {:?}, reported: {}",
+            embedded, reported
+        );
+
+        let mismatch = FrontendVersionMismatch {
+            embedded: embedded.map(ToOwned::to_owned),
+            reported: reported.to_owned(),
+            frontend_available: embedded.is_some(),
+        };
+        let json = serde_json::to_string(&mismatch).unwrap();
+        let msg = format!("version-mismatch,{json}");
+        let _ = self
+            .webview_websocket_conn
+            .send(Message::Binary(msg.into_bytes()))
+            .await;
+        let _ = self
+            .editor_sender
+            .send(EditorActorRequest::FrontendVersionMismatch(mismatch));
+    }
 }
@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use typst_ts_core::debug_loc::DocumentPosition;
+
+use crate::debug_loc::project_id;
+use crate::json_list::JsonList;
+
+/// A source location of a bookmark.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookmarkSource {
+    pub filepath: String,
+    /// 0-based line.
+    pub line: usize,
+    /// 0-based character.
+    pub character: usize,
+}
+
+/// A named reading position, kept both as a position in the document and as
+/// the source location it was resolved to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub position: Option<DocumentPosition>,
+    pub source: Option<BookmarkSource>,
+}
+
+/// The bookmarks of a project, persisted across sessions.
+#[derive(Debug)]
+pub struct Bookmarks(JsonList<Bookmark>);
+
+impl Default for Bookmarks {
+    fn default() -> Self {
+        Self(JsonList::new("bookmarks"))
+    }
+}
+
+impl Bookmarks {
+    /// Loads the bookmarks saved at `path`, if any.
+    pub fn load(path: PathBuf) -> Self {
+        Self(JsonList::load("bookmarks", path))
+    }
+
+    pub fn items(&self) -> &[Bookmark] {
+        self.0.items()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Bookmark> {
+        self.items().iter().find(|bookmark| bookmark.name == name)
+    }
+
+    /// Adds a bookmark, replacing the one with the same name, and saves the
+    /// bookmarks.
+    pub fn set(&mut self, bookmark: Bookmark) {
+        self.0.update(
+            |items| match items.iter_mut().find(|b| b.name == bookmark.name) {
+                Some(existing) => *existing = bookmark,
+                None => items.push(bookmark),
+            },
+        );
+    }
+
+    /// Deletes a bookmark, returning whether it existed.
+    pub fn delete(&mut self, name: &str) -> bool {
+        if self.get(name).is_none() {
+            return false;
+        }
+        self.0
+            .update(|items| items.retain(|bookmark| bookmark.name != name));
+        true
+    }
+}
+
+/// The default path of the bookmarks of a project.
+pub fn bookmarks_path(root: &Path) -> Option<PathBuf> {
+    Some(
+        dirs::data_dir()?
+            .join("typst-preview")
+            .join(project_id(root))
+            .join("bookmarks.json"),
+    )
+}
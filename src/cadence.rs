@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+/// Tracks the intervals between edits to decide how long to wait for more
+/// edits before compiling.
+///
+/// While the user types continuously the wait is stretched along with the
+/// typing interval, up to a limit, and an edit after a pause is compiled
+/// immediately.
+#[derive(Debug)]
+pub struct TypingCadence {
+    max_delay: Duration,
+    last_edit: Option<Instant>,
+    /// The moving average of the intervals between continuous edits.
+    interval: Option<Duration>,
+}
+
+impl TypingCadence {
+    /// Creates a cadence waiting at most `max_delay`, where zero compiles
+    /// every edit immediately.
+    pub fn new(max_delay: Duration) -> Self {
+        Self {
+            max_delay,
+            last_edit: None,
+            interval: None,
+        }
+    }
+
+    /// Records an edit, returning how long to wait for the next edit before
+    /// compiling.
+    pub fn edit(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = self.last_edit.replace(now).map(|last| now - last);
+
+        // edits further apart than the longest wait are not continuous
+        let Some(elapsed) = elapsed.filter(|elapsed| *elapsed < self.max_delay) else {
+            self.interval = None;
+            return Duration::ZERO;
+        };
+        let interval = match self.interval {
+            Some(interval) => (interval * 3 + elapsed) / 4,
+            None => elapsed,
+        };
+        self.interval = Some(interval);
+
+        (interval * 2).min(self.max_delay)
+    }
+}
@@ -0,0 +1,76 @@
+use std::ops::Range;
+use std::sync::{Arc, RwLock};
+
+use once_cell::sync::Lazy;
+use typst::syntax::{LinkedNode, Source, Span};
+
+/// Checks the sources of the project beyond what the compiler reports, e.g.
+/// a spell checker or a custom lint.
+///
+/// Checkers run on the compiler thread after each compilation, on the
+/// sources of the project read by the compilation, so they should be quick.
+pub trait Checker: Send + Sync {
+    /// The name shown as the source of the findings.
+    fn name(&self) -> &str;
+
+    fn check(&self, source: &Source) -> Vec<Finding>;
+}
+
+/// A problem found by a checker.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    /// The byte range of the problem in the source.
+    pub range: Range<usize>,
+    pub message: String,
+    pub hints: Vec<String>,
+}
+
+/// A finding located in a source.
+#[derive(Debug, Clone)]
+pub(crate) struct SourceFinding {
+    pub checker: String,
+    /// The span of the innermost syntax node at the start of the finding, to
+    /// show the finding where the node is rendered.
+    pub span: Span,
+    pub finding: Finding,
+}
+
+static CHECKERS: Lazy<RwLock<Vec<Arc<dyn Checker>>>> = Lazy::new(Default::default);
+
+/// Registers a checker, which runs from the next compilation on.
+pub fn register_checker(checker: Arc<dyn Checker>) {
+    CHECKERS.write().unwrap().push(checker);
+}
+
+/// Runs the registered checkers on the sources, dropping the findings out of
+/// the bounds of their source.
+pub(crate) fn check(sources: &[Source]) -> Vec<SourceFinding> {
+    let checkers = CHECKERS.read().unwrap().clone();
+    if checkers.is_empty() {
+        return vec![];
+    }
+
+    let mut findings = vec![];
+    for source in sources {
+        let root = LinkedNode::new(source.root());
+        for checker in &checkers {
+            for finding in checker.check(source) {
+                let range = &finding.range;
+                if range.start > range.end || range.end > source.len_bytes() {
+                    continue;
+                }
+                // the leaf ending at the start of the range is skipped, as
+                // the finding starts in the next one
+                let Some(leaf) = root.leaf_at(range.start + 1) else {
+                    continue;
+                };
+                findings.push(SourceFinding {
+                    checker: checker.name().to_owned(),
+                    span: leaf.span(),
+                    finding,
+                });
+            }
+        }
+    }
+    findings
+}
@@ -0,0 +1,269 @@
+//! A client of the control plane, for Rust tools driving a running preview
+//! like an editor does, e.g. a terminal editor.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue};
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::actor::editor::DiagnosticList;
+pub use crate::actor::editor::{CompileStatus, CompileStatusEvent};
+pub use crate::diagnostics::{Diagnostic, DiagnosticSeverity, FileDiagnostics, QuickFix};
+pub use crate::messages::{Localized, StatusMessage};
+use crate::{ChannelKind, ChannelProtocol, DocToSrcJumpInfo, EventKind};
+
+type Stream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug)]
+pub enum ClientError {
+    WebSocket(tungstenite::Error),
+    /// A message of the server couldn't be decoded.
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WebSocket(err) => write!(f, "websocket error: {err}"),
+            Self::Json(err) => write!(f, "invalid message: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<tungstenite::Error> for ClientError {
+    fn from(err: tungstenite::Error) -> Self {
+        Self::WebSocket(err)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+/// Connects to the control plane of a preview, e.g. at `127.0.0.1:23626`,
+/// subscribing to the given kinds of events, or to all of them but the
+/// thumbnails if none is given.
+pub async fn connect(
+    addr: &str,
+    subscriptions: &[EventKind],
+) -> Result<(PreviewClient, Events), ClientError> {
+    let mut url = format!("ws://{addr}/");
+    if !subscriptions.is_empty() {
+        let kinds = subscriptions
+            .iter()
+            .map(EventKind::as_str)
+            .collect::<Vec<_>>();
+        url.push_str(&format!("?subscribe={}", kinds.join(",")));
+    }
+    let mut request = url.into_client_request()?;
+    let protocol = ChannelProtocol::fallback(ChannelKind::Editor).to_string();
+    request.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_str(&protocol).unwrap(),
+    );
+
+    let (stream, _) = tokio_tungstenite::connect_async(request).await?;
+    let (sink, stream) = stream.split();
+    Ok((
+        PreviewClient {
+            sink: Mutex::new(sink),
+        },
+        Events { stream },
+    ))
+}
+
+/// Sends requests to a preview. Responses come back as [`Events`].
+pub struct PreviewClient {
+    sink: Mutex<SplitSink<Stream, Message>>,
+}
+
+impl PreviewClient {
+    pub async fn send(&self, request: &Request) -> Result<(), ClientError> {
+        self.send_json(serde_json::to_string(request)?).await
+    }
+
+    /// Sends a request given as JSON, e.g. one without a [`Request`] variant.
+    pub async fn send_json(&self, json: String) -> Result<(), ClientError> {
+        self.sink.lock().await.send(Message::Text(json)).await?;
+        Ok(())
+    }
+
+    /// Replaces all of the memory files by the given ones.
+    pub async fn sync_memory_files(
+        &self,
+        files: HashMap<PathBuf, String>,
+    ) -> Result<(), ClientError> {
+        self.send(&Request::SyncMemoryFiles { files }).await
+    }
+
+    /// Updates the content of edited files, which shadows the files on disk.
+    pub async fn update_memory_files(
+        &self,
+        files: HashMap<PathBuf, String>,
+    ) -> Result<(), ClientError> {
+        self.send(&Request::UpdateMemoryFiles { files }).await
+    }
+
+    /// Goes back to the files on disk, e.g. once they are saved.
+    pub async fn remove_memory_files(&self, files: Vec<PathBuf>) -> Result<(), ClientError> {
+        self.send(&Request::RemoveMemoryFiles { files }).await
+    }
+
+    /// Scrolls the webviews to a 0-based source position.
+    pub async fn scroll_to(
+        &self,
+        filepath: PathBuf,
+        line: usize,
+        character: usize,
+    ) -> Result<(), ClientError> {
+        self.send(&Request::PanelScrollTo {
+            filepath,
+            line,
+            character,
+        })
+        .await
+    }
+
+    pub async fn resync(&self) -> Result<(), ClientError> {
+        self.send(&Request::Resync).await
+    }
+
+    /// Closes the connection, which ends the [`Events`] as well.
+    pub async fn close(&self) -> Result<(), ClientError> {
+        self.sink.lock().await.close().await?;
+        Ok(())
+    }
+}
+
+/// The requests of the control plane. See `docs/editor.typ` for their
+/// meaning.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event")]
+pub enum Request {
+    #[serde(rename = "syncMemoryFiles")]
+    SyncMemoryFiles { files: HashMap<PathBuf, String> },
+    #[serde(rename = "updateMemoryFiles")]
+    UpdateMemoryFiles { files: HashMap<PathBuf, String> },
+    #[serde(rename = "removeMemoryFiles")]
+    RemoveMemoryFiles { files: Vec<PathBuf> },
+    #[serde(rename = "setInputs")]
+    SetInputs { inputs: HashMap<String, String> },
+    #[serde(rename = "panelScrollTo")]
+    PanelScrollTo {
+        filepath: PathBuf,
+        line: usize,
+        character: usize,
+    },
+    #[serde(rename = "changeCursorPosition")]
+    ChangeCursorPosition {
+        filepath: PathBuf,
+        line: usize,
+        character: usize,
+    },
+    #[serde(rename = "queryStructure")]
+    QueryStructure,
+    #[serde(rename = "queryBreadcrumbs")]
+    QueryBreadcrumbs {
+        filepath: PathBuf,
+        line: usize,
+        character: usize,
+    },
+    #[serde(rename = "queryDependencies")]
+    QueryDependencies,
+    #[serde(rename = "setProfile")]
+    SetProfile { name: Option<String> },
+    #[serde(rename = "listProfiles")]
+    ListProfiles,
+    #[serde(rename = "navigateBack")]
+    NavigateBack,
+    #[serde(rename = "navigateForward")]
+    NavigateForward,
+    #[serde(rename = "resync")]
+    Resync,
+}
+
+/// The events sent by a preview, where the ones without a dedicated variant
+/// are given as JSON.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Asks the editor to reveal the source of an element clicked in a
+    /// webview.
+    EditorScrollTo(DocToSrcJumpInfo),
+    /// Asks the editor to send its memory files with
+    /// [`PreviewClient::sync_memory_files`].
+    SyncEditorChanges,
+    /// The status of the latest compilation, with its message in the
+    /// locale of the preview.
+    CompileStatus(Localized<CompileStatusEvent>),
+    /// The diagnostics of the latest compilation, replacing the previous
+    /// ones.
+    Diagnostics { files: Vec<FileDiagnostics> },
+    Other {
+        event: String,
+        payload: serde_json::Value,
+    },
+}
+
+impl Event {
+    fn parse(text: &str) -> Result<Self, serde_json::Error> {
+        let payload = serde_json::from_str::<serde_json::Value>(text)?;
+        let event = payload
+            .get("event")
+            .and_then(|event| event.as_str())
+            .unwrap_or_default()
+            .to_owned();
+        Ok(match event.as_str() {
+            "editorScrollTo" => Self::EditorScrollTo(serde_json::from_value(payload)?),
+            "syncEditorChanges" => Self::SyncEditorChanges,
+            "compileStatus" => Self::CompileStatus(serde_json::from_value(payload)?),
+            "diagnostics" => {
+                let list = serde_json::from_value::<DiagnosticList>(payload)?;
+                Self::Diagnostics { files: list.files }
+            }
+            _ => Self::Other { event, payload },
+        })
+    }
+}
+
+/// The events sent by a preview.
+pub struct Events {
+    stream: SplitStream<Stream>,
+}
+
+impl Events {
+    /// Waits for the next event, which is `None` once the connection is
+    /// closed.
+    pub async fn next(&mut self) -> Option<Result<Event, ClientError>> {
+        let text = match self.next_json().await? {
+            Ok(text) => text,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(Event::parse(&text).map_err(From::from))
+    }
+
+    /// Waits for the next event as JSON.
+    pub async fn next_json(&mut self) -> Option<Result<String, ClientError>> {
+        loop {
+            match self.stream.next().await? {
+                Ok(Message::Text(text)) => return Some(Ok(text)),
+                Ok(Message::Close(_)) => return None,
+                // pings are answered by the stream itself
+                Ok(_) => continue,
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use typst_ts_core::debug_loc::DocumentPosition;
+
+use crate::json_list::JsonList;
+
+/// The source location a comment is anchored to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommentAnchor {
+    pub filepath: String,
+    /// 0-based line.
+    pub line: usize,
+    /// 0-based character.
+    pub character: usize,
+}
+
+/// A review comment anchored to a source location.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Comment {
+    pub id: String,
+    pub text: String,
+    pub author: Option<String>,
+    /// Seconds since the unix epoch.
+    pub created: u64,
+    pub anchor: CommentAnchor,
+    /// The position of the anchor in the latest document, if it is rendered.
+    pub position: Option<DocumentPosition>,
+}
+
+/// A comment to create, anchored by a span id, a source location or a
+/// document position, in that order of preference.
+#[derive(Debug, Deserialize)]
+pub struct NewComment {
+    pub text: String,
+    #[serde(default)]
+    pub author: Option<String>,
+    /// Span id in hex-format.
+    #[serde(default)]
+    pub span: Option<String>,
+    #[serde(default)]
+    pub anchor: Option<CommentAnchor>,
+    #[serde(default)]
+    pub position: Option<DocumentPosition>,
+}
+
+impl NewComment {
+    pub fn into_comment(self, anchor: CommentAnchor) -> Comment {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Comment {
+            id: format!("{:x}", now.as_nanos()),
+            text: self.text,
+            author: self.author,
+            created: now.as_secs(),
+            anchor,
+            position: self.position,
+        }
+    }
+}
+
+/// The comments of a project, persisted in the project.
+#[derive(Debug)]
+pub struct Comments(JsonList<Comment>);
+
+impl Default for Comments {
+    fn default() -> Self {
+        Self(JsonList::new("comments"))
+    }
+}
+
+impl Comments {
+    /// Loads the comments saved at `path`, if any.
+    pub fn load(path: PathBuf) -> Self {
+        Self(JsonList::load("comments", path))
+    }
+
+    pub fn items(&self) -> &[Comment] {
+        self.0.items()
+    }
+
+    /// The comments, to move their positions, which are saved with the next
+    /// change.
+    pub fn items_mut(&mut self) -> &mut [Comment] {
+        self.0.items_mut()
+    }
+
+    pub fn add(&mut self, comment: Comment) {
+        self.0.update(|items| items.push(comment));
+    }
+
+    /// Deletes a comment, returning whether it existed.
+    pub fn delete(&mut self, id: &str) -> bool {
+        if !self.items().iter().any(|comment| comment.id == id) {
+            return false;
+        }
+        self.0
+            .update(|items| items.retain(|comment| comment.id != id));
+        true
+    }
+}
+
+/// The path of the comments of a project, which lives in the project so that
+/// it can be shared with the sources.
+pub fn comments_path(root: &Path) -> PathBuf {
+    root.join(".typst-preview").join("comments.json")
+}
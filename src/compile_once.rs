@@ -0,0 +1,55 @@
+use std::io::Read;
+
+use log::error;
+use typst_ts_compiler::service::{CompileDriver, CompileEnv, Compiler};
+
+use crate::diagnostics::file_diagnostics;
+use crate::export::export_document;
+use crate::{CompileOnceArgs, MemoryFiles};
+
+/// Compiles the document once in the world of the preview, writing the
+/// exports requested by `args` and printing the diagnostics to stderr.
+///
+/// Returns whether the compilation succeeded.
+pub fn compile_once(mut compiler_driver: CompileDriver, args: &CompileOnceArgs) -> bool {
+    if args.memory_files_stdin {
+        let mut input = String::new();
+        let files = std::io::stdin()
+            .read_to_string(&mut input)
+            .map_err(|err| err.to_string())
+            .and_then(|_| {
+                serde_json::from_str::<MemoryFiles>(&input).map_err(|err| err.to_string())
+            });
+        let files = match files {
+            Ok(files) => files,
+            Err(err) => {
+                error!("failed to read memory files from stdin: {}", err);
+                return false;
+            }
+        };
+        let cwd = std::env::current_dir().unwrap();
+        for (path, content) in files.files {
+            let path = cwd.join(path);
+            if let Err(err) = compiler_driver
+                .world
+                .map_shadow(&path, content.as_bytes().into())
+            {
+                error!("failed to shadow {:?}: {:?}", path, err);
+                return false;
+            }
+        }
+    }
+
+    let root = compiler_driver.world.root.clone();
+    let document = match compiler_driver.compile(&mut CompileEnv::default()) {
+        Ok(document) => document,
+        Err(errors) => {
+            for file in file_diagnostics(&compiler_driver.world, &root, &errors) {
+                eprint!("{file}");
+            }
+            return false;
+        }
+    };
+
+    export_document(&compiler_driver.world, &document, &args.export)
+}
@@ -0,0 +1,134 @@
+use std::path::Path;
+use std::time::Duration;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::overlay::Overlays;
+
+/// The name of the configuration file looked up in the root of the project.
+pub const CONFIG_FILE_NAME: &str = "typst-preview.json";
+
+/// The configuration file of a project.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Config {
+    /// The profile to start with, unless one is given on the command line.
+    #[serde(default)]
+    pub default_profile: Option<String>,
+    /// Named sets of settings which can be switched between at runtime.
+    #[serde(default)]
+    pub profiles: IndexMap<String, Profile>,
+}
+
+/// The settings changed by a profile, where unset ones are taken from the
+/// command line.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    /// The longest wait in milliseconds for more edits before compiling.
+    pub max_compile_delay: Option<u64>,
+    /// Whether to show compile errors as placeholders in the preview.
+    pub error_placeholders: Option<bool>,
+    /// The layout overlays shown in the webviews.
+    pub overlays: Option<Overlays>,
+}
+
+/// The settings of the preview which profiles can change.
+#[derive(Debug, Clone, Copy)]
+pub struct ProfileSettings {
+    pub max_compile_delay: Duration,
+    pub error_placeholders: bool,
+    pub overlays: Overlays,
+}
+
+impl Profile {
+    pub fn apply(&self, base: &ProfileSettings) -> ProfileSettings {
+        ProfileSettings {
+            max_compile_delay: self
+                .max_compile_delay
+                .map_or(base.max_compile_delay, Duration::from_millis),
+            error_placeholders: self.error_placeholders.unwrap_or(base.error_placeholders),
+            overlays: self.overlays.unwrap_or(base.overlays),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from `path`, or else from the configuration
+    /// file in `root` if there is one.
+    pub fn load(path: Option<&Path>, root: &Path) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => {
+                let path = root.join(CONFIG_FILE_NAME);
+                if !path.exists() {
+                    return Ok(Self::default());
+                }
+                path
+            }
+        };
+        let data = std::fs::read(&path).map_err(|err| format!("failed to read {path:?}: {err}"))?;
+        serde_json::from_slice(&data).map_err(|err| format!("invalid config {path:?}: {err}"))
+    }
+}
+
+/// The profiles of the project and the settings of the command line, to
+/// switch profiles at runtime.
+#[derive(Debug, Clone)]
+pub struct Profiles {
+    profiles: IndexMap<String, Profile>,
+    base: ProfileSettings,
+    active: Option<String>,
+}
+
+/// The names of the profiles and the active one, as reported to the editor.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileList {
+    items: Vec<String>,
+    active: Option<String>,
+}
+
+impl Profiles {
+    pub fn new(config: Config, base: ProfileSettings) -> Self {
+        Self {
+            profiles: config.profiles,
+            base,
+            active: None,
+        }
+    }
+
+    /// Activates the profile `name`, or the settings of the command line if
+    /// `None`, returning the resulting settings.
+    pub fn activate(&mut self, name: Option<&str>) -> Result<ProfileSettings, String> {
+        let Some(name) = name else {
+            self.active = None;
+            return Ok(self.base);
+        };
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| format!("unknown profile: {name}"))?;
+        self.active = Some(name.to_owned());
+        Ok(profile.apply(&self.base))
+    }
+
+    /// The settings of the active profile.
+    pub fn settings(&self) -> ProfileSettings {
+        match self
+            .active
+            .as_ref()
+            .and_then(|name| self.profiles.get(name))
+        {
+            Some(profile) => profile.apply(&self.base),
+            None => self.base,
+        }
+    }
+
+    pub fn list(&self) -> ProfileList {
+        ProfileList {
+            items: self.profiles.keys().cloned().collect(),
+            active: self.active.clone(),
+        }
+    }
+}
@@ -1,7 +1,14 @@
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
 use std::{ops::DerefMut, sync::Arc};
 
 use indexmap::IndexSet;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use siphasher::sip::SipHasher;
 use tokio::sync::RwLock;
+use typst::syntax::{FileId, PackageSpec, Span, VirtualPath};
 use typst_ts_core::debug_loc::SourceSpan;
 
 #[derive(Debug)]
@@ -38,8 +45,9 @@ impl InternId {
         format!("{:x}", self.to_u64())
     }
 
-    pub fn from_hex(hex: &str) -> Self {
-        Self::from_u64(u64::from_str_radix(hex, 16).unwrap())
+    /// Parses an id given by an editor, which may be malformed.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        u64::from_str_radix(hex, 16).ok().map(Self::from_u64)
     }
 }
 
@@ -50,6 +58,8 @@ impl InternId {
 #[derive(Clone, Default)]
 pub struct SpanInterner {
     inner: Arc<RwLock<SpanInternerImpl>>,
+    /// Where the interned spans are persisted across restarts.
+    cache_path: Option<Arc<Path>>,
 }
 
 impl SpanInterner {
@@ -57,6 +67,42 @@ impl SpanInterner {
         Self::default()
     }
 
+    /// Creates an interner persisted at `cache_path`, restoring the spans
+    /// saved by a previous run, so that span ids held by editors survive a
+    /// restart.
+    pub fn with_cache(cache_path: PathBuf) -> Self {
+        let inner = std::fs::read(&cache_path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<PersistedInterner>(&data).ok())
+            .and_then(SpanInternerImpl::restore)
+            .unwrap_or_default();
+        info!(
+            "SpanInterner: restored {} spans from {:?}",
+            inner.span2id.len(),
+            cache_path
+        );
+
+        Self {
+            inner: Arc::new(RwLock::new(inner)),
+            cache_path: Some(cache_path.into()),
+        }
+    }
+
+    /// Saves the interned spans if the interner is persisted.
+    pub async fn save(&self) {
+        let Some(cache_path) = &self.cache_path else {
+            return;
+        };
+        let data = serde_json::to_vec(&self.inner.read().await.persist()).unwrap();
+        let res = cache_path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(cache_path, data));
+        if let Err(err) = res {
+            warn!("SpanInterner: failed to save to {:?}: {}", cache_path, err);
+        }
+    }
+
     #[allow(unused)]
     pub async fn reset(&self) {
         self.inner.write().await.reset();
@@ -66,6 +112,12 @@ impl SpanInterner {
         self.inner.read().await.span_by_str(str)
     }
 
+    /// Looks up many span ids at once, holding the lock only once.
+    pub async fn spans_by_str(&self, strs: &[String]) -> Vec<InternQuery<SourceSpan>> {
+        let inner = self.inner.read().await;
+        strs.iter().map(|s| inner.span_by_str(s)).collect()
+    }
+
     #[allow(unused)]
     pub async fn span(&self, id: InternId) -> InternQuery<SourceSpan> {
         self.inner.read().await.span(id)
@@ -87,6 +139,8 @@ impl SpanInterner {
 pub struct SpanInternerImpl {
     lifetime: usize,
     span2id: IndexSet<(usize, SourceSpan)>,
+    /// The number of spans interned in the current lifetime.
+    interned: usize,
 }
 
 impl Default for SpanInternerImpl {
@@ -97,22 +151,76 @@ impl Default for SpanInternerImpl {
 
 const GARAGE_COLLECT_THRESHOLD: usize = 30;
 
+/// The number of spans interned before a new lifetime is started.
+const SPANS_PER_LIFETIME: usize = 4096;
+
+/// The default cache path of the interned spans for a project.
+pub fn span_cache_path(root: &Path) -> Option<PathBuf> {
+    Some(
+        dirs::cache_dir()?
+            .join("typst-preview")
+            .join(project_id(root))
+            .join("spans.json"),
+    )
+}
+
+/// A stable identifier of the project at `root`, used to name the
+/// directories keeping its state.
+pub fn project_id(root: &Path) -> String {
+    let mut hasher = SipHasher::new();
+    root.hash(&mut hasher);
+    format!("{:x}", hasher.finish())
+}
+
+/// A span identified by its file path instead of its file id, which is only
+/// valid in the current process.
+#[derive(Serialize, Deserialize)]
+struct PersistedSpan {
+    lifetime: usize,
+    package: Option<String>,
+    path: PathBuf,
+    number: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedInterner {
+    lifetime: usize,
+    spans: Vec<PersistedSpan>,
+}
+
+/// The low bits of a span are its number in the file, see [`Span`].
+const SPAN_NUMBER_BITS: u32 = 48;
+
 impl SpanInternerImpl {
     pub fn new() -> Self {
         Self {
             lifetime: 1,
             span2id: IndexSet::new(),
+            interned: 0,
         }
     }
 
     pub fn reset(&mut self) {
         self.lifetime += 1;
+        self.interned = 0;
         self.span2id
             .retain(|(id, _)| self.lifetime - id < GARAGE_COLLECT_THRESHOLD);
     }
 
+    /// Starts a new lifetime once many spans are interned in the current one.
+    /// Until then, interning a span again gives the same id, so the ids of
+    /// unchanged spans stay the same across compilations.
+    pub fn reset_if_full(&mut self) {
+        if self.interned >= SPANS_PER_LIFETIME {
+            self.reset();
+        }
+    }
+
     pub fn span_by_str(&self, str: &str) -> InternQuery<SourceSpan> {
-        self.span(InternId::from_hex(str))
+        match InternId::from_hex(str) {
+            Some(id) => self.span(id),
+            None => InternQuery::Ok(None),
+        }
     }
 
     pub fn span(&self, id: InternId) -> InternQuery<SourceSpan> {
@@ -130,9 +238,63 @@ impl SpanInternerImpl {
 
     pub fn intern(&mut self, span: SourceSpan) -> InternId {
         let item = (self.lifetime, span);
-        let (idx, _) = self.span2id.insert_full(item);
+        let (idx, inserted) = self.span2id.insert_full(item);
+        self.interned += usize::from(inserted);
         // combine lifetime
 
         InternId::new(self.lifetime, idx)
     }
+
+    fn persist(&self) -> PersistedInterner {
+        let spans = self
+            .span2id
+            .iter()
+            .map(|(lifetime, span)| {
+                let id = span.id();
+                PersistedSpan {
+                    lifetime: *lifetime,
+                    package: id.and_then(|id| id.package()).map(ToString::to_string),
+                    path: id.map_or_else(PathBuf::new, |id| id.vpath().as_rooted_path().to_owned()),
+                    number: span.number(),
+                }
+            })
+            .collect();
+
+        PersistedInterner {
+            lifetime: self.lifetime,
+            spans,
+        }
+    }
+
+    fn restore(persisted: PersistedInterner) -> Option<Self> {
+        let mut span2id = IndexSet::with_capacity(persisted.spans.len());
+        for span in persisted.spans {
+            let restored = if span.path.as_os_str().is_empty() {
+                Span::detached()
+            } else {
+                let package = match span.package {
+                    Some(package) => Some(package.parse::<PackageSpec>().ok()?),
+                    None => None,
+                };
+                let id = FileId::new(package, VirtualPath::new(&span.path));
+                let raw = (id.into_raw() as u64) << SPAN_NUMBER_BITS | span.number;
+                Span::from_raw(NonZeroU64::new(raw)?)
+            };
+
+            // the index of a span is its intern id, so they must be kept
+            if !span2id.insert((span.lifetime, restored)) {
+                return None;
+            }
+        }
+
+        let interned = span2id
+            .iter()
+            .filter(|(lifetime, _)| *lifetime == persisted.lifetime)
+            .count();
+        Some(Self {
+            lifetime: persisted.lifetime,
+            span2id,
+            interned,
+        })
+    }
 }
@@ -0,0 +1,76 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use serde::Serialize;
+use typst::layout::{Frame, FrameItem};
+use typst_ts_core::TypstDocument;
+
+/// What the latest compilation depends on.
+#[derive(Debug, Clone, Serialize)]
+pub struct Dependencies {
+    /// The absolute paths of the files read by the compilation, i.e. sources,
+    /// images, data files and bibliographies, including memory-only files.
+    pub files: Vec<String>,
+    /// The families of the fonts used in the document. Fonts are loaded once
+    /// at startup, so they are reported by name rather than by file.
+    pub fonts: Vec<String>,
+}
+
+/// What the webviews show about the latest document.
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentInfo {
+    /// The families of the fonts used in the document.
+    pub fonts: Vec<String>,
+    /// The packages loaded by the compilation, e.g. `@preview/cetz:0.1.0`.
+    pub packages: Vec<String>,
+}
+
+/// Collect the packages the files read by a compilation belong to.
+pub fn loaded_packages<P: AsRef<Path>>(files: &[P]) -> Vec<String> {
+    // See https://github.com/typst/packages#local-packages
+    let package_dirs = [dirs::cache_dir(), dirs::data_dir()]
+        .into_iter()
+        .flatten()
+        .map(|base| base.join("typst/packages"))
+        .collect::<Vec<_>>();
+
+    let mut packages = BTreeSet::new();
+    for file in files {
+        let Some(relative) = package_dirs
+            .iter()
+            .find_map(|dir| file.as_ref().strip_prefix(dir).ok())
+        else {
+            continue;
+        };
+        let mut components = relative.iter().map(|c| c.to_string_lossy());
+        if let (Some(namespace), Some(name), Some(version)) =
+            (components.next(), components.next(), components.next())
+        {
+            packages.insert(format!("@{namespace}/{name}:{version}"));
+        }
+    }
+    packages.into_iter().collect()
+}
+
+/// Collect the families of the fonts used in the document.
+pub fn document_fonts(document: &TypstDocument) -> Vec<String> {
+    let mut fonts = BTreeSet::new();
+    for page in &document.pages {
+        frame_fonts(page, &mut fonts);
+    }
+    fonts.into_iter().collect()
+}
+
+fn frame_fonts(frame: &Frame, fonts: &mut BTreeSet<String>) {
+    for (_, item) in frame.items() {
+        match item {
+            FrameItem::Group(group) => frame_fonts(&group.frame, fonts),
+            FrameItem::Text(text) => {
+                if !fonts.contains(&text.font.info().family) {
+                    fonts.insert(text.font.info().family.clone());
+                }
+            }
+            FrameItem::Shape(..) | FrameItem::Image(..) | FrameItem::Meta(..) => {}
+        }
+    }
+}
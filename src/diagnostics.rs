@@ -0,0 +1,271 @@
+use std::fmt;
+use std::path::Path;
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use typst::diag::{Severity, SourceDiagnostic};
+use typst::syntax::{FileId, Source};
+use typst::World;
+
+use crate::actor::typst::resolve_file_path;
+use crate::checker::SourceFinding;
+use crate::snapshot::resolve_range;
+
+/// The diagnostics of a compilation in one file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileDiagnostics {
+    /// A stable identity of the file, which is its path relative to the root,
+    /// or to the package prefixed by the package, e.g.
+    /// `@preview/cetz:0.1.0/src/lib.typ`. It doesn't depend on whether the
+    /// file exists on disk, so memory-only files are identified as well.
+    pub id: String,
+    /// The path of the file relative to the root, or to the package.
+    pub relative_path: String,
+    /// The absolute path of the file, which is in the package cache for
+    /// package files.
+    pub path: Option<String>,
+    /// Whether the file is not part of the project, e.g. a package source.
+    pub readonly: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Prints the diagnostics in the format of compilers, one per line with
+/// 1-based positions.
+impl fmt::Display for FileDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path.as_deref().unwrap_or(&self.id);
+        for diag in &self.diagnostics {
+            let severity = match diag.severity {
+                DiagnosticSeverity::Error => "error",
+                DiagnosticSeverity::Warning => "warning",
+            };
+            match diag.start {
+                Some((line, column)) => writeln!(
+                    f,
+                    "{path}:{}:{}: {severity}: {}",
+                    line + 1,
+                    column + 1,
+                    diag.message
+                )?,
+                None => writeln!(f, "{path}: {severity}: {}", diag.message)?,
+            }
+            for hint in &diag.hints {
+                writeln!(f, "  hint: {hint}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+    pub hints: Vec<String>,
+    /// The 0-based line and column of the start of the range, if known.
+    pub start: Option<(usize, usize)>,
+    /// The 0-based line and column of the end of the range, if known.
+    pub end: Option<(usize, usize)>,
+    /// The name of the checker which reported the diagnostic, which is
+    /// absent for the diagnostics of the compiler.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// The edits suggested by the hints, which editors can offer as quick
+    /// fixes.
+    #[serde(default)]
+    pub fixes: Vec<QuickFix>,
+}
+
+/// An edit replacing the range of a diagnostic, e.g. the one suggested by
+/// the hint "try adding spaces around the minus sign: `a - b`".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuickFix {
+    /// The hint suggesting the edit.
+    pub title: String,
+    /// The 0-based line and column of the start of the replaced range.
+    pub start: (usize, usize),
+    /// The 0-based line and column of the end of the replaced range.
+    pub end: (usize, usize),
+    pub replacement: String,
+}
+
+/// Takes the edits suggested by hints which end with the code to write in
+/// place of the diagnosed `node`, e.g. "did you mean `#emph`?". Diagnostics
+/// without a range have none.
+fn quick_fixes(
+    hints: &[String],
+    node: &str,
+    start: Option<(usize, usize)>,
+    end: Option<(usize, usize)>,
+) -> Vec<QuickFix> {
+    let Some((start, end)) = start.zip(end) else {
+        return vec![];
+    };
+    if start == end {
+        return vec![];
+    }
+    hints
+        .iter()
+        .filter_map(|hint| {
+            Some(QuickFix {
+                title: hint.clone(),
+                start,
+                end,
+                replacement: suggested_replacement(hint, node)?.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Matches the hints of the compiler which suggest a replacement of the
+/// node, which are "did you mean `code`?" and "…, try adding …: `code`".
+/// Other hints quote code too, e.g. "`x` was defined here".
+fn suggested_replacement<'a>(hint: &'a str, node: &str) -> Option<&'a str> {
+    let hint = hint.trim_end_matches(['.', '?', '!']).strip_suffix('`')?;
+    let (prefix, code) = hint.rsplit_once('`')?;
+    if code.is_empty() || code == node || prefix.contains('`') {
+        return None;
+    }
+    let prefix = prefix.trim_end().to_lowercase();
+    // the last clause, e.g. "if you meant to use subtraction, try adding
+    // spaces around the minus sign:"
+    let clause = prefix.rsplit(',').next()?.trim_start();
+    let clause = clause.strip_prefix("or ").unwrap_or(clause);
+    if clause == "did you mean" {
+        return Some(code);
+    }
+    // the code rewrites the node, e.g. with spaces or in quotes, rather than
+    // being written somewhere else
+    let unspaced = |text: &str| text.split_whitespace().collect::<String>();
+    let rewrites = unspaced(code).contains(&unspaced(node));
+    let tries = clause.starts_with("try ") && clause.ends_with(':');
+    (tries && rewrites).then_some(code)
+}
+
+/// Group the diagnostics of a compilation by the files they are located in.
+///
+/// Diagnostics without a location are reported in the main file.
+pub fn file_diagnostics(
+    world: &dyn World,
+    root: &Path,
+    diagnostics: &[SourceDiagnostic],
+) -> Vec<FileDiagnostics> {
+    file_diagnostics_with_findings(world, root, diagnostics, &[])
+}
+
+/// Group the diagnostics of a compilation and the findings of the checkers
+/// by the files they are located in, reporting the findings as warnings
+/// after the diagnostics of the compiler.
+pub(crate) fn file_diagnostics_with_findings(
+    world: &dyn World,
+    root: &Path,
+    diagnostics: &[SourceDiagnostic],
+    findings: &[SourceFinding],
+) -> Vec<FileDiagnostics> {
+    let mut files = IndexMap::<FileId, FileDiagnostics>::new();
+    for diag in diagnostics {
+        let id = diag.span.id().unwrap_or_else(|| world.main().id());
+        let source = world.source(id).ok();
+        let (start, end) = source
+            .as_ref()
+            .and_then(|source| resolve_range(source, diag.span, None))
+            .unwrap_or_default();
+        let node = source
+            .as_ref()
+            .and_then(|source| source.text().get(source.range(diag.span)?));
+
+        let hints = diag
+            .hints
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        file_entry(&mut files, root, id)
+            .diagnostics
+            .push(Diagnostic {
+                severity: match diag.severity {
+                    Severity::Error => DiagnosticSeverity::Error,
+                    Severity::Warning => DiagnosticSeverity::Warning,
+                },
+                message: diag.message.to_string(),
+                fixes: node.map_or_else(Vec::new, |node| quick_fixes(&hints, node, start, end)),
+                hints,
+                start,
+                end,
+                source: None,
+            });
+    }
+
+    for SourceFinding {
+        checker,
+        span,
+        finding,
+    } in findings
+    {
+        let Some(id) = span.id() else {
+            continue;
+        };
+        // findings have a precise range, unlike the span of the node
+        let line_column = |source: &Source, offset: usize| {
+            Some((source.byte_to_line(offset)?, source.byte_to_column(offset)?))
+        };
+        let source = world.source(id).ok();
+        let (start, end) = source
+            .as_ref()
+            .map(|source| {
+                (
+                    line_column(source, finding.range.start),
+                    line_column(source, finding.range.end),
+                )
+            })
+            .unwrap_or_default();
+        let node = source
+            .as_ref()
+            .and_then(|source| source.text().get(finding.range.clone()));
+
+        file_entry(&mut files, root, id)
+            .diagnostics
+            .push(Diagnostic {
+                severity: DiagnosticSeverity::Warning,
+                message: finding.message.clone(),
+                hints: finding.hints.clone(),
+                start,
+                end,
+                source: Some(checker.clone()),
+                fixes: node.map_or_else(Vec::new, |node| {
+                    quick_fixes(&finding.hints, node, start, end)
+                }),
+            });
+    }
+
+    files.into_values().collect()
+}
+
+fn file_entry<'a>(
+    files: &'a mut IndexMap<FileId, FileDiagnostics>,
+    root: &Path,
+    id: FileId,
+) -> &'a mut FileDiagnostics {
+    files.entry(id).or_insert_with(|| {
+        let relative_path = id.vpath().as_rootless_path().to_string_lossy().to_string();
+        let resolved = resolve_file_path(root, id);
+        FileDiagnostics {
+            id: match id.package() {
+                Some(package) => format!("{package}/{relative_path}"),
+                None => relative_path.clone(),
+            },
+            relative_path,
+            path: resolved
+                .as_ref()
+                .map(|(path, _)| path.to_string_lossy().to_string()),
+            readonly: resolved.map_or(false, |(_, readonly)| readonly),
+            diagnostics: vec![],
+        }
+    })
+}
@@ -0,0 +1,232 @@
+use std::fmt;
+use std::sync::Arc;
+
+use comemo::Prehashed;
+use serde::Serialize;
+use typst::foundations::Dict;
+use typst::layout::{Frame, FrameItem, Point, Transform};
+use typst::model::Document;
+use typst_ts_compiler::service::{CompileDriver, CompileEnv, Compiler};
+
+use crate::diagnostics::file_diagnostics;
+use crate::renderer::page_hash;
+use crate::{typst_inputs, DiffArgs};
+
+/// The differences between the documents compiled with two input sets.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffReport {
+    left_pages: usize,
+    right_pages: usize,
+    /// The pages differing in either document, by page number.
+    pages: Vec<PageDiff>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PageChange {
+    /// The page is rendered differently, but its text is the same.
+    Visual,
+    /// The text of the page changed.
+    Text,
+    /// The page only exists with the right inputs.
+    Added,
+    /// The page only exists with the left inputs.
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PageDiff {
+    /// The 1-based page number.
+    page: usize,
+    change: PageChange,
+    /// The lines of text only on the left page.
+    removed: Vec<String>,
+    /// The lines of text only on the right page.
+    added: Vec<String>,
+}
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.pages.is_empty() {
+            return writeln!(f, "no differences in {} pages", self.left_pages);
+        }
+        if self.left_pages != self.right_pages {
+            writeln!(f, "pages: {} -> {}", self.left_pages, self.right_pages)?;
+        }
+        for page in &self.pages {
+            let change = match page.change {
+                PageChange::Visual => "visual change only",
+                PageChange::Text => "text changed",
+                PageChange::Added => "added",
+                PageChange::Removed => "removed",
+            };
+            writeln!(f, "page {}: {change}", page.page)?;
+            for line in &page.removed {
+                writeln!(f, "  - {line}")?;
+            }
+            for line in &page.added {
+                writeln!(f, "  + {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compiles the document with the left and the right inputs, each added to
+/// the inputs of the world, and reports the pages which differ.
+///
+/// Returns `None` if either compilation fails, after printing the
+/// diagnostics to stderr.
+pub fn diff_inputs(mut compiler_driver: CompileDriver, args: &DiffArgs) -> Option<DiffReport> {
+    let left = compile_with(&mut compiler_driver, args, &args.left)?;
+    let right = compile_with(&mut compiler_driver, args, &args.right)?;
+    Some(diff_documents(&left, &right))
+}
+
+fn compile_with(
+    compiler_driver: &mut CompileDriver,
+    args: &DiffArgs,
+    inputs: &[(String, String)],
+) -> Option<Arc<Document>> {
+    let mut dict = typst_inputs(args.world.inputs.clone());
+    let overrides: Dict = typst_inputs(inputs.iter().cloned());
+    for (key, value) in overrides {
+        dict.insert(key, value);
+    }
+    compiler_driver
+        .world
+        .set_inputs(Arc::new(Prehashed::new(dict)));
+
+    let root = compiler_driver.world.root.clone();
+    match compiler_driver.compile(&mut CompileEnv::default()) {
+        Ok(document) => Some(document),
+        Err(errors) => {
+            for file in file_diagnostics(&compiler_driver.world, &root, &errors) {
+                eprint!("{file}");
+            }
+            None
+        }
+    }
+}
+
+/// Compares the documents page by page, first by content hash and then by
+/// the text of the differing pages.
+pub fn diff_documents(left: &Document, right: &Document) -> DiffReport {
+    let mut pages = vec![];
+    for idx in 0..left.pages.len().max(right.pages.len()) {
+        let page = idx + 1;
+        match (left.pages.get(idx), right.pages.get(idx)) {
+            (Some(left), Some(right)) => {
+                if page_hash(left) == page_hash(right) {
+                    continue;
+                }
+                let (removed, added) = diff_lines(&page_lines(left), &page_lines(right));
+                let change = if removed.is_empty() && added.is_empty() {
+                    PageChange::Visual
+                } else {
+                    PageChange::Text
+                };
+                pages.push(PageDiff {
+                    page,
+                    change,
+                    removed,
+                    added,
+                });
+            }
+            (Some(left), None) => pages.push(PageDiff {
+                page,
+                change: PageChange::Removed,
+                removed: page_lines(left),
+                added: vec![],
+            }),
+            (None, Some(right)) => pages.push(PageDiff {
+                page,
+                change: PageChange::Added,
+                removed: vec![],
+                added: page_lines(right),
+            }),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    DiffReport {
+        left_pages: left.pages.len(),
+        right_pages: right.pages.len(),
+        pages,
+    }
+}
+
+/// Extracts the text of a page as lines, joining the text items sharing a
+/// baseline from left to right.
+fn page_lines(page: &Frame) -> Vec<String> {
+    let mut texts = vec![];
+    collect_texts(page, Transform::identity(), &mut texts);
+    texts.sort_by(|(a, _), (b, _)| (a.y, a.x).partial_cmp(&(b.y, b.x)).unwrap());
+
+    let mut lines: Vec<String> = vec![];
+    let mut baseline = None;
+    for (pos, text) in texts {
+        // text items of a line may be shifted a bit, e.g. for sub- and
+        // superscripts
+        match (baseline, lines.last_mut()) {
+            (Some(y), Some(line)) if (pos.y - y).abs().to_pt() < 2.0 => {
+                line.push(' ');
+                line.push_str(&text);
+            }
+            _ => {
+                baseline = Some(pos.y);
+                lines.push(text);
+            }
+        }
+    }
+    lines
+}
+
+fn collect_texts(frame: &Frame, ts: Transform, texts: &mut Vec<(Point, String)>) {
+    for (pos, item) in frame.items() {
+        let ts = ts.pre_concat(Transform::translate(pos.x, pos.y));
+        match item {
+            FrameItem::Group(group) => {
+                collect_texts(&group.frame, ts.pre_concat(group.transform), texts)
+            }
+            FrameItem::Text(text) => {
+                texts.push((Point::zero().transform(ts), text.text.to_string()));
+            }
+            FrameItem::Shape(..) | FrameItem::Image(..) | FrameItem::Meta(..) => {}
+        }
+    }
+}
+
+/// Finds the lines removed from and added to `left` by a longest common
+/// subsequence of the lines.
+fn diff_lines(left: &[String], right: &[String]) -> (Vec<String>, Vec<String>) {
+    let (n, m) = (left.len(), right.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if left[i] == right[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut removed, mut added) = (vec![], vec![]);
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if left[i] == right[j] {
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            removed.push(left[i].clone());
+            i += 1;
+        } else {
+            added.push(right[j].clone());
+            j += 1;
+        }
+    }
+    removed.extend(left[i..].iter().cloned());
+    added.extend(right[j..].iter().cloned());
+    (removed, added)
+}
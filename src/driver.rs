@@ -0,0 +1,77 @@
+use std::borrow::Cow;
+
+use typst_ts_compiler::service::CompileDriver;
+use typst_ts_compiler::TypstSystemWorld;
+use typst_ts_core::config::CompileOpts;
+
+use crate::{typst_inputs, WorldArgs};
+
+pub static EMBEDDED_FONT: &[Cow<'_, [u8]>] = &[
+    // Embed default fonts.
+    Cow::Borrowed(include_bytes!("../assets/fonts/LinLibertine_R.ttf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/LinLibertine_RB.ttf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/LinLibertine_RBI.ttf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/LinLibertine_RI.ttf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/NewCMMath-Book.otf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/NewCMMath-Regular.otf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/NewCM10-Regular.otf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/NewCM10-Bold.otf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/NewCM10-Italic.otf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/NewCM10-BoldItalic.otf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/DejaVuSansMono.ttf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/DejaVuSansMono-Bold.ttf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/DejaVuSansMono-Oblique.ttf").as_slice()),
+    Cow::Borrowed(include_bytes!("../assets/fonts/DejaVuSansMono-BoldOblique.ttf").as_slice()),
+    // Embed CJK fonts.
+    #[cfg(feature = "embedded-cjk-fonts")]
+    Cow::Borrowed(include_bytes!("../assets/fonts/InriaSerif-Bold.ttf").as_slice()),
+    #[cfg(feature = "embedded-cjk-fonts")]
+    Cow::Borrowed(include_bytes!("../assets/fonts/InriaSerif-BoldItalic.ttf").as_slice()),
+    #[cfg(feature = "embedded-cjk-fonts")]
+    Cow::Borrowed(include_bytes!("../assets/fonts/InriaSerif-Italic.ttf").as_slice()),
+    #[cfg(feature = "embedded-cjk-fonts")]
+    Cow::Borrowed(include_bytes!("../assets/fonts/InriaSerif-Regular.ttf").as_slice()),
+    #[cfg(feature = "embedded-cjk-fonts")]
+    Cow::Borrowed(include_bytes!("../assets/fonts/Roboto-Regular.ttf").as_slice()),
+    #[cfg(feature = "embedded-cjk-fonts")]
+    Cow::Borrowed(include_bytes!("../assets/fonts/NotoSerifCJKsc-Regular.otf").as_slice()),
+    // Embed emoji fonts.
+    #[cfg(feature = "embedded-emoji-fonts")]
+    Cow::Borrowed(include_bytes!("../assets/fonts/TwitterColorEmoji.ttf").as_slice()),
+    #[cfg(feature = "embedded-emoji-fonts")]
+    Cow::Borrowed(include_bytes!("../assets/fonts/NotoColorEmoji.ttf").as_slice()),
+];
+
+/// Creates the compiler of the document, which the preview and the
+/// subcommands share.
+pub fn compiler_driver(arguments: &WorldArgs) -> Result<CompileDriver, String> {
+    let cwd = || std::env::current_dir().map_err(|err| err.to_string());
+    let entry = if arguments.input.is_absolute() {
+        arguments.input.clone()
+    } else {
+        cwd()?.join(&arguments.input)
+    };
+    let root = if let Some(root) = &arguments.root {
+        if root.is_absolute() {
+            root.clone()
+        } else {
+            cwd()?.join(root)
+        }
+    } else {
+        cwd()?
+    };
+    if !entry.starts_with(&root) {
+        return Err("entry file must be in the root directory".to_owned());
+    }
+
+    let world = TypstSystemWorld::new(CompileOpts {
+        root_dir: root.clone(),
+        font_paths: arguments.font_paths.clone(),
+        with_embedded_fonts: EMBEDDED_FONT.to_owned(),
+        inputs: typst_inputs(arguments.inputs.clone()),
+        ..CompileOpts::default()
+    })
+    .map_err(|err| format!("incorrect options: {err}"))?;
+
+    Ok(CompileDriver::new(world).with_entry_file(entry))
+}
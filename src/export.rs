@@ -0,0 +1,53 @@
+use log::error;
+use typst::World;
+use typst_ts_core::TypstDocument;
+
+use crate::postprocess::{Artifact, ArtifactKind, PostProcessors};
+use crate::{export_source_map, export_structure, ExportArgs};
+
+/// Write the exports of a compiled document, returning whether all of them
+/// were written.
+pub(crate) fn export_document(
+    world: &dyn World,
+    document: &TypstDocument,
+    args: &ExportArgs,
+) -> bool {
+    let post_processors = PostProcessors::new(&args.post_processors);
+    let mut success = true;
+    if let Some(path) = &args.output {
+        let svg = typst_ts_svg_exporter::render_svg(document);
+        let artifact = Artifact {
+            kind: ArtifactKind::Svg,
+            path: Some(path),
+        };
+        if let Err(err) = std::fs::write(path, post_processors.run(artifact, svg.into_bytes())) {
+            error!("failed to write {:?}: {}", path, err);
+            success = false;
+        }
+    }
+    if let Some(path) = &args.pdf {
+        let pdf = typst_pdf::pdf(document, None, None);
+        let artifact = Artifact {
+            kind: ArtifactKind::Pdf,
+            path: Some(path),
+        };
+        if let Err(err) = std::fs::write(path, post_processors.run(artifact, pdf)) {
+            error!("failed to write {:?}: {}", path, err);
+            success = false;
+        }
+    }
+    if let Some(path) = &args.source_map {
+        if let Err(err) = export_source_map(world, document, path) {
+            error!("failed to export source map: {}", err);
+            success = false;
+        }
+    }
+    if let Some(path) = &args.structure {
+        if let Err(err) = export_structure(document, path) {
+            error!("failed to export structure: {}", err);
+            success = false;
+        }
+    }
+
+    success
+}
@@ -0,0 +1,173 @@
+//! A C ABI to embed the preview in-process, for editors which are not
+//! written in Rust. See `include/typst_preview.h`.
+//!
+//! The preview is started with the arguments of the command line and is
+//! driven like an editor: messages and events are the JSON of the control
+//! plane documented in `docs/editor.typ`, which are exchanged in-process
+//! rather than through the control plane.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::time::Duration;
+
+use clap::Parser;
+use log::error;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+
+use crate::{compiler_driver, preview, CliArguments, Previewer};
+
+/// A preview running in-process.
+pub struct TypstPreview {
+    runtime: Runtime,
+    previewer: Previewer,
+    messages: mpsc::UnboundedSender<String>,
+    // events are queued until they are polled, so the embedder doesn't need
+    // an async runtime
+    events: mpsc::UnboundedReceiver<String>,
+}
+
+/// Starts a preview with the arguments of the command line, without the
+/// program name, e.g. `--root`, `--data-plane-host` and the input file.
+///
+/// Returns null if the arguments are invalid or the preview fails to start.
+/// The preview is stopped with `typst_preview_stop`.
+///
+/// # Safety
+///
+/// `argv` must point to `argc` nul-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn typst_preview_start(
+    argc: c_int,
+    argv: *const *const c_char,
+) -> *mut TypstPreview {
+    let mut args = vec!["typst-preview".to_owned()];
+    for idx in 0..argc.max(0) as usize {
+        let arg = *argv.add(idx);
+        if arg.is_null() {
+            return std::ptr::null_mut();
+        }
+        args.push(CStr::from_ptr(arg).to_string_lossy().into_owned());
+    }
+
+    match std::panic::catch_unwind(move || start(args)) {
+        Ok(Ok(preview)) => Box::into_raw(Box::new(preview)),
+        Ok(Err(err)) => {
+            error!("failed to start the preview: {}", err);
+            std::ptr::null_mut()
+        }
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+fn start(args: Vec<String>) -> Result<TypstPreview, String> {
+    let arguments = CliArguments::try_parse_from(args).map_err(|err| err.to_string())?;
+    if arguments.command.is_some() {
+        return Err("subcommands can't be embedded".to_owned());
+    }
+    // remote roots are not mounted, as they need the command line
+    if arguments.world.remote.is_some() {
+        return Err("remote roots can't be embedded".to_owned());
+    }
+    let compiler_driver = compiler_driver(&arguments.world)?;
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let previewer = runtime.block_on(preview(arguments.preview, compiler_driver));
+    let (messages, events) = {
+        let _guard = runtime.enter();
+        previewer.connect_editor()
+    };
+
+    Ok(TypstPreview {
+        runtime,
+        previewer,
+        messages,
+        events,
+    })
+}
+
+/// Sends a JSON message of the control plane to the preview, e.g.
+/// `{"event":"updateMemoryFiles","files":{...}}`.
+///
+/// Returns 0 on success and -1 on failure.
+///
+/// # Safety
+///
+/// `preview` must be returned by `typst_preview_start` and not stopped yet,
+/// and `message` must be a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn typst_preview_send_message(
+    preview: *mut TypstPreview,
+    message: *const c_char,
+) -> c_int {
+    let (Some(preview), false) = (preview.as_ref(), message.is_null()) else {
+        return -1;
+    };
+    let message = CStr::from_ptr(message).to_string_lossy().into_owned();
+    match preview.messages.send(message) {
+        Ok(()) => 0,
+        Err(_) => {
+            error!("failed to send a message to the preview: the editor actor is gone");
+            -1
+        }
+    }
+}
+
+/// Takes the next JSON event sent by the preview without waiting, or
+/// returns null if there is none. The event is freed with
+/// `typst_preview_free_string`.
+///
+/// # Safety
+///
+/// `preview` must be returned by `typst_preview_start` and not stopped yet.
+#[no_mangle]
+pub unsafe extern "C" fn typst_preview_poll_event(preview: *mut TypstPreview) -> *mut c_char {
+    let Some(preview) = preview.as_mut() else {
+        return std::ptr::null_mut();
+    };
+    match preview.events.try_recv() {
+        // JSON escapes nul characters, so events never contain one
+        Ok(event) => CString::new(event).map_or(std::ptr::null_mut(), CString::into_raw),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Frees an event returned by `typst_preview_poll_event`.
+///
+/// # Safety
+///
+/// `event` must be returned by `typst_preview_poll_event` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn typst_preview_free_string(event: *mut c_char) {
+    if !event.is_null() {
+        drop(CString::from_raw(event));
+    }
+}
+
+/// Stops the preview, saving the state which survives a restart, and frees
+/// it.
+///
+/// # Safety
+///
+/// `preview` must be returned by `typst_preview_start` and not stopped yet.
+#[no_mangle]
+pub unsafe extern "C" fn typst_preview_stop(preview: *mut TypstPreview) {
+    if preview.is_null() {
+        return;
+    }
+    let TypstPreview {
+        runtime,
+        previewer,
+        messages,
+        events,
+    } = *Box::from_raw(preview);
+    // the session summary is still sent to the editor, which is disconnected
+    // afterwards
+    runtime.block_on(previewer.shutdown_hook());
+    drop((messages, events));
+    previewer.stop();
+    // the connections keep running until the runtime is shut down
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use clap::Parser;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Response, StatusCode};
+use log::{error, info};
+use tokio::sync::oneshot;
+use typst::model::Document;
+use typst_ts_compiler::service::{CompileDriver, CompileEnv, Compiler};
+use typst_ts_core::ImmutStr;
+
+use crate::diagnostics::file_diagnostics;
+use crate::renderer::page_hash;
+use crate::{compiler_driver, preview, GalleryArgs, PreviewArgs, PreviewMode, WorldArgs};
+
+/// A template of the gallery with its latest compilation.
+struct Template {
+    name: String,
+    entry: PathBuf,
+    state: Mutex<TemplateState>,
+}
+
+#[derive(Default)]
+struct TemplateState {
+    /// The latest document, or the diagnostics of the failed compilation.
+    result: Option<Result<Arc<Document>, String>>,
+    /// The rendered first page, with the content hash of the page it was
+    /// rendered from.
+    thumbnail: Option<(u128, String)>,
+}
+
+/// The templates of a directory, compiled in one world so that the fonts are
+/// only loaded once.
+struct Gallery {
+    world: WorldArgs,
+    compiler_driver: Mutex<CompileDriver>,
+    templates: Vec<Template>,
+    /// The frontends of the live previews started so far, by template index.
+    previews: tokio::sync::Mutex<HashMap<usize, ImmutStr>>,
+}
+
+impl Gallery {
+    /// Compiles a template again, so that reloading the gallery shows the
+    /// latest version of the files.
+    fn compile(&self, template: &Template) {
+        let mut compiler_driver = self.compiler_driver.lock().unwrap();
+        compiler_driver.entry_file = template.entry.clone();
+        let root = compiler_driver.world.root.clone();
+        let result = match compiler_driver.compile(&mut CompileEnv::default()) {
+            Ok(document) => Ok(document),
+            Err(errors) => Err(file_diagnostics(&compiler_driver.world, &root, &errors)
+                .iter()
+                .map(ToString::to_string)
+                .collect()),
+        };
+        drop(compiler_driver);
+        template.state.lock().unwrap().result = Some(result);
+    }
+
+    /// Starts a live preview of a template, or returns the frontend of the one
+    /// started before. The preview runs on a thread of its own until the
+    /// gallery exits.
+    async fn live_preview(&self, idx: usize) -> Option<ImmutStr> {
+        let mut previews = self.previews.lock().await;
+        if let Some(html) = previews.get(&idx) {
+            return Some(html.clone());
+        }
+
+        let world = template_world(&self.world, self.templates.get(idx)?.entry.clone());
+        let compiler_driver = match compiler_driver(&world) {
+            Ok(compiler_driver) => compiler_driver,
+            Err(err) => {
+                error!(
+                    "Gallery: failed to create the preview of {:?}: {}",
+                    world.input, err
+                );
+                return None;
+            }
+        };
+        // any free ports, since previews of several templates run at once
+        let arguments = PreviewArgs::parse_from([
+            "typst-preview",
+            "--data-plane-host",
+            "127.0.0.1:0",
+            "--control-plane-host",
+            "127.0.0.1:0",
+            "--no-span-cache",
+        ]);
+        let (html_tx, html_rx) = oneshot::channel();
+        std::thread::Builder::new()
+            .name(format!("GalleryPreview-{idx}"))
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap();
+                runtime.block_on(async move {
+                    let previewer = preview(arguments, compiler_driver).await;
+                    let _ = html_tx.send(previewer.frontend_html(PreviewMode::Document));
+                    previewer.join().await;
+                });
+            })
+            .ok()?;
+
+        let html = html_rx.await.ok()?;
+        info!("Gallery: started the live preview of {:?}", world.input);
+        previews.insert(idx, html.clone());
+        Some(html)
+    }
+}
+
+impl Template {
+    /// Renders the first page of the latest document, reusing the rendering
+    /// of the previous compilation if the page didn't change.
+    fn thumbnail(&self) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let Some(Ok(document)) = &state.result else {
+            return None;
+        };
+        let page = document.pages.first()?.clone();
+        let hash = page_hash(&page);
+        if let Some((cached, thumbnail)) = &state.thumbnail {
+            if *cached == hash {
+                return Some(thumbnail.clone());
+            }
+        }
+        let first = Document {
+            pages: vec![page],
+            ..Document::default()
+        };
+        let thumbnail = typst_ts_svg_exporter::render_svg(&first);
+        state.thumbnail = Some((hash, thumbnail.clone()));
+        Some(thumbnail)
+    }
+}
+
+/// The world of a template of the gallery, whose root defaults to the
+/// directory of the templates.
+fn template_world(world: &WorldArgs, entry: PathBuf) -> WorldArgs {
+    WorldArgs {
+        root: world.root.clone().or_else(|| Some(world.input.clone())),
+        input: entry,
+        ..world.clone()
+    }
+}
+
+/// Lists the template entry files in a directory, which are its typst files,
+/// sorted by name.
+pub fn template_entries(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut entries = std::fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file() && path.extension().map_or(false, |ext| ext == "typ"))
+        .collect::<Vec<_>>();
+    entries.sort();
+    Ok(entries)
+}
+
+/// Serves a gallery of the first pages of the templates in the directory
+/// given as the input, each linking to a live preview of the template. The
+/// templates are compiled again whenever the gallery is loaded.
+pub async fn gallery(entries: Vec<PathBuf>, args: &GalleryArgs) {
+    let world = template_world(&args.world, args.world.input.clone());
+    let compiler_driver = match compiler_driver(&world) {
+        Ok(compiler_driver) => compiler_driver,
+        Err(err) => {
+            error!("Gallery: {}", err);
+            return;
+        }
+    };
+    let gallery = Arc::new(Gallery {
+        world: args.world.clone(),
+        compiler_driver: Mutex::new(compiler_driver),
+        templates: entries
+            .into_iter()
+            .map(|entry| Template {
+                name: entry.file_stem().unwrap().to_string_lossy().to_string(),
+                entry,
+                state: Mutex::default(),
+            })
+            .collect(),
+        previews: tokio::sync::Mutex::default(),
+    });
+
+    let make_service = make_service_fn(move |_| {
+        let gallery = gallery.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req| {
+                let gallery = gallery.clone();
+                async move {
+                    let path = req.uri().path().to_owned();
+                    if let Some(idx) = path.strip_prefix("/preview/") {
+                        let html = match idx.parse() {
+                            Ok(idx) => gallery.live_preview(idx).await,
+                            Err(_) => None,
+                        };
+                        return Ok::<_, hyper::Error>(html.map_or_else(
+                            || status(StatusCode::NOT_FOUND),
+                            |html| html_response(html.to_string()),
+                        ));
+                    }
+                    let res = tokio::task::spawn_blocking(move || serve(&gallery, &path))
+                        .await
+                        .unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR));
+                    Ok::<_, hyper::Error>(res)
+                }
+            }))
+        }
+    });
+
+    let addr = match args.host.parse() {
+        Ok(addr) => addr,
+        Err(err) => {
+            error!("Gallery: invalid host {}: {}", args.host, err);
+            return;
+        }
+    };
+    let server = hyper::Server::bind(&addr).serve(make_service);
+    let addr = server.local_addr();
+    info!("Gallery: listening on http://{}", addr);
+    if !args.dont_open_in_browser {
+        if let Err(err) = open::that_detached(format!("http://{}", addr)) {
+            error!("failed to open browser: {}", err);
+        }
+    }
+    if let Err(err) = server.await {
+        error!("Gallery: server error: {}", err);
+    }
+}
+
+fn serve(gallery: &Gallery, path: &str) -> Response<Body> {
+    if path == "/" {
+        for template in &gallery.templates {
+            gallery.compile(template);
+        }
+        return html_response(gallery_page(&gallery.templates));
+    }
+    if let Some(rest) = path.strip_prefix("/thumbnail/") {
+        return rest
+            .strip_suffix(".svg")
+            .and_then(|idx| idx.parse::<usize>().ok())
+            .and_then(|idx| gallery.templates.get(idx))
+            .and_then(Template::thumbnail)
+            .map_or_else(|| status(StatusCode::NOT_FOUND), svg);
+    }
+
+    status(StatusCode::NOT_FOUND)
+}
+
+fn gallery_page(templates: &[Template]) -> String {
+    let mut page = String::from(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Templates</title>
+<style>
+body { font-family: sans-serif; margin: 24px; background: #eee; }
+main { display: grid; grid-template-columns: repeat(auto-fill, minmax(220px, 1fr)); gap: 24px; }
+figure { margin: 0; }
+figure img { width: 100%; background: white; box-shadow: 0 1px 4px rgba(0, 0, 0, 0.3); }
+figcaption { margin-top: 8px; text-align: center; }
+pre { white-space: pre-wrap; font-size: 12px; color: #b00; background: white; padding: 8px; }
+</style>
+</head>
+<body>
+<main>
+"#,
+    );
+    for (idx, template) in templates.iter().enumerate() {
+        let state = template.state.lock().unwrap();
+        let name = escape_html(&template.name);
+        let _ = match &state.result {
+            Some(Ok(_)) => writeln!(
+                page,
+                r#"<figure><a href="/preview/{idx}" target="_blank"><img src="/thumbnail/{idx}.svg" alt="{name}"></a><figcaption>{name}</figcaption></figure>"#
+            ),
+            None => writeln!(page, r#"<figure><figcaption>{name}</figcaption></figure>"#),
+            Some(Err(diagnostics)) => writeln!(
+                page,
+                r#"<figure><pre>{}</pre><figcaption>{name}</figcaption></figure>"#,
+                escape_html(diagnostics)
+            ),
+        };
+    }
+    page.push_str("</main>\n</body>\n</html>\n");
+    page
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn html_response(body: String) -> Response<Body> {
+    let mut res = Response::new(Body::from(body));
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    res
+}
+
+fn svg(body: String) -> Response<Body> {
+    let mut res = Response::new(Body::from(body));
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("image/svg+xml"),
+    );
+    res
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = status;
+    res
+}
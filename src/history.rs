@@ -0,0 +1,62 @@
+use typst_ts_core::debug_loc::DocumentPosition;
+
+/// A position visited by a doc to src or a src to doc jump.
+#[derive(Debug, Clone)]
+pub struct NavigationEntry {
+    pub filepath: String,
+    /// 0-based line.
+    pub line: usize,
+    /// 0-based character.
+    pub character: usize,
+    /// The position in the document, which is resolved on replay if unknown.
+    pub position: Option<DocumentPosition>,
+}
+
+impl NavigationEntry {
+    fn same_location(&self, other: &Self) -> bool {
+        self.filepath == other.filepath
+            && self.line == other.line
+            && self.character == other.character
+    }
+}
+
+const HISTORY_CAPACITY: usize = 100;
+
+/// Browser-style navigation history.
+#[derive(Debug, Default)]
+pub struct NavigationHistory {
+    entries: Vec<NavigationEntry>,
+    /// The index of the current entry.
+    current: usize,
+}
+
+impl NavigationHistory {
+    /// Records a visited position, dropping the entries after the current one.
+    pub fn push(&mut self, entry: NavigationEntry) {
+        if let Some(current) = self.entries.get(self.current) {
+            if current.same_location(&entry) {
+                return;
+            }
+            self.entries.truncate(self.current + 1);
+        }
+
+        self.entries.push(entry);
+        if self.entries.len() > HISTORY_CAPACITY {
+            self.entries.remove(0);
+        }
+        self.current = self.entries.len() - 1;
+    }
+
+    pub fn back(&mut self) -> Option<&NavigationEntry> {
+        self.current = self.current.checked_sub(1)?;
+        self.entries.get(self.current)
+    }
+
+    pub fn forward(&mut self) -> Option<&NavigationEntry> {
+        if self.current + 1 >= self.entries.len() {
+            return None;
+        }
+        self.current += 1;
+        self.entries.get(self.current)
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use log::{info, warn};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A list of items persisted as a JSON file, which is rewritten on every
+/// change.
+#[derive(Debug)]
+pub struct JsonList<T> {
+    /// The name of the items, for logging.
+    name: &'static str,
+    path: Option<PathBuf>,
+    items: Vec<T>,
+}
+
+impl<T> JsonList<T> {
+    /// Creates an empty list which is not persisted.
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            path: None,
+            items: vec![],
+        }
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> JsonList<T> {
+    /// Loads the items saved at `path`, if any.
+    pub fn load(name: &'static str, path: PathBuf) -> Self {
+        let items = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+
+        let list = Self {
+            name,
+            path: Some(path),
+            items,
+        };
+        info!(
+            "JsonList: loaded {} {} from {:?}",
+            list.items.len(),
+            list.name,
+            list.path
+        );
+        list
+    }
+
+    /// Changes the items and saves them.
+    pub fn update<R>(&mut self, f: impl FnOnce(&mut Vec<T>) -> R) -> R {
+        let res = f(&mut self.items);
+        self.save();
+        res
+    }
+
+    /// Changes the items without saving them, until the next update.
+    pub fn items_mut(&mut self) -> &mut [T] {
+        &mut self.items
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let data = serde_json::to_vec_pretty(&self.items).unwrap();
+        let res = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(path, data));
+        if let Err(err) = res {
+            warn!(
+                "JsonList: failed to save {} to {:?}: {}",
+                self.name, path, err
+            );
+        }
+    }
+}
@@ -1,29 +1,127 @@
 mod actor;
 mod args;
+mod bookmark;
+mod cadence;
+mod checker;
+pub mod client;
+mod comment;
+mod compile_once;
+mod config;
 mod debug_loc;
+mod dependencies;
+mod diagnostics;
+mod diff;
+mod driver;
+mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod gallery;
+mod history;
+mod json_list;
+mod mailbox;
+mod messages;
 mod outline;
+mod overlay;
+mod postprocess;
+mod protocol;
+mod remote;
+mod renderer;
+mod safe_mode;
+mod screenshot;
+mod session;
+mod snapshot;
+mod source_map;
+mod spill;
+mod structure;
+mod watch;
 
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
-use debug_loc::SpanInterner;
+use debug_loc::{span_cache_path, SpanInterner};
 use futures::SinkExt;
-use log::info;
-use serde::Deserialize;
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, watch, Mutex};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::{
+    header::{HOST, ORIGIN, SEC_WEBSOCKET_PROTOCOL},
+    HeaderValue, StatusCode,
+};
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::WebSocketStream;
+use typst::foundations::IntoValue;
 use typst::{layout::Position, syntax::Span};
 use typst_ts_compiler::service::CompileDriver;
-use typst_ts_core::{error::prelude::ZResult, ImmutStr, TypstDocument as Document};
-
-pub use typst_ts_compiler::service::DocToSrcJumpInfo;
+use typst_ts_core::{error::prelude::ZResult, ImmutStr, TypstDict, TypstDocument as Document};
 
 use actor::editor::CompileStatus;
-use actor::editor::EditorActor;
-use actor::typst::TypstActor;
+use actor::editor::{EditorActor, EditorActorRequest, EditorConn, EmbeddedConn};
+use actor::render::RenderActorRequest;
+use actor::supervisor::{Supervised, Supervisor};
+use actor::typst::{TypstActor, TypstActorHandle, TypstActorRequest};
+use actor::webview::WebviewActorRequest;
 pub use args::*;
+pub use checker::{register_checker, Checker, Finding};
+pub use compile_once::compile_once;
+use config::{Config, ProfileSettings, Profiles};
+pub use diff::{diff_documents, diff_inputs, DiffReport};
+pub use driver::{compiler_driver, EMBEDDED_FONT};
+pub use gallery::{gallery, template_entries};
+use mailbox::{MailboxConfigs, MailboxReceiver, MailboxSender};
+pub use messages::{Locale, MessageCode};
+use messages::{Localized, StatusMessage};
+use overlay::Overlays;
+use postprocess::PostProcessors;
+pub use postprocess::{
+    register_post_processor, Artifact, ArtifactKind, ExternalCommand, PostProcessor,
+};
+use protocol::Plane;
+pub use protocol::{ChannelKind, ChannelProtocol, EventKind, Subscriptions, PROTOCOL_VERSION};
+pub use remote::{mount_remote, RemoteRoot};
+use renderer::SvgFilesWriter;
+pub use safe_mode::record_panics;
+use safe_mode::{CrashTracking, SafeModeReport};
+use session::{Session, SessionSummary};
+pub use source_map::{export_source_map, source_map, SourceMap, SourceMapEntry};
+use spill::SpillStore;
+pub use structure::{export_structure, structure, DocumentStructure};
+pub use watch::watch;
+
+/// The source range of a clicked document element.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocToSrcJumpInfo {
+    /// The absolute path of the source file, which is not necessarily a
+    /// typst source, e.g. a bibliography or an image.
+    pub filepath: String,
+    /// The 0-based line and column of the start of the range, if known.
+    pub start: Option<(usize, usize)>,
+    /// The 0-based line and column of the end of the range, if known.
+    pub end: Option<(usize, usize)>,
+    /// Whether the file is not part of the project, e.g. a package source,
+    /// so it is opened for inspection only.
+    pub readonly: bool,
+    /// Whether the range belongs to an enclosing element of the clicked one,
+    /// because the clicked element has no usable span, e.g. it is generated
+    /// by a show rule.
+    pub approximate: bool,
+}
+
+impl From<typst_ts_compiler::service::DocToSrcJumpInfo> for DocToSrcJumpInfo {
+    fn from(info: typst_ts_compiler::service::DocToSrcJumpInfo) -> Self {
+        Self {
+            filepath: info.filepath,
+            start: info.start,
+            end: info.end,
+            readonly: false,
+            approximate: false,
+        }
+    }
+}
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChangeCursorPositionRequest {
     filepath: PathBuf,
     line: usize,
@@ -53,11 +151,39 @@ impl SrcToDocJumpRequest {
     }
 }
 
+/// A source range to highlight in the preview.
+#[derive(Debug, Deserialize)]
+pub struct HighlightRange {
+    filepath: PathBuf,
+    /// The 0-based line and character of the start of the range.
+    start: (usize, usize),
+    /// The 0-based line and character of the end of the range.
+    end: (usize, usize),
+}
+
 #[derive(Debug, Deserialize)]
 pub struct MemoryFiles {
     files: HashMap<PathBuf, String>,
 }
 
+/// The `sys.inputs` of the document, replacing the previous ones.
+#[derive(Debug, Deserialize)]
+pub struct Inputs {
+    inputs: HashMap<String, String>,
+}
+
+/// Converts string key-value pairs to the dictionary of `sys.inputs`.
+pub fn typst_inputs<K, V>(pairs: impl IntoIterator<Item = (K, V)>) -> TypstDict
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    pairs
+        .into_iter()
+        .map(|(key, value)| (key.as_ref().into(), value.as_ref().into_value()))
+        .collect()
+}
+
 #[derive(Debug, Deserialize)]
 pub struct MemoryFilesShort {
     files: Vec<PathBuf>,
@@ -67,10 +193,39 @@ pub struct MemoryFilesShort {
 /// If this file is not found, please refer to https://enter-tainer.github.io/typst-preview/dev.html to build the frontend.
 const HTML: &str = include_str!("../addons/vscode/out/frontend/index.html");
 
+/// The build id of the embedded frontend, which is baked into the bundle by
+/// `addons/frontend/vite.config.js` and reported back by connecting webviews.
+static FRONTEND_BUILD: Lazy<Option<&'static str>> = Lazy::new(|| {
+    const MARKER: &str = "typst-preview-frontend-build:";
+    let rest = &HTML[HTML.find(MARKER)?..];
+    let end = rest[MARKER.len()..]
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .map_or(rest.len(), |end| MARKER.len() + end);
+    Some(&rest[..end])
+});
+
+pub(crate) fn frontend_build() -> Option<&'static str> {
+    *FRONTEND_BUILD
+}
+
+/// Get the embedded frontend HTML connecting to the data plane at the given
+/// url, e.g. `ws://127.0.0.1:23625`.
+pub(crate) fn frontend_html(data_plane_url: &str) -> String {
+    HTML.replace("ws://127.0.0.1:23625", data_plane_url)
+}
+
 pub struct Previewer {
     frontend_html_factory: Box<dyn Fn(PreviewMode) -> ImmutStr>,
     data_plane_handle: tokio::task::JoinHandle<()>,
     control_plane_handle: tokio::task::JoinHandle<()>,
+    control_plane_addr: SocketAddr,
+    span_interner: SpanInterner,
+    session: Session,
+    spill: Option<Arc<SpillStore>>,
+    editor_tx: MailboxSender<EditorActorRequest>,
+    crash_tracking: Option<CrashTracking>,
+    dispatcher: Dispatcher,
+    typst_actor: TypstActorHandle,
 }
 
 impl Previewer {
@@ -79,6 +234,67 @@ impl Previewer {
         (self.frontend_html_factory)(mode)
     }
 
+    /// Get the address the control plane listens on.
+    pub fn control_plane_addr(&self) -> SocketAddr {
+        self.control_plane_addr
+    }
+
+    /// Get a future that saves the state which should survive a restart,
+    /// to be awaited before the program exits.
+    pub fn shutdown_hook(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let span_interner = self.span_interner.clone();
+        let session = self.session.clone();
+        let editor_tx = self.editor_tx.clone();
+        let spill = self.spill.clone();
+        let crash_tracking = self.crash_tracking.clone();
+        async move {
+            let summary = clean_up(
+                &span_interner,
+                &session,
+                spill.as_deref(),
+                crash_tracking.as_ref(),
+            )
+            .await;
+            // waits a bit for the summary to reach the editor, if any
+            let (sent_tx, sent_rx) = tokio::sync::oneshot::channel();
+            let _ = editor_tx.send(EditorActorRequest::SessionSummary(summary, Some(sent_tx)));
+            let _ = tokio::time::timeout(Duration::from_secs(1), sent_rx).await;
+        }
+    }
+
+    /// Connects an editor running in the same process, without going
+    /// through the control plane. The editor sends the JSON messages of the
+    /// control plane to the returned sender and gets the events from the
+    /// returned receiver. Unlike the editors of the control plane, the
+    /// program keeps running once it disconnects.
+    ///
+    /// Must be called within the runtime the preview was started on.
+    pub(crate) fn connect_editor(
+        &self,
+    ) -> (
+        tokio::sync::mpsc::UnboundedSender<String>,
+        tokio::sync::mpsc::UnboundedReceiver<String>,
+    ) {
+        let (message_tx, message_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (event_tx, event_rx) = tokio::sync::mpsc::unbounded_channel();
+        let conn = EmbeddedConn::new(message_rx, event_tx);
+        tokio::spawn(self.dispatcher.clone().serve_editor(
+            Box::new(conn),
+            Subscriptions::default(),
+            true,
+        ));
+        (message_tx, event_rx)
+    }
+
+    /// Stops the servers and the compiler, waiting for the thread of the
+    /// compiler to exit. The open connections end with the runtime the
+    /// preview was started on.
+    pub fn stop(self) {
+        self.data_plane_handle.abort();
+        self.control_plane_handle.abort();
+        self.typst_actor.shutdown();
+    }
+
     /// Join the previewer actors.
     // todo: close the actors
     pub async fn join(self) {
@@ -113,39 +329,148 @@ pub trait CompileHost {
 
 // todo: replace CompileDriver by CompileHost
 pub async fn preview(arguments: PreviewArgs, compiler_driver: CompileDriver) -> Previewer {
-    let enable_partial_rendering = arguments.enable_partial_rendering;
-    let invert_colors = arguments.invert_colors;
+    let root = compiler_driver.world.root.clone();
+    let (safe_mode, crash_tracking) =
+        safe_mode::start(&root, &compiler_driver.entry_file, arguments.safe_mode);
+    // safe mode falls back to the defaults for whatever may have crashed
+    let safe = safe_mode.is_some();
+    let enable_partial_rendering = arguments.enable_partial_rendering && !safe;
+    let invert_colors = if safe {
+        String::new()
+    } else {
+        arguments.invert_colors
+    };
+    let outline_limit = arguments.outline_limit;
+    let enable_error_placeholders = arguments.enable_error_placeholders && !safe;
+    let renderer = if safe {
+        RendererBackend::Vector
+    } else {
+        arguments.renderer
+    };
+    let mailboxes = if safe {
+        MailboxConfigs::default()
+    } else {
+        MailboxConfigs::new(&arguments.mailboxes)
+    };
+    let session = Session::new(arguments.session_summary);
+    let post_processors = PostProcessors::new(&arguments.post_processors);
+
+    // safe mode leaves the configuration out as well
+    let config = if safe {
+        Config::default()
+    } else {
+        Config::load(arguments.config.as_deref(), &root).unwrap_or_else(|err| {
+            error!("{}", err);
+            Config::default()
+        })
+    };
+    let profile = arguments.profile.or(config.default_profile.clone());
+    let mut profiles = Profiles::new(
+        config,
+        ProfileSettings {
+            max_compile_delay: Duration::from_millis(arguments.max_compile_delay),
+            error_placeholders: enable_error_placeholders,
+            overlays: Overlays::default(),
+        },
+    );
+    if let Err(err) = profiles.activate(profile.as_deref()) {
+        error!("{}", err);
+    }
+    let profile_watch = watch::channel(profiles.settings());
+
+    let spill = match arguments.spill_threshold {
+        Some(threshold) => SpillStore::start(threshold, &arguments.artifact_host)
+            .await
+            .map_err(|err| error!("{}", err))
+            .ok(),
+        None => None,
+    };
+
+    // Shared resource
+    let span_cache_path = span_cache_path(&root).filter(|_| !arguments.no_span_cache);
+    let span_interner = match span_cache_path {
+        // the cache may be what crashed the previous previews, so it is
+        // discarded for the next one to start afresh
+        Some(cache_path) if safe => {
+            let _ = std::fs::remove_file(cache_path);
+            SpanInterner::new()
+        }
+        Some(cache_path) => SpanInterner::with_cache(cache_path),
+        None => SpanInterner::new(),
+    };
 
     // Create the world that serves sources, fonts and files.
     let actor::typst::Channels {
         typst_mailbox,
         doc_watch,
+        partial_watch,
         renderer_mailbox,
         editor_conn,
         webview_conn: (webview_tx, _),
-    } = TypstActor::set_up_channels();
+    } = TypstActor::set_up_channels(&mailboxes);
+    let svg_files = match (renderer, arguments.renderer_output) {
+        (RendererBackend::Vector, _) => None,
+        (RendererBackend::SvgFiles, Some(output)) => {
+            Some(SvgFilesWriter::spawn(output, post_processors.clone()))
+        }
+        (RendererBackend::SvgFiles, None) => {
+            warn!("the svg-files renderer needs --renderer-output, ignoring it");
+            None
+        }
+    };
     let typst_actor = TypstActor::new(
         compiler_driver,
         typst_mailbox.1,
         doc_watch.0,
+        partial_watch.0,
         renderer_mailbox.0.clone(),
         editor_conn.0.clone(),
         webview_tx.clone(),
+        arguments.source_map,
+        arguments.structure,
+        session.clone(),
+        profiles,
+        profile_watch.0,
+        svg_files,
+        post_processors.clone(),
     );
 
-    // Shared resource
-    let span_interner = SpanInterner::new();
+    let typst_actor = typst_actor.spawn(arguments.compile_threads);
 
-    tokio::spawn(typst_actor.run());
+    let dispatcher = Dispatcher {
+        span_interner: span_interner.clone(),
+        typst_tx: typst_mailbox.0,
+        webview_tx,
+        doc_watch_rx: doc_watch.1,
+        partial_rx: partial_watch.1,
+        renderer_tx: renderer_mailbox.0,
+        editor_tx: editor_conn.0.clone(),
+        editor_rx: Arc::new(Mutex::new(Some({
+            // nothing takes the editor events until an editor connects
+            editor_conn.1.detach();
+            editor_conn.1
+        }))),
+        editor_connections: Arc::default(),
+        editor_reconnect_timeout: Duration::from_millis(arguments.editor_reconnect_timeout),
+        data_plane_url: arguments.data_plane_url.clone(),
+        enable_partial_rendering,
+        invert_colors,
+        outline_limit,
+        profile: profile_watch.1,
+        update_stats: arguments.update_stats,
+        spill: spill.clone(),
+        mailboxes,
+        session: session.clone(),
+        locale: arguments.locale,
+        safe_mode,
+        crash_tracking: crash_tracking.clone(),
+        post_processors,
+    };
 
     let (data_plane_port_tx, data_plane_port_rx) = tokio::sync::oneshot::channel();
     let data_plane_addr = arguments.data_plane_host;
     let data_plane_handle = {
-        let span_interner = span_interner.clone();
-        let typst_tx = typst_mailbox.0.clone();
-        let webview_tx = webview_tx.clone();
-        let doc_watch_rx = doc_watch.1.clone();
-        let renderer_tx = renderer_mailbox.0.clone();
+        let dispatcher = dispatcher.clone();
         tokio::spawn(async move {
             // Create the event loop and TCP listener we'll accept connections on.
             let try_socket = TcpListener::bind(&data_plane_addr).await;
@@ -156,59 +481,15 @@ pub async fn preview(arguments: PreviewArgs, compiler_driver: CompileDriver) ->
             );
             let _ = data_plane_port_tx.send(listener.local_addr().unwrap().port());
             while let Ok((stream, _)) = listener.accept().await {
-                let span_interner = span_interner.clone();
-                let webview_tx = webview_tx.clone();
-                let webview_rx = webview_tx.subscribe();
-                let typst_tx = typst_tx.clone();
-                let doc_watch_rx = doc_watch_rx.clone();
-                let mut conn = accept_connection(stream).await;
-                if enable_partial_rendering {
-                    conn.send(Message::Binary("partial-rendering,true".into()))
-                        .await
-                        .unwrap();
-                }
-                if !invert_colors.is_empty() {
-                    conn.send(Message::Binary(
-                        format!("invert-colors,{}", invert_colors).into(),
-                    ))
-                    .await
-                    .unwrap();
-                }
-                let actor::webview::Channels { svg } =
-                    actor::webview::WebviewActor::set_up_channels();
-                let webview_actor = actor::webview::WebviewActor::new(
-                    conn,
-                    svg.1,
-                    webview_tx.clone(),
-                    webview_rx,
-                    editor_conn.0.clone(),
-                    renderer_tx.clone(),
-                );
-                tokio::spawn(webview_actor.run());
-                let render_actor = actor::render::RenderActor::new(
-                    renderer_tx.subscribe(),
-                    doc_watch_rx.clone(),
-                    typst_tx,
-                    svg.0,
-                    webview_tx,
-                );
-                render_actor.spawn();
-                let outline_render_actor = actor::render::OutlineRenderActor::new(
-                    renderer_tx.subscribe(),
-                    doc_watch_rx,
-                    editor_conn.0.clone(),
-                    span_interner,
-                );
-                outline_render_actor.spawn();
+                tokio::spawn(dispatcher.clone().serve(stream, Plane::Data));
             }
         })
     };
 
     let control_plane_addr = arguments.control_plane_host;
+    let (control_plane_addr_tx, control_plane_addr_rx) = tokio::sync::oneshot::channel();
     let control_plane_handle = {
-        let span_interner = span_interner.clone();
-        let typst_tx = typst_mailbox.0.clone();
-        let editor_rx = editor_conn.1;
+        let dispatcher = dispatcher.clone();
         tokio::spawn(async move {
             let try_socket = TcpListener::bind(&control_plane_addr).await;
             let listener = try_socket.expect("Failed to bind");
@@ -216,18 +497,18 @@ pub async fn preview(arguments: PreviewArgs, compiler_driver: CompileDriver) ->
                 "Control plane server listening on: {}",
                 listener.local_addr().unwrap()
             );
-            let (stream, _) = listener.accept().await.unwrap();
-            let conn = accept_connection(stream).await;
-            let editor_actor =
-                EditorActor::new(editor_rx, conn, typst_tx, webview_tx, span_interner);
-            editor_actor.run().await;
+            let _ = control_plane_addr_tx.send(listener.local_addr().unwrap());
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(dispatcher.clone().serve(stream, Plane::Control));
+            }
         })
     };
     let data_plane_port = data_plane_port_rx.await.unwrap();
-    let html = HTML.replace(
-        "ws://127.0.0.1:23625",
-        format!("ws://127.0.0.1:{data_plane_port}").as_str(),
-    );
+    let control_plane_addr = control_plane_addr_rx.await.unwrap();
+    let data_plane_url = arguments
+        .data_plane_url
+        .unwrap_or_else(|| format!("ws://127.0.0.1:{data_plane_port}"));
+    let html = frontend_html(&data_plane_url);
     // previewMode
     let frontend_html_factory = Box::new(move |mode| -> ImmutStr {
         let mode = match mode {
@@ -245,19 +526,367 @@ pub async fn preview(arguments: PreviewArgs, compiler_driver: CompileDriver) ->
         frontend_html_factory,
         data_plane_handle,
         control_plane_handle,
+        control_plane_addr,
+        span_interner,
+        session,
+        spill,
+        editor_tx: editor_conn.0,
+        crash_tracking,
+        dispatcher,
+        typst_actor,
+    }
+}
+
+/// Sends the thumbnails of the pages of every new document to the editor.
+async fn send_thumbnails(
+    mut document: watch::Receiver<Option<Arc<Document>>>,
+    editor_tx: MailboxSender<EditorActorRequest>,
+) {
+    loop {
+        let doc = document.borrow_and_update().clone();
+        if let Some(doc) = doc {
+            let Ok(thumbnails) =
+                tokio::task::spawn_blocking(move || screenshot::thumbnails(&doc)).await
+            else {
+                break;
+            };
+            if editor_tx
+                .send(EditorActorRequest::Thumbnails(thumbnails))
+                .is_err()
+            {
+                break;
+            }
+        }
+        if document.changed().await.is_err() {
+            break;
+        }
     }
 }
 
-async fn accept_connection(stream: TcpStream) -> WebSocketStream<TcpStream> {
+/// Saves the state which should survive a restart and removes the temporary
+/// files before the program exits, returning the summary of the session.
+async fn clean_up(
+    span_interner: &SpanInterner,
+    session: &Session,
+    spill: Option<&SpillStore>,
+    crash_tracking: Option<&CrashTracking>,
+) -> SessionSummary {
+    span_interner.save().await;
+    if let Some(spill) = spill {
+        spill.remove_all();
+    }
+    if let Some(crash_tracking) = crash_tracking {
+        crash_tracking.clean_exit();
+    }
+    session.finish().await
+}
+
+/// Spawns the actors serving a newly accepted connection, according to the
+/// channel kind negotiated during the websocket handshake.
+#[derive(Clone)]
+struct Dispatcher {
+    span_interner: SpanInterner,
+    typst_tx: MailboxSender<TypstActorRequest>,
+    webview_tx: broadcast::Sender<WebviewActorRequest>,
+    doc_watch_rx: watch::Receiver<Option<Arc<Document>>>,
+    /// Whether the latest compilation failed, which new renderers start with.
+    partial_rx: watch::Receiver<bool>,
+    renderer_tx: broadcast::Sender<RenderActorRequest>,
+    editor_tx: MailboxSender<EditorActorRequest>,
+    /// There is exactly one editor actor, taken by the connected editor and
+    /// put back once it disconnects.
+    editor_rx: Arc<Mutex<Option<MailboxReceiver<EditorActorRequest>>>>,
+    /// The number of editor connections so far, to tell whether an editor
+    /// connected again while waiting for one.
+    editor_connections: Arc<AtomicUsize>,
+    editor_reconnect_timeout: Duration,
+    /// The url of the data plane given on the command line, if any.
+    data_plane_url: Option<String>,
+    enable_partial_rendering: bool,
+    invert_colors: String,
+    outline_limit: Option<usize>,
+    /// The settings of the active profile, which new renderers start with.
+    profile: watch::Receiver<ProfileSettings>,
+    update_stats: bool,
+    /// Where the updates of huge documents are written, if enabled.
+    spill: Option<Arc<SpillStore>>,
+    mailboxes: MailboxConfigs,
+    session: Session,
+    locale: Locale,
+    /// Why the preview runs in safe mode, if it does.
+    safe_mode: Option<SafeModeReport>,
+    crash_tracking: Option<CrashTracking>,
+    post_processors: PostProcessors,
+}
+
+impl Dispatcher {
+    async fn serve(self, stream: TcpStream, plane: Plane) {
+        let Some((conn, handshake)) = accept_connection(stream, plane).await else {
+            return;
+        };
+        let Handshake {
+            protocol,
+            subscriptions,
+            host,
+            origin,
+        } = handshake;
+        info!("Dispatching connection with protocol: {}", protocol);
+        if protocol.kind != ChannelKind::Editor {
+            // the page of the webview fetches the spilled updates
+            if let (Some(spill), Some(origin)) = (&self.spill, &origin) {
+                spill.allow_origin(origin);
+            }
+        }
+        match protocol.kind {
+            ChannelKind::Editor => {
+                self.serve_editor(Box::new(conn), subscriptions, false)
+                    .await
+            }
+            ChannelKind::Webview => self.serve_webview(conn, host, true).await,
+            ChannelKind::Viewer => self.serve_webview(conn, host, false).await,
+        }
+    }
+
+    /// Serves an editor until it disconnects. The program exits unless
+    /// another editor connects in time, or the editor is `embedded` in the
+    /// process.
+    async fn serve_editor(
+        self,
+        mut conn: Box<dyn EditorConn>,
+        subscriptions: Subscriptions,
+        embedded: bool,
+    ) {
+        let Some(editor_rx) = self.editor_rx.lock().await.take() else {
+            warn!("An editor is already connected, rejecting the new one");
+            let _ = conn.close().await;
+            return;
+        };
+        editor_rx.attach();
+        let connection = self.editor_connections.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(report) = &self.safe_mode {
+            let _ = self
+                .editor_tx
+                .send(EditorActorRequest::SafeMode(report.clone()));
+        }
+        let thumbnails = subscriptions.contains(EventKind::Thumbnails).then(|| {
+            tokio::spawn(send_thumbnails(
+                self.doc_watch_rx.clone(),
+                self.editor_tx.clone(),
+            ))
+        });
+        let editor_actor = EditorActor::new(
+            editor_rx,
+            conn,
+            self.typst_tx.clone(),
+            self.webview_tx.clone(),
+            self.span_interner.clone(),
+            self.outline_limit,
+            subscriptions,
+            self.session.clone(),
+            self.locale,
+        );
+        let mut editor_actor = editor_actor;
+        let mut supervisor = Supervisor::new("EditorActor");
+        // the editor is asked to sync its files again whenever the actor runs
+        while let Supervised::Restart = supervisor.supervise(editor_actor.run()).await {}
+        if let Some(thumbnails) = thumbnails {
+            thumbnails.abort();
+        }
+
+        let editor_rx = editor_actor.into_mailbox();
+        editor_rx.detach();
+        self.editor_rx.lock().await.replace(editor_rx);
+        if embedded {
+            return;
+        }
+        tokio::time::sleep(self.editor_reconnect_timeout).await;
+        if self.editor_connections.load(Ordering::SeqCst) != connection {
+            return;
+        }
+        info!("No editor connected again, shutting down whole program");
+        clean_up(
+            &self.span_interner,
+            &self.session,
+            self.spill.as_deref(),
+            self.crash_tracking.as_ref(),
+        )
+        .await;
+        std::process::exit(0);
+    }
+
+    async fn serve_webview(
+        self,
+        mut conn: WebSocketStream<TcpStream>,
+        host: Option<String>,
+        interactive: bool,
+    ) {
+        // the frontend sent to the webview connects to the data plane the
+        // way the webview itself did, e.g. through a proxy
+        let data_plane_url = match (&self.data_plane_url, host) {
+            (Some(url), _) => url.clone(),
+            (None, Some(host)) => format!("ws://{host}"),
+            (None, None) => match conn.get_ref().local_addr() {
+                Ok(addr) => format!("ws://127.0.0.1:{}", addr.port()),
+                Err(_) => "ws://127.0.0.1:23625".to_owned(),
+            },
+        };
+        if self.enable_partial_rendering {
+            conn.send(Message::Binary("partial-rendering,true".into()))
+                .await
+                .unwrap();
+        }
+        if let Some(report) = &self.safe_mode {
+            let json = serde_json::to_string(&Localized {
+                event: report,
+                message: StatusMessage::new(MessageCode::SafeMode, self.locale),
+            })
+            .unwrap();
+            conn.send(Message::Binary(format!("safe-mode,{json}").into()))
+                .await
+                .unwrap();
+        }
+        if !self.invert_colors.is_empty() {
+            conn.send(Message::Binary(
+                format!("invert-colors,{}", self.invert_colors).into(),
+            ))
+            .await
+            .unwrap();
+        }
+        let actor::webview::Channels {
+            svg,
+            stats,
+            full_render,
+        } = actor::webview::WebviewActor::set_up_channels(self.mailboxes.webview);
+        let webview_actor = actor::webview::WebviewActor::new(
+            conn,
+            svg.1,
+            stats.1,
+            self.webview_tx.clone(),
+            self.webview_tx.subscribe(),
+            self.editor_tx.clone(),
+            self.renderer_tx.clone(),
+            full_render.0,
+            self.typst_tx.clone(),
+            data_plane_url,
+            interactive,
+        );
+        tokio::spawn(async move {
+            let mut webview_actor = webview_actor;
+            let mut supervisor = Supervisor::new("WebviewActor");
+            loop {
+                match supervisor.supervise(webview_actor.run()).await {
+                    Supervised::Done(()) | Supervised::GiveUp => break,
+                    Supervised::Restart => webview_actor.resync(),
+                }
+            }
+        });
+        let profile = *self.profile.borrow();
+        let render_actor = actor::render::RenderActor::new(
+            self.renderer_tx.subscribe(),
+            full_render.1,
+            self.doc_watch_rx.clone(),
+            self.typst_tx,
+            svg.0,
+            self.webview_tx,
+            self.editor_tx.clone(),
+            self.update_stats.then_some(stats.0),
+            Box::new(renderer::create_renderer),
+            *self.partial_rx.borrow(),
+            profile.error_placeholders,
+            profile.overlays,
+            self.spill,
+            self.post_processors,
+        );
+        render_actor.spawn();
+        let outline_render_actor = actor::render::OutlineRenderActor::new(
+            self.renderer_tx.subscribe(),
+            self.doc_watch_rx,
+            self.editor_tx,
+            self.span_interner,
+        );
+        outline_render_actor.spawn();
+    }
+}
+
+/// What a client asked for when connecting.
+struct Handshake {
+    protocol: ChannelProtocol,
+    subscriptions: Subscriptions,
+    /// The `Host` header, i.e. how the client reached the server.
+    host: Option<String>,
+    /// The `Origin` header of browser clients.
+    origin: Option<String>,
+}
+
+/// Accepts a websocket connection and negotiates its subprotocol. Clients
+/// that offer no subprotocol are treated as the fallback kind of the
+/// `plane`, and the kinds the plane doesn't accept are refused.
+async fn accept_connection(
+    stream: TcpStream,
+    plane: Plane,
+) -> Option<(WebSocketStream<TcpStream>, Handshake)> {
     let addr = stream
         .peer_addr()
         .expect("connected streams should have a peer address");
     info!("Peer address: {}", addr);
 
-    let ws_stream = tokio_tungstenite::accept_async(stream)
-        .await
-        .expect("Error during the websocket handshake occurred");
+    let mut protocol = ChannelProtocol::fallback(plane.fallback());
+    let mut subscriptions = Subscriptions::default();
+    let mut host = None;
+    let mut origin = None;
+    let negotiate = |req: &Request, mut resp: Response| {
+        subscriptions = Subscriptions::parse(req.uri().query());
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned)
+        };
+        host = header(HOST);
+        origin = header(ORIGIN);
+        let Some(offered) = req.headers().get(SEC_WEBSOCKET_PROTOCOL) else {
+            return Ok(resp);
+        };
+        let negotiated = offered.to_str().ok().and_then(ChannelProtocol::negotiate);
+        let Some(negotiated) = negotiated else {
+            let mut err = ErrorResponse::new(Some(format!(
+                "unsupported subprotocols: {:?}, expected e.g. {}",
+                offered, protocol
+            )));
+            *err.status_mut() = StatusCode::BAD_REQUEST;
+            return Err(err);
+        };
+        if !plane.accepts(negotiated.kind) {
+            let mut err = ErrorResponse::new(Some(format!(
+                "{negotiated} is not accepted on the {plane}, expected e.g. {protocol}"
+            )));
+            *err.status_mut() = StatusCode::BAD_REQUEST;
+            return Err(err);
+        }
+        resp.headers_mut().insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_str(&negotiated.to_string()).unwrap(),
+        );
+        protocol = negotiated;
+        Ok(resp)
+    };
+
+    let ws_stream = match tokio_tungstenite::accept_hdr_async(stream, negotiate).await {
+        Ok(ws_stream) => ws_stream,
+        Err(err) => {
+            warn!("Error during the websocket handshake occurred: {}", err);
+            return None;
+        }
+    };
 
     info!("New WebSocket connection: {}", addr);
-    ws_stream
+    Some((
+        ws_stream,
+        Handshake {
+            protocol,
+            subscriptions,
+            host,
+            origin,
+        },
+    ))
 }
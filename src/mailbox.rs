@@ -0,0 +1,404 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+
+use log::warn;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::Notify;
+
+/// What a full mailbox does with a new message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OverflowPolicy {
+    /// Merges the message into the newest queued one if they are of the same
+    /// kind, e.g. two edits of the same files, and blocks otherwise.
+    Coalesce,
+    /// Drops the oldest queued message.
+    DropOldest,
+    /// Blocks the sender until the receiver catches up.
+    Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MailboxConfig {
+    /// The number of queued messages after which the overflow policy
+    /// applies, or `None` for an unbounded mailbox.
+    pub capacity: Option<usize>,
+    pub policy: OverflowPolicy,
+}
+
+impl MailboxConfig {
+    /// Parses `CAPACITY[:POLICY]`, where the capacity may be `unbounded` and
+    /// the policy is one of `coalesce`, `drop-oldest` and `block`.
+    pub fn parse(raw: &str, default: MailboxConfig) -> Result<Self, String> {
+        let (capacity, policy) = match raw.split_once(':') {
+            Some((capacity, policy)) => (capacity, Some(policy)),
+            None => (raw, None),
+        };
+        let capacity = match capacity {
+            "unbounded" => None,
+            capacity => Some(
+                capacity
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|capacity| *capacity > 0)
+                    .ok_or_else(|| format!("invalid mailbox capacity: {capacity}"))?,
+            ),
+        };
+        let policy = match policy {
+            None => default.policy,
+            Some("coalesce") => OverflowPolicy::Coalesce,
+            Some("drop-oldest") => OverflowPolicy::DropOldest,
+            Some("block") => OverflowPolicy::Block,
+            Some(policy) => return Err(format!("unknown overflow policy: {policy}")),
+        };
+        Ok(Self { capacity, policy })
+    }
+}
+
+/// The configurations of the mailboxes of the actors.
+#[derive(Debug, Clone, Copy)]
+pub struct MailboxConfigs {
+    /// The requests to the compiler.
+    pub typst: MailboxConfig,
+    /// The events sent to the editor.
+    pub editor: MailboxConfig,
+    /// The updates sent to each webview.
+    pub webview: MailboxConfig,
+}
+
+impl Default for MailboxConfigs {
+    fn default() -> Self {
+        Self {
+            typst: MailboxConfig {
+                capacity: Some(1024),
+                policy: OverflowPolicy::Coalesce,
+            },
+            editor: MailboxConfig {
+                capacity: Some(1024),
+                policy: OverflowPolicy::Coalesce,
+            },
+            // the renderer has its own thread, which is fine to hold up
+            webview: MailboxConfig {
+                capacity: Some(64),
+                policy: OverflowPolicy::Block,
+            },
+        }
+    }
+}
+
+impl MailboxConfigs {
+    /// Parses `NAME=CAPACITY[:POLICY]`, where the policy defaults to the one
+    /// of the mailbox.
+    pub fn parse_override(raw: &str) -> Result<(String, MailboxConfig), String> {
+        let (name, config) = raw
+            .split_once('=')
+            .ok_or("mailbox must be a name and a capacity separated by an equal sign")?;
+        let default = Self::default();
+        let default = match name {
+            "typst" => default.typst,
+            "editor" => default.editor,
+            "webview" => default.webview,
+            name => return Err(format!("unknown mailbox: {name}")),
+        };
+        let config = MailboxConfig::parse(config, default)?;
+        // the webview is sent deltas, so dropping one corrupts its document
+        if name == "webview" && config.policy == OverflowPolicy::DropOldest {
+            return Err("the webview mailbox can't drop messages".to_owned());
+        }
+        Ok((name.to_owned(), config))
+    }
+
+    pub fn new(overrides: &[(String, MailboxConfig)]) -> Self {
+        let mut configs = Self::default();
+        for (name, config) in overrides {
+            match name.as_str() {
+                "typst" => configs.typst = *config,
+                "editor" => configs.editor = *config,
+                "webview" => configs.webview = *config,
+                _ => {}
+            }
+        }
+        configs
+    }
+}
+
+/// Messages which can be merged with a newer message of the same kind when
+/// the mailbox is full.
+pub trait Coalesce: Sized {
+    /// Merges `newer` into `self`, or gives it back if they can't be merged.
+    fn coalesce(&mut self, newer: Self) -> Option<Self> {
+        Some(newer)
+    }
+}
+
+/// Updates of the webviews are deltas, which can't be merged.
+impl Coalesce for Vec<u8> {}
+
+/// The queue depth and the overflow counters of a mailbox.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MailboxStats {
+    name: &'static str,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    depth: usize,
+    max_depth: usize,
+    sent: u64,
+    coalesced: u64,
+    dropped: u64,
+    /// How many times a sender waited for the receiver.
+    blocked: u64,
+}
+
+#[derive(Debug)]
+pub struct SendError<T>(pub T);
+
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiver_alive: bool,
+    /// Whether the receiver takes messages. Messages sent to a detached
+    /// mailbox are dropped.
+    attached: bool,
+    stats: MailboxStats,
+}
+
+struct Shared<T> {
+    config: MailboxConfig,
+    state: Mutex<State<T>>,
+    readable: Notify,
+    writable: Condvar,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap()
+    }
+}
+
+trait Metered: Send + Sync {
+    fn stats(&self) -> MailboxStats;
+}
+
+impl<T: Send> Metered for Shared<T> {
+    fn stats(&self) -> MailboxStats {
+        let state = self.lock();
+        MailboxStats {
+            depth: state.queue.len(),
+            ..state.stats.clone()
+        }
+    }
+}
+
+static MAILBOXES: Lazy<Mutex<Vec<Weak<dyn Metered>>>> = Lazy::new(Default::default);
+
+/// The statistics of the live mailboxes.
+pub fn mailbox_stats() -> Vec<MailboxStats> {
+    let mut mailboxes = MAILBOXES.lock().unwrap();
+    mailboxes.retain(|mailbox| mailbox.strong_count() > 0);
+    mailboxes
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|mailbox| mailbox.stats())
+        .collect()
+}
+
+/// Creates a mailbox of an actor, whose size is bounded by `config`.
+pub fn mailbox<T: Coalesce + Send + 'static>(
+    name: &'static str,
+    config: MailboxConfig,
+) -> (MailboxSender<T>, MailboxReceiver<T>) {
+    let shared = Arc::new(Shared {
+        config,
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            senders: 1,
+            receiver_alive: true,
+            attached: true,
+            stats: MailboxStats {
+                name,
+                capacity: config.capacity,
+                policy: config.policy,
+                depth: 0,
+                max_depth: 0,
+                sent: 0,
+                coalesced: 0,
+                dropped: 0,
+                blocked: 0,
+            },
+        }),
+        readable: Notify::new(),
+        writable: Condvar::new(),
+    });
+    let metered: Arc<dyn Metered> = shared.clone();
+    MAILBOXES.lock().unwrap().push(Arc::downgrade(&metered));
+
+    (
+        MailboxSender {
+            shared: shared.clone(),
+        },
+        MailboxReceiver { shared },
+    )
+}
+
+pub struct MailboxSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Coalesce> MailboxSender<T> {
+    /// Queues a message, applying the overflow policy if the mailbox is full.
+    /// Fails if the receiver is gone.
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.queue(msg, true)
+    }
+
+    /// Queues a message like [`Self::send`], but drops it instead of waiting
+    /// for the receiver when the mailbox is full.
+    pub fn try_send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.queue(msg, false)
+    }
+
+    fn queue(&self, mut msg: T, block: bool) -> Result<(), SendError<T>> {
+        let shared = &*self.shared;
+        let mut state = shared.lock();
+        loop {
+            if !state.receiver_alive {
+                return Err(SendError(msg));
+            }
+            if !state.attached {
+                state.stats.dropped += 1;
+                return Ok(());
+            }
+            let full = shared
+                .config
+                .capacity
+                .is_some_and(|capacity| state.queue.len() >= capacity);
+            if !full {
+                break;
+            }
+            match shared.config.policy {
+                OverflowPolicy::DropOldest => {
+                    state.queue.pop_front();
+                    state.stats.dropped += 1;
+                    break;
+                }
+                OverflowPolicy::Coalesce => {
+                    if let Some(newest) = state.queue.back_mut() {
+                        match newest.coalesce(msg) {
+                            None => {
+                                state.stats.sent += 1;
+                                state.stats.coalesced += 1;
+                                return Ok(());
+                            }
+                            Some(newer) => msg = newer,
+                        }
+                    }
+                }
+                OverflowPolicy::Block => {}
+            }
+
+            if !block {
+                state.stats.dropped += 1;
+                return Ok(());
+            }
+            if state.stats.blocked == 0 {
+                warn!("mailbox {} is full, blocking its senders", state.stats.name);
+            }
+            state.stats.blocked += 1;
+            state = wait(&shared.writable, state);
+        }
+
+        state.queue.push_back(msg);
+        state.stats.sent += 1;
+        state.stats.max_depth = state.stats.max_depth.max(state.queue.len());
+        drop(state);
+        shared.readable.notify_one();
+        Ok(())
+    }
+}
+
+/// Waits for the receiver to take messages. Other tasks of a multi-threaded
+/// runtime are moved off the blocked worker meanwhile.
+fn wait<'a, T>(writable: &Condvar, state: MutexGuard<'a, State<T>>) -> MutexGuard<'a, State<T>> {
+    match Handle::try_current().map(|handle| handle.runtime_flavor()) {
+        Ok(RuntimeFlavor::MultiThread) => {
+            tokio::task::block_in_place(|| writable.wait(state).unwrap())
+        }
+        _ => writable.wait(state).unwrap(),
+    }
+}
+
+impl<T> Clone for MailboxSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for MailboxSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.readable.notify_one();
+        }
+    }
+}
+
+pub struct MailboxReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> MailboxReceiver<T> {
+    /// Starts taking messages again after [`Self::detach`].
+    pub fn attach(&self) {
+        self.shared.lock().attached = true;
+    }
+
+    /// Drops the queued messages and the ones sent until [`Self::attach`],
+    /// for a receiver which is put aside, so its senders never wait for it.
+    pub fn detach(&self) {
+        let mut state = self.shared.lock();
+        state.attached = false;
+        state.queue.clear();
+        drop(state);
+        self.shared.writable.notify_all();
+    }
+
+    /// Takes the oldest message, or returns `None` once all senders are gone
+    /// and the mailbox is empty.
+    ///
+    /// This is cancel safe, so it can be used in `tokio::select!`.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            let readable = self.shared.readable.notified();
+            {
+                let mut state = self.shared.lock();
+                if let Some(msg) = state.queue.pop_front() {
+                    drop(state);
+                    self.shared.writable.notify_one();
+                    return Some(msg);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            readable.await;
+        }
+    }
+}
+
+impl<T> Drop for MailboxReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver_alive = false;
+        state.queue.clear();
+        drop(state);
+        self.shared.writable.notify_all();
+    }
+}
@@ -2,15 +2,16 @@ use clap::Parser;
 use log::{error, info};
 
 use typst_ts_compiler::service::CompileDriver;
-use typst_ts_compiler::TypstSystemWorld;
-use typst_ts_core::config::CompileOpts;
 
 use hyper::{
     service::{make_service_fn, service_fn},
     Error,
 };
 
-use typst_preview::{preview, CliArguments, PreviewMode, Previewer};
+use typst_preview::{
+    compile_once, compiler_driver, diff_inputs, gallery, mount_remote, preview, record_panics,
+    template_entries, watch, CliArguments, Command, PreviewMode, Previewer, WorldArgs,
+};
 
 pub fn make_static_host(
     previewer: &Previewer,
@@ -54,8 +55,96 @@ pub fn make_static_host(
 }
 
 /// Entry point.
-#[tokio::main]
-async fn main() {
+fn main() {
+    let mut arguments = CliArguments::parse();
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(threads) = arguments.runtime_threads {
+        runtime.worker_threads(threads.max(1));
+    }
+    let runtime = runtime.enable_all().build().unwrap();
+
+    if let Some(Command::CompileOnce(args)) = &mut arguments.command {
+        // only problems are logged, the diagnostics are the output
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Warn)
+            .try_init();
+        runtime.block_on(mount_remote_root(&mut args.world, false));
+        let compiler_driver = create_compiler_driver(&args.world);
+        let success = compile_once(compiler_driver, args);
+        std::process::exit(if success { 0 } else { 1 });
+    }
+
+    if let Some(Command::DiffInputs(args)) = &mut arguments.command {
+        let _ = env_logger::builder()
+            .filter_level(log::LevelFilter::Warn)
+            .try_init();
+        runtime.block_on(mount_remote_root(&mut args.world, false));
+        let compiler_driver = create_compiler_driver(&args.world);
+        let Some(report) = diff_inputs(compiler_driver, args) else {
+            std::process::exit(1);
+        };
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        } else {
+            print!("{report}");
+        }
+        return;
+    }
+
+    runtime.block_on(async move {
+        match arguments.command {
+            Some(Command::Watch(mut args)) => {
+                init_logger();
+                mount_remote_root(&mut args.world, true).await;
+                let compiler_driver = create_compiler_driver(&args.world);
+                watch(compiler_driver, args).await;
+            }
+            Some(Command::Gallery(mut args)) => {
+                init_logger();
+                mount_remote_root(&mut args.world, true).await;
+                let dir = &args.world.input;
+                let entries = match template_entries(dir) {
+                    Ok(entries) => entries,
+                    Err(err) => {
+                        error!("failed to list the templates in {:?}: {}", dir, err);
+                        std::process::exit(1);
+                    }
+                };
+                gallery(entries, &args).await;
+            }
+            _ => run(arguments).await,
+        }
+    });
+}
+
+/// Mirrors the remote root, if any, and points the world at the mirror.
+/// The mirror keeps being synced in the background if `poll` is set.
+async fn mount_remote_root(arguments: &mut WorldArgs, poll: bool) {
+    let Some(url) = &arguments.remote else {
+        return;
+    };
+    let interval = (poll && arguments.remote_poll_interval > 0)
+        .then(|| Duration::from_millis(arguments.remote_poll_interval));
+    let cache = match mount_remote(url, interval).await {
+        Ok(cache) => cache,
+        Err(err) => {
+            error!("failed to mount the remote root {}: {}", url, err);
+            std::process::exit(1);
+        }
+    };
+    arguments.root = Some(match &arguments.root {
+        Some(root) => cache.join(root.strip_prefix("/").unwrap_or(root)),
+        None => cache.clone(),
+    });
+    let input = arguments
+        .input
+        .strip_prefix("/")
+        .unwrap_or(&arguments.input);
+    arguments.input = cache.join(input);
+}
+
+fn init_logger() {
     let _ = env_logger::builder()
         // TODO: set this back to Info
         .filter_module("typst_preview", log::LevelFilter::Debug)
@@ -67,46 +156,45 @@ async fn main() {
         )
         .filter_module("typst_ts_compiler::service::watch", log::LevelFilter::Debug)
         .try_init();
-    let arguments = CliArguments::parse();
-    info!("Arguments: {:#?}", arguments);
-    let entry = if arguments.input.is_absolute() {
-        arguments.input.clone()
-    } else {
-        std::env::current_dir().unwrap().join(&arguments.input)
-    };
-    let root = if let Some(root) = &arguments.root {
-        if root.is_absolute() {
-            root.clone()
-        } else {
-            std::env::current_dir().unwrap().join(root)
-        }
-    } else {
-        std::env::current_dir().unwrap()
-    };
-    if !entry.starts_with(&root) {
-        error!("entry file must be in the root directory");
+}
+
+/// Creates the compiler of the document, exiting if it can't be created.
+fn create_compiler_driver(arguments: &WorldArgs) -> CompileDriver {
+    compiler_driver(arguments).unwrap_or_else(|err| {
+        error!("{}", err);
         std::process::exit(1);
-    }
+    })
+}
 
-    let compiler_driver = {
-        let world = TypstSystemWorld::new(CompileOpts {
-            root_dir: root.clone(),
-            font_paths: arguments.font_paths.clone(),
-            with_embedded_fonts: EMBEDDED_FONT.to_owned(),
-            ..CompileOpts::default()
-        })
-        .expect("incorrect options");
+async fn run(mut arguments: CliArguments) {
+    init_logger();
+    info!("Arguments: {:#?}", arguments);
 
-        CompileDriver::new(world).with_entry_file(entry)
-    };
+    // An interrupt while the preview starts waits for it to start, so that
+    // its state is saved. Before that, there is no state to save.
+    let shutdown_hook = Arc::new(Mutex::new(None::<Pin<Box<dyn Future<Output = ()> + Send>>>));
+    {
+        let shutdown_hook = shutdown_hook.clone();
+        tokio::spawn(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            info!("Ctrl-C received, exiting");
+            if let Some(shutdown_hook) = shutdown_hook.lock().await.take() {
+                shutdown_hook.await;
+            }
+            std::process::exit(0);
+        });
+    }
 
-    tokio::spawn(async move {
-        let _ = tokio::signal::ctrl_c().await;
-        info!("Ctrl-C received, exiting");
-        std::process::exit(0);
-    });
+    mount_remote_root(&mut arguments.world, true).await;
+    let compiler_driver = create_compiler_driver(&arguments.world);
 
-    let previewer = preview(arguments.preview, compiler_driver).await;
+    record_panics();
+    let previewer = {
+        let mut shutdown_hook = shutdown_hook.lock().await;
+        let previewer = preview(arguments.preview, compiler_driver).await;
+        *shutdown_hook = Some(Box::pin(previewer.shutdown_hook()));
+        previewer
+    };
 
     let static_file_addr = arguments.static_file_host;
     let mode = arguments.preview_mode;
@@ -121,40 +209,6 @@ async fn main() {
     let _ = tokio::join!(previewer.join(), static_server_handle);
 }
 
-use std::{borrow::Cow, net::SocketAddr};
-
-pub static EMBEDDED_FONT: &[Cow<'_, [u8]>] = &[
-    // Embed default fonts.
-    Cow::Borrowed(include_bytes!("../assets/fonts/LinLibertine_R.ttf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/LinLibertine_RB.ttf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/LinLibertine_RBI.ttf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/LinLibertine_RI.ttf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/NewCMMath-Book.otf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/NewCMMath-Regular.otf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/NewCM10-Regular.otf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/NewCM10-Bold.otf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/NewCM10-Italic.otf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/NewCM10-BoldItalic.otf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/DejaVuSansMono.ttf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/DejaVuSansMono-Bold.ttf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/DejaVuSansMono-Oblique.ttf").as_slice()),
-    Cow::Borrowed(include_bytes!("../assets/fonts/DejaVuSansMono-BoldOblique.ttf").as_slice()),
-    // Embed CJK fonts.
-    #[cfg(feature = "embedded-cjk-fonts")]
-    Cow::Borrowed(include_bytes!("../assets/fonts/InriaSerif-Bold.ttf").as_slice()),
-    #[cfg(feature = "embedded-cjk-fonts")]
-    Cow::Borrowed(include_bytes!("../assets/fonts/InriaSerif-BoldItalic.ttf").as_slice()),
-    #[cfg(feature = "embedded-cjk-fonts")]
-    Cow::Borrowed(include_bytes!("../assets/fonts/InriaSerif-Italic.ttf").as_slice()),
-    #[cfg(feature = "embedded-cjk-fonts")]
-    Cow::Borrowed(include_bytes!("../assets/fonts/InriaSerif-Regular.ttf").as_slice()),
-    #[cfg(feature = "embedded-cjk-fonts")]
-    Cow::Borrowed(include_bytes!("../assets/fonts/Roboto-Regular.ttf").as_slice()),
-    #[cfg(feature = "embedded-cjk-fonts")]
-    Cow::Borrowed(include_bytes!("../assets/fonts/NotoSerifCJKsc-Regular.otf").as_slice()),
-    // Embed emoji fonts.
-    #[cfg(feature = "embedded-emoji-fonts")]
-    Cow::Borrowed(include_bytes!("../assets/fonts/TwitterColorEmoji.ttf").as_slice()),
-    #[cfg(feature = "embedded-emoji-fonts")]
-    Cow::Borrowed(include_bytes!("../assets/fonts/NotoColorEmoji.ttf").as_slice()),
-];
+use std::{future::Future, net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
+
+use tokio::sync::Mutex;
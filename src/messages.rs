@@ -0,0 +1,147 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+/// The languages of the status messages sent to the editor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+    Es,
+    Fr,
+    Zh,
+}
+
+impl Locale {
+    /// Parses a language tag such as `de`, `de-DE` or `de_DE.UTF-8`, of
+    /// which only the language is used.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let language = raw
+            .split(|c| c == '-' || c == '_' || c == '.')
+            .next()
+            .unwrap_or_default();
+        match language.to_ascii_lowercase().as_str() {
+            "en" | "c" => Ok(Self::En),
+            "de" => Ok(Self::De),
+            "es" => Ok(Self::Es),
+            "fr" => Ok(Self::Fr),
+            "zh" => Ok(Self::Zh),
+            _ => Err(format!(
+                "unsupported locale: {raw}, expected one of en, de, es, fr and zh"
+            )),
+        }
+    }
+}
+
+/// The stable codes of the status messages, which editors match instead of
+/// the text, since the text depends on the locale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageCode {
+    #[serde(rename = "compile.compiling")]
+    Compiling,
+    #[serde(rename = "compile.success")]
+    CompileSuccess,
+    #[serde(rename = "compile.error")]
+    CompileError,
+    #[serde(rename = "render.rendering")]
+    Rendering,
+    #[serde(rename = "render.uploaded")]
+    Uploaded,
+    #[serde(rename = "frontend.versionMismatch")]
+    FrontendVersionMismatch,
+    #[serde(rename = "preview.safeMode")]
+    SafeMode,
+}
+
+impl MessageCode {
+    /// The text of the message in the given language.
+    pub fn text(self, locale: Locale) -> &'static str {
+        use Locale::*;
+        use MessageCode::*;
+        match (self, locale) {
+            (Compiling, En) => "Compiling",
+            (Compiling, De) => "Wird kompiliert",
+            (Compiling, Es) => "Compilando",
+            (Compiling, Fr) => "Compilation en cours",
+            (Compiling, Zh) => "正在编译",
+
+            (CompileSuccess, En) => "Compile succeeded",
+            (CompileSuccess, De) => "Kompilierung erfolgreich",
+            (CompileSuccess, Es) => "Compilación correcta",
+            (CompileSuccess, Fr) => "Compilation réussie",
+            (CompileSuccess, Zh) => "编译成功",
+
+            (CompileError, En) => "Compile failed",
+            (CompileError, De) => "Kompilierung fehlgeschlagen",
+            (CompileError, Es) => "Error de compilación",
+            (CompileError, Fr) => "Échec de la compilation",
+            (CompileError, Zh) => "编译失败",
+
+            (Rendering, En) => "Rendering",
+            (Rendering, De) => "Wird gerendert",
+            (Rendering, Es) => "Renderizando",
+            (Rendering, Fr) => "Rendu en cours",
+            (Rendering, Zh) => "正在渲染",
+
+            (Uploaded, En) => "Preview updated",
+            (Uploaded, De) => "Vorschau aktualisiert",
+            (Uploaded, Es) => "Vista previa actualizada",
+            (Uploaded, Fr) => "Aperçu mis à jour",
+            (Uploaded, Zh) => "预览已更新",
+
+            (FrontendVersionMismatch, En) => {
+                "The preview frontend doesn't match the preview server, please reinstall the extension or the server."
+            }
+            (FrontendVersionMismatch, De) => {
+                "Das Vorschau-Frontend passt nicht zum Vorschau-Server, bitte installieren Sie die Erweiterung oder den Server neu."
+            }
+            (FrontendVersionMismatch, Es) => {
+                "La interfaz de la vista previa no coincide con el servidor de vista previa, reinstale la extensión o el servidor."
+            }
+            (FrontendVersionMismatch, Fr) => {
+                "L'interface de l'aperçu ne correspond pas au serveur d'aperçu, veuillez réinstaller l'extension ou le serveur."
+            }
+            (FrontendVersionMismatch, Zh) => "预览前端与预览服务器不匹配，请重新安装扩展或服务器。",
+
+            (SafeMode, En) => {
+                "The preview runs in safe mode after repeated crashes, without caches, partial rendering and custom settings."
+            }
+            (SafeMode, De) => {
+                "Die Vorschau läuft nach wiederholten Abstürzen im abgesicherten Modus, ohne Caches, partielles Rendern und eigene Einstellungen."
+            }
+            (SafeMode, Es) => {
+                "La vista previa se ejecuta en modo seguro tras varios fallos, sin cachés, renderizado parcial ni ajustes personalizados."
+            }
+            (SafeMode, Fr) => {
+                "L'aperçu fonctionne en mode sans échec après des plantages répétés, sans caches, rendu partiel ni réglages personnalisés."
+            }
+            (SafeMode, Zh) => "预览多次崩溃后以安全模式运行，已禁用缓存、部分渲染和自定义设置。",
+        }
+    }
+}
+
+/// A status message with its stable code and its text in the locale of the
+/// server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusMessage {
+    pub code: MessageCode,
+    pub text: Cow<'static, str>,
+}
+
+impl StatusMessage {
+    pub fn new(code: MessageCode, locale: Locale) -> Self {
+        Self {
+            code,
+            text: Cow::Borrowed(code.text(locale)),
+        }
+    }
+}
+
+/// An event sent to a client along with its status message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Localized<T> {
+    #[serde(flatten)]
+    pub event: T,
+    pub message: StatusMessage,
+}
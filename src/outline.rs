@@ -1,6 +1,9 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
 use std::num::NonZeroUsize;
 
 use serde::Serialize;
+use siphasher::sip::SipHasher;
 use typst::foundations::{Content, NativeElement, Smart};
 use typst::introspection::Introspector;
 use typst::model::HeadingElem;
@@ -115,11 +118,143 @@ impl HeadingNode {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct Outline {
+    /// The revision of the outline, which deltas are based on.
+    revision: u64,
     items: Vec<OutlineItem>,
+    /// Whether some top-level items are left out due to the outline limit.
+    truncated: bool,
+}
+
+impl Outline {
+    pub fn set_revision(&mut self, revision: u64) {
+        self.revision = revision;
+    }
+
+    /// Keep at most `limit` items, filling the outline level by level so
+    /// that the top levels are always shown first.
+    pub fn truncated(&self, limit: usize) -> Outline {
+        let (items, truncated) = truncate_items(&self.items, limit);
+        Outline {
+            revision: self.revision,
+            items,
+            truncated,
+        }
+    }
+
+    /// The chain of items enclosing a position in the document, from the
+    /// top level down.
+    ///
+    /// An item encloses the positions from its own up to the next item of
+    /// the same or a higher level, so the chain ends with the innermost
+    /// heading before the position.
+    pub fn breadcrumbs(&self, position: &DocumentPosition) -> Vec<Breadcrumb> {
+        let mut res = vec![];
+        let mut items = &self.items;
+        while let Some(item) = items.iter().rev().find(|item| {
+            item.position
+                .as_ref()
+                .map_or(false, |start| !is_before(position, start))
+        }) {
+            res.push(Breadcrumb {
+                id: item.id.clone(),
+                title: item.title.clone(),
+                span: item.span.clone(),
+                position: item.position,
+            });
+            items = &item.children;
+        }
+        res
+    }
+
+    /// Get the children of the item `id`, or the top-level items if `id` is
+    /// `None`, keeping at most `limit` items.
+    pub fn children(&self, id: Option<&str>, limit: Option<usize>) -> Option<OutlineChildren> {
+        let items = match id {
+            Some(id) => &find_item(&self.items, id)?.children,
+            None => &self.items,
+        };
+        let (children, truncated) = truncate_items(items, limit.unwrap_or(usize::MAX));
+        Some(OutlineChildren {
+            revision: self.revision,
+            id: id.map(ToOwned::to_owned),
+            children,
+            truncated,
+        })
+    }
+}
+
+/// The children of an outline item, requested by the editor.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutlineChildren {
+    revision: u64,
+    /// The id of the parent item, or `None` for the top-level items.
+    id: Option<String>,
+    children: Vec<OutlineItem>,
+    /// Whether some children are left out due to the outline limit.
+    truncated: bool,
+}
+
+/// An outline item enclosing a source position, for breadcrumbs.
+#[derive(Debug, Clone, Serialize)]
+pub struct Breadcrumb {
+    /// The id of the outline item.
+    id: String,
+    title: String,
+    span: Option<String>,
+    position: Option<DocumentPosition>,
+}
+
+fn find_item<'a>(items: &'a [OutlineItem], id: &str) -> Option<&'a OutlineItem> {
+    items.iter().find_map(|item| {
+        if item.id == id {
+            Some(item)
+        } else {
+            find_item(&item.children, id)
+        }
+    })
+}
+
+fn truncate_items(items: &[OutlineItem], limit: usize) -> (Vec<OutlineItem>, bool) {
+    // ids of the items within the limit, in breadth-first order
+    let mut included = HashSet::new();
+    let mut queue = items.iter().collect::<VecDeque<_>>();
+    while let Some(item) = queue.pop_front() {
+        if included.len() >= limit {
+            break;
+        }
+        included.insert(item.id.as_str());
+        queue.extend(item.children.iter());
+    }
+
+    fn prune(items: &[OutlineItem], included: &HashSet<&str>) -> Vec<OutlineItem> {
+        items
+            .iter()
+            .filter(|item| included.contains(item.id.as_str()))
+            .map(|item| OutlineItem {
+                id: item.id.clone(),
+                title: item.title.clone(),
+                span: item.span.clone(),
+                position: item.position,
+                children: prune(&item.children, included),
+                truncated: item.truncated
+                    || item
+                        .children
+                        .iter()
+                        .any(|child| !included.contains(child.id.as_str())),
+            })
+            .collect()
+    }
+
+    let truncated = items
+        .iter()
+        .any(|item| !included.contains(item.id.as_str()));
+    (prune(items, &included), truncated)
 }
 
 #[derive(Debug, Clone, Serialize)]
 struct OutlineItem {
+    /// Stable id of the heading across compilations.
+    id: String,
     /// Plain text title.
     title: String,
     /// Span id in hex-format.
@@ -128,6 +263,9 @@ struct OutlineItem {
     position: Option<DocumentPosition>,
     /// The children of the outline item.
     children: Vec<OutlineItem>,
+    /// Whether some children are left out due to the outline limit, which
+    /// can be fetched by their parent's id.
+    truncated: bool,
 }
 
 pub fn outline(interner: &mut SpanInternerImpl, document: &TypstDocument) -> Outline {
@@ -138,7 +276,11 @@ pub fn outline(interner: &mut SpanInternerImpl, document: &TypstDocument) -> Out
         outline_item(interner, heading, &mut items);
     }
 
-    Outline { items }
+    Outline {
+        revision: 0,
+        items,
+        truncated: false,
+    }
 }
 
 fn outline_item(interner: &mut SpanInternerImpl, src: &HeadingNode, res: &mut Vec<OutlineItem>) {
@@ -160,10 +302,195 @@ fn outline_item(interner: &mut SpanInternerImpl, src: &HeadingNode, res: &mut Ve
 
     let span = interner.intern(span);
 
+    // the location of a heading is derived from a hash of its content, so the
+    // id changes when the heading is edited or moved across equal headings.
+    let mut hasher = SipHasher::new();
+    src.element.location().hash(&mut hasher);
+    let id = format!("{:x}", hasher.finish());
+
     res.push(OutlineItem {
+        id,
         title,
         span: Some(span.to_hex()),
         position: Some(src.position),
         children,
+        truncated: false,
     });
 }
+
+/// A flattened outline item, placed by its parent and index among siblings.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutlineEntry {
+    id: String,
+    /// The id of the parent item, or `None` for a top-level item.
+    parent: Option<String>,
+    /// The index among the children of the parent.
+    index: usize,
+    title: String,
+    span: Option<String>,
+    position: Option<DocumentPosition>,
+    /// Whether some children are left out due to the outline limit.
+    truncated: bool,
+}
+
+/// The changes between two revisions of an outline.
+///
+/// Entries are listed in pre-order of the new outline, so they can be applied
+/// one by one after the removals.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutlineDelta {
+    /// The revision this delta applies to.
+    base: u64,
+    /// The revision after applying this delta.
+    revision: u64,
+    /// Ids of the removed items.
+    removed: Vec<String>,
+    /// Items that are not in the base revision.
+    inserted: Vec<OutlineEntry>,
+    /// Items that are moved or otherwise changed. Items are moved if their
+    /// parent or their previous sibling changed, so the siblings shifted by
+    /// an insertion or a removal are not listed.
+    moved: Vec<OutlineEntry>,
+    /// New positions of the retained items which are otherwise unchanged,
+    /// e.g. the headings below the text being typed.
+    positions: HashMap<String, Option<DocumentPosition>>,
+    /// Refreshed span ids of the retained items.
+    ///
+    /// Span ids expire after a while, so they are refreshed even if the item
+    /// itself is unchanged.
+    spans: HashMap<String, Option<String>>,
+    /// Whether some top-level items are left out due to the outline limit,
+    /// if that changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<bool>,
+}
+
+impl OutlineDelta {
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty()
+            && self.inserted.is_empty()
+            && self.moved.is_empty()
+            && self.positions.is_empty()
+            && self.spans.is_empty()
+            && self.truncated.is_none()
+    }
+}
+
+fn flatten(items: &[OutlineItem], parent: Option<&str>, res: &mut Vec<OutlineEntry>) {
+    for (index, item) in items.iter().enumerate() {
+        res.push(OutlineEntry {
+            id: item.id.clone(),
+            parent: parent.map(ToOwned::to_owned),
+            index,
+            title: item.title.clone(),
+            span: item.span.clone(),
+            position: item.position,
+            truncated: item.truncated,
+        });
+        flatten(&item.children, Some(&item.id), res);
+    }
+}
+
+/// The previous sibling of each item among the siblings kept by `retained`,
+/// keyed by id.
+fn anchors<'a>(
+    entries: &'a [OutlineEntry],
+    retained: impl Fn(&OutlineEntry) -> bool,
+) -> HashMap<&'a str, Option<&'a str>> {
+    let mut anchors = HashMap::new();
+    let mut last_child = HashMap::<Option<&str>, &str>::new();
+    for entry in entries.iter().filter(|entry| retained(entry)) {
+        let parent = entry.parent.as_deref();
+        anchors.insert(entry.id.as_str(), last_child.get(&parent).copied());
+        last_child.insert(parent, entry.id.as_str());
+    }
+    anchors
+}
+
+fn same_position(x: &Option<DocumentPosition>, y: &Option<DocumentPosition>) -> bool {
+    match (x, y) {
+        (Some(x), Some(y)) => x.page_no == y.page_no && x.x == y.x && x.y == y.y,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Whether `x` comes before `y` in reading order, going by lines and then
+/// from left to right.
+fn is_before(x: &DocumentPosition, y: &DocumentPosition) -> bool {
+    (x.page_no, x.y, x.x) < (y.page_no, y.y, y.x)
+}
+
+/// Compute the changes from `prev` to `next`.
+pub fn outline_delta(prev: &Outline, next: &Outline) -> OutlineDelta {
+    let mut prev_entries = vec![];
+    flatten(&prev.items, None, &mut prev_entries);
+    let mut next_entries = vec![];
+    flatten(&next.items, None, &mut next_entries);
+
+    // items are placed after the same sibling as before, unless they are
+    // moved, ignoring the siblings inserted or removed in between
+    let same_parent = |x: &HashMap<&str, &OutlineEntry>, entry: &OutlineEntry| {
+        x.get(entry.id.as_str())
+            .is_some_and(|other| other.parent == entry.parent)
+    };
+    let prev_by_id = prev_entries.iter().map(|e| (e.id.as_str(), e)).collect();
+    let next_by_id = next_entries.iter().map(|e| (e.id.as_str(), e)).collect();
+    let prev_anchors = anchors(&prev_entries, |e| same_parent(&next_by_id, e));
+    let next_anchors = anchors(&next_entries, |e| same_parent(&prev_by_id, e));
+    let moved_ids = next_entries
+        .iter()
+        .filter(|e| prev_anchors.get(e.id.as_str()) != next_anchors.get(e.id.as_str()))
+        .map(|e| e.id.clone())
+        .collect::<HashSet<_>>();
+
+    let prev_entries: HashMap<_, _> = prev_entries.iter().map(|e| (e.id.clone(), e)).collect();
+
+    let next_ids = next_entries
+        .iter()
+        .map(|e| e.id.as_str())
+        .collect::<HashSet<_>>();
+    let mut removed = prev_entries
+        .keys()
+        .filter(|id| !next_ids.contains(id.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    removed.sort();
+
+    let mut inserted = vec![];
+    let mut moved = vec![];
+    let mut positions = HashMap::new();
+    let mut spans = HashMap::new();
+    for entry in next_entries {
+        let Some(prev) = prev_entries.get(&entry.id) else {
+            inserted.push(entry);
+            continue;
+        };
+
+        if prev.parent != entry.parent
+            || moved_ids.contains(&entry.id)
+            || prev.title != entry.title
+            || prev.truncated != entry.truncated
+        {
+            moved.push(entry);
+            continue;
+        }
+        if !same_position(&prev.position, &entry.position) {
+            positions.insert(entry.id.clone(), entry.position);
+        }
+        if prev.span != entry.span {
+            spans.insert(entry.id, entry.span);
+        }
+    }
+
+    OutlineDelta {
+        base: prev.revision,
+        revision: next.revision,
+        removed,
+        inserted,
+        moved,
+        positions,
+        spans,
+        truncated: (prev.truncated != next.truncated).then_some(next.truncated),
+    }
+}
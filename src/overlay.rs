@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use typst_ts_core::TypstDocument;
+
+use crate::source_map::page_items;
+
+/// The layout overlays shown in the webviews.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Overlays {
+    /// The bounds of the content laid out on each page.
+    #[serde(default)]
+    pub content_bounds: bool,
+    /// Lines along the baselines of the text.
+    #[serde(default)]
+    pub baseline_grid: bool,
+    /// The bounding boxes of the rendered elements.
+    #[serde(default)]
+    pub bounding_boxes: bool,
+}
+
+impl Overlays {
+    pub fn is_empty(&self) -> bool {
+        !self.content_bounds && !self.baseline_grid && !self.bounding_boxes
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OverlayKind {
+    ContentBounds,
+    Baseline,
+    BoundingBox,
+}
+
+/// A shape of an overlay on a page.
+#[derive(Debug, Clone, Serialize)]
+pub struct OverlayShape {
+    kind: OverlayKind,
+    /// The 1-based page number.
+    page: usize,
+    /// `[x, y, width, height]` in points, where baselines have no height.
+    rect: [f64; 4],
+}
+
+/// Compute the shapes of the enabled overlays from the frames of the
+/// document.
+///
+/// Frames don't keep the page margins, so the content bounds are the extent
+/// of the content actually laid out on the page, which is within the margins
+/// unless the content overflows them.
+pub fn overlay(document: &TypstDocument, overlays: Overlays) -> Vec<OverlayShape> {
+    let mut shapes = vec![];
+    if overlays.is_empty() {
+        return shapes;
+    }
+
+    for (idx, page) in document.pages.iter().enumerate() {
+        let page_no = idx + 1;
+        let items = page_items(page_no, page);
+
+        if overlays.content_bounds && !items.is_empty() {
+            let (mut min_x, mut min_y) = (f64::INFINITY, f64::INFINITY);
+            let (mut max_x, mut max_y) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+            for item in &items {
+                let [x, y, w, h] = item.rect;
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x + w);
+                max_y = max_y.max(y + h);
+            }
+            shapes.push(OverlayShape {
+                kind: OverlayKind::ContentBounds,
+                page: page_no,
+                rect: [min_x, min_y, max_x - min_x, max_y - min_y],
+            });
+        }
+
+        if overlays.baseline_grid {
+            let mut baselines = items
+                .iter()
+                .filter_map(|item| item.baseline)
+                .collect::<Vec<_>>();
+            baselines.sort_by(f64::total_cmp);
+            // lines of text share a baseline up to rounding errors
+            baselines.dedup_by(|a, b| (*a - *b).abs() < 0.5);
+            let width = page.width().to_pt();
+            shapes.extend(baselines.into_iter().map(|y| OverlayShape {
+                kind: OverlayKind::Baseline,
+                page: page_no,
+                rect: [0., y, width, 0.],
+            }));
+        }
+
+        if overlays.bounding_boxes {
+            shapes.extend(items.iter().map(|item| OverlayShape {
+                kind: OverlayKind::BoundingBox,
+                page: page_no,
+                rect: item.rect,
+            }));
+        }
+    }
+
+    shapes
+}
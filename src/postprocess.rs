@@ -0,0 +1,163 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, RwLock};
+
+use log::error;
+use once_cell::sync::Lazy;
+
+/// The kinds of artifacts which can be post-processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    /// The encoded update of the document sent to the webviews, which is a
+    /// `new,` or `diff-v1,` message that must stay decodable by the webview.
+    Webview,
+    /// An SVG file written to disk, either a page of the `svg-files`
+    /// renderer or an export.
+    Svg,
+    /// A page rasterized as PNG for the editor.
+    Png,
+    /// A PDF file exported by `watch` or `compile-once`.
+    Pdf,
+}
+
+impl ArtifactKind {
+    fn parse(raw: &str) -> Result<Self, String> {
+        match raw {
+            "webview" => Ok(Self::Webview),
+            "svg" => Ok(Self::Svg),
+            "png" => Ok(Self::Png),
+            "pdf" => Ok(Self::Pdf),
+            _ => Err(format!(
+                "unknown artifact kind: {raw}, expected webview, svg, png or pdf"
+            )),
+        }
+    }
+}
+
+/// An artifact given to the post-processors.
+#[derive(Debug, Clone, Copy)]
+pub struct Artifact<'a> {
+    pub kind: ArtifactKind,
+    /// The file the artifact is written to, if it is written to disk.
+    pub path: Option<&'a Path>,
+}
+
+/// Transforms artifacts before they are sent to the webviews, written to
+/// disk or sent to the editor, e.g. to stamp a watermark.
+///
+/// Post-processors run on the thread producing the artifact, in the order
+/// they were registered, each given the output of the previous one.
+pub trait PostProcessor: Send + Sync {
+    /// Whether the post-processor applies to the artifacts of this kind.
+    fn accepts(&self, kind: ArtifactKind) -> bool;
+
+    /// Returns the processed artifact. An error keeps the artifact as it
+    /// was given.
+    fn process(&self, artifact: &Artifact, data: Vec<u8>) -> Result<Vec<u8>, String>;
+}
+
+/// A command given the artifact on stdin, which writes the processed
+/// artifact to stdout.
+#[derive(Debug, Clone)]
+pub struct ExternalCommand {
+    kind: ArtifactKind,
+    program: String,
+    args: Vec<String>,
+}
+
+impl ExternalCommand {
+    /// Parses `KIND=COMMAND`, where the command is split at whitespace into
+    /// the program and its arguments, without any shell.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let (kind, command) = raw
+            .split_once('=')
+            .ok_or("post-processor must be a kind and a command separated by an equal sign")?;
+        let mut words = command.split_whitespace().map(str::to_owned);
+        let program = words.next().ok_or("post-processor command is empty")?;
+        Ok(Self {
+            kind: ArtifactKind::parse(kind)?,
+            program,
+            args: words.collect(),
+        })
+    }
+}
+
+impl PostProcessor for ExternalCommand {
+    fn accepts(&self, kind: ArtifactKind) -> bool {
+        self.kind == kind
+    }
+
+    fn process(&self, artifact: &Artifact, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        if let Some(path) = artifact.path {
+            command.env("TYPST_PREVIEW_ARTIFACT_PATH", path);
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|err| format!("failed to run {}: {err}", self.program))?;
+
+        // writes from another thread, so that a command writing its output
+        // before reading all of its input doesn't deadlock
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = std::thread::spawn(move || stdin.write_all(&data));
+        let output = child
+            .wait_with_output()
+            .map_err(|err| format!("failed to run {}: {err}", self.program))?;
+        let _ = writer.join();
+
+        if !output.status.success() {
+            return Err(format!("{} exited with {}", self.program, output.status));
+        }
+        Ok(output.stdout)
+    }
+}
+
+static POST_PROCESSORS: Lazy<RwLock<Vec<Arc<dyn PostProcessor>>>> = Lazy::new(Default::default);
+
+/// Registers a post-processor, which applies to the artifacts of every
+/// preview and export produced from then on.
+pub fn register_post_processor(post_processor: Arc<dyn PostProcessor>) {
+    POST_PROCESSORS.write().unwrap().push(post_processor);
+}
+
+/// The post-processors of a preview or an export: the registered ones,
+/// followed by the commands given on its command line.
+#[derive(Clone, Default)]
+pub struct PostProcessors {
+    commands: Arc<Vec<ExternalCommand>>,
+}
+
+impl PostProcessors {
+    pub fn new(commands: &[ExternalCommand]) -> Self {
+        Self {
+            commands: Arc::new(commands.to_vec()),
+        }
+    }
+
+    /// Runs the post-processors accepting the artifact, skipping the ones
+    /// which fail.
+    pub fn run(&self, artifact: Artifact, mut data: Vec<u8>) -> Vec<u8> {
+        let registered = POST_PROCESSORS.read().unwrap().clone();
+        let commands = self
+            .commands
+            .iter()
+            .map(|command| command as &dyn PostProcessor);
+        for post_processor in registered.iter().map(|p| &**p).chain(commands) {
+            if !post_processor.accepts(artifact.kind) {
+                continue;
+            }
+            // keeps a copy to fall back to, as the post-processor takes the data
+            match post_processor.process(&artifact, data.clone()) {
+                Ok(processed) => data = processed,
+                Err(err) => error!("post-processor failed on {:?}: {}", artifact.kind, err),
+            }
+        }
+        data
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use log::warn;
+use percent_encoding::percent_decode_str;
+
+/// The latest version of the preview protocol spoken by this server.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+const PROTOCOL_PREFIX: &str = "typst-preview.";
+
+/// The kind of client on the other side of a websocket connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// An editor driving the preview over the control plane.
+    Editor,
+    /// A webview rendering the document and sending interactions back.
+    Webview,
+    /// A webview that only renders the document and never drives the
+    /// editor or other webviews.
+    Viewer,
+}
+
+impl ChannelKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Editor => "editor",
+            Self::Webview => "webview",
+            Self::Viewer => "viewer",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "editor" => Some(Self::Editor),
+            "webview" => Some(Self::Webview),
+            "viewer" => Some(Self::Viewer),
+            _ => None,
+        }
+    }
+}
+
+/// The listener a connection arrives on, which decides the kinds of channel
+/// it may open. The data plane can be exposed to remote viewers, so it never
+/// accepts an editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Plane {
+    Control,
+    Data,
+}
+
+impl Plane {
+    /// The kind of the clients that offer no subprotocol at all.
+    pub fn fallback(self) -> ChannelKind {
+        match self {
+            Self::Control => ChannelKind::Editor,
+            Self::Data => ChannelKind::Webview,
+        }
+    }
+
+    pub fn accepts(self, kind: ChannelKind) -> bool {
+        match self {
+            Self::Control => kind == ChannelKind::Editor,
+            Self::Data => matches!(kind, ChannelKind::Webview | ChannelKind::Viewer),
+        }
+    }
+}
+
+impl fmt::Display for Plane {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Control => "control plane",
+            Self::Data => "data plane",
+        })
+    }
+}
+
+/// A websocket subprotocol (`Sec-WebSocket-Protocol`) identifying the kind
+/// of channel and the protocol version, e.g. `typst-preview.webview.v1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelProtocol {
+    pub kind: ChannelKind,
+    pub version: u32,
+}
+
+impl ChannelProtocol {
+    /// The protocol assumed for clients that offer no subprotocol at all.
+    pub fn fallback(kind: ChannelKind) -> Self {
+        Self {
+            kind,
+            version: PROTOCOL_VERSION,
+        }
+    }
+
+    /// Parses a single subprotocol name.
+    pub fn parse(name: &str) -> Option<Self> {
+        let rest = name.trim().strip_prefix(PROTOCOL_PREFIX)?;
+        let (kind, version) = rest.split_once('.')?;
+        let kind = ChannelKind::from_str(kind)?;
+        let version = version.strip_prefix('v')?.parse().ok()?;
+        Some(Self { kind, version })
+    }
+
+    /// Picks the first protocol we support from the comma separated list
+    /// offered by the client.
+    pub fn negotiate(offered: &str) -> Option<Self> {
+        offered
+            .split(',')
+            .filter_map(Self::parse)
+            .find(|protocol| protocol.version >= 1 && protocol.version <= PROTOCOL_VERSION)
+    }
+}
+
+impl fmt::Display for ChannelProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{PROTOCOL_PREFIX}{}.v{}",
+            self.kind.as_str(),
+            self.version
+        )
+    }
+}
+
+/// The kinds of events pushed to the editor without being asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// The outline of the document and its deltas.
+    Outline,
+    /// The compile status of the document.
+    Diagnostics,
+    /// Jumps to the source of elements clicked in the webviews.
+    Jump,
+    /// The pages visible in the webviews.
+    VisiblePages,
+    /// Review comments.
+    Comments,
+    /// The statistics of the updates sent to the webviews, if enabled.
+    Stats,
+    /// The summary of the session sent on exit.
+    Session,
+    /// Small images of the pages of every new document, which are only
+    /// rendered for clients subscribing to them.
+    Thumbnails,
+}
+
+impl EventKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Outline => "outline",
+            Self::Diagnostics => "diagnostics",
+            Self::Jump => "jump",
+            Self::VisiblePages => "visiblePages",
+            Self::Comments => "comments",
+            Self::Stats => "stats",
+            Self::Session => "session",
+            Self::Thumbnails => "thumbnails",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "outline" => Some(Self::Outline),
+            "diagnostics" => Some(Self::Diagnostics),
+            "jump" => Some(Self::Jump),
+            "visiblePages" => Some(Self::VisiblePages),
+            "comments" => Some(Self::Comments),
+            "stats" => Some(Self::Stats),
+            "session" => Some(Self::Session),
+            "thumbnails" => Some(Self::Thumbnails),
+            _ => None,
+        }
+    }
+}
+
+/// The events a control plane client subscribed to during the handshake,
+/// given by the `subscribe` query parameter, e.g. `?subscribe=outline,jump`.
+/// Clients that don't subscribe receive all events but the thumbnails, which
+/// are costly to render.
+#[derive(Debug, Clone, Default)]
+pub struct Subscriptions(Option<HashSet<EventKind>>);
+
+impl Subscriptions {
+    /// Parses the subscriptions from the query of the handshake request.
+    pub fn parse(query: Option<&str>) -> Self {
+        let Some(kinds) = query
+            .into_iter()
+            .flat_map(|q| q.split('&'))
+            .find_map(|pair| pair.strip_prefix("subscribe="))
+        else {
+            return Self::default();
+        };
+
+        let kinds = percent_decode_str(kinds).decode_utf8_lossy();
+        let kinds = kinds
+            .split(',')
+            .filter(|kind| !kind.is_empty())
+            .filter_map(|kind| {
+                let parsed = EventKind::from_str(kind);
+                if parsed.is_none() {
+                    warn!("Ignoring subscription to unknown event kind: {}", kind);
+                }
+                parsed
+            })
+            .collect();
+        Self(Some(kinds))
+    }
+
+    pub fn contains(&self, kind: EventKind) -> bool {
+        match &self.0 {
+            Some(kinds) => kinds.contains(&kind),
+            None => kind != EventKind::Thumbnails,
+        }
+    }
+}
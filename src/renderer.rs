@@ -0,0 +1,190 @@
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+
+use log::error;
+use siphasher::sip128::{Hasher128, SipHasher13};
+use typst::layout::Frame;
+use typst::model::Document;
+use typst_ts_core::debug_loc::{ElementPoint, SourceSpanOffset};
+use typst_ts_core::error::prelude::ZResult;
+use typst_ts_svg_exporter::IncrSvgDocServer;
+
+use crate::postprocess::{Artifact, ArtifactKind, PostProcessors};
+
+/// Produces the artifacts sent to a webview from the compiled documents.
+pub trait Renderer: Send {
+    /// Packs the latest rendered document as a whole, if any.
+    fn pack_current(&mut self) -> Option<Vec<u8>>;
+
+    /// Packs the changes from the latest rendered document to `document`.
+    fn pack_delta(&mut self, document: Arc<Document>) -> Vec<u8>;
+
+    /// Resolves the spans of the element at a path in the rendered artifact.
+    /// Backends without element paths resolve nothing.
+    fn resolve_span_by_element_path(
+        &mut self,
+        _path: &[ElementPoint],
+    ) -> ZResult<Option<(SourceSpanOffset, SourceSpanOffset)>> {
+        Ok(None)
+    }
+
+    /// Resolves the paths of the elements rendered from a span.
+    fn resolve_element_paths_by_span(
+        &mut self,
+        _span: SourceSpanOffset,
+    ) -> ZResult<Vec<Vec<ElementPoint>>> {
+        Ok(vec![])
+    }
+}
+
+impl Renderer for IncrSvgDocServer {
+    fn pack_current(&mut self) -> Option<Vec<u8>> {
+        IncrSvgDocServer::pack_current(self)
+    }
+
+    fn pack_delta(&mut self, document: Arc<Document>) -> Vec<u8> {
+        IncrSvgDocServer::pack_delta(self, document)
+    }
+
+    fn resolve_span_by_element_path(
+        &mut self,
+        path: &[ElementPoint],
+    ) -> ZResult<Option<(SourceSpanOffset, SourceSpanOffset)>> {
+        IncrSvgDocServer::resolve_span_by_element_path(self, path)
+    }
+
+    fn resolve_element_paths_by_span(
+        &mut self,
+        span: SourceSpanOffset,
+    ) -> ZResult<Vec<Vec<ElementPoint>>> {
+        IncrSvgDocServer::resolve_element_paths_by_span(self, span)
+    }
+}
+
+/// Writes the pages of the compiled documents as plain SVG files, once for
+/// all webviews, on a thread of its own so that neither the compilations nor
+/// the webviews are held up by the files.
+pub struct SvgFilesWriter {
+    sender: mpsc::Sender<Arc<Document>>,
+}
+
+impl SvgFilesWriter {
+    pub fn spawn(dir: PathBuf, post_processors: PostProcessors) -> Self {
+        if let Err(err) = std::fs::create_dir_all(&dir) {
+            error!("SvgFilesWriter: failed to create {:?}: {}", dir, err);
+        }
+        let (sender, receiver) = mpsc::channel::<Arc<Document>>();
+        std::thread::Builder::new()
+            .name("SvgFilesWriter".to_owned())
+            .spawn(move || {
+                // the content hashes of the written pages, by page index
+                let mut written = vec![];
+                while let Ok(mut document) = receiver.recv() {
+                    // documents compiled while writing are outdated already
+                    while let Ok(newer) = receiver.try_recv() {
+                        document = newer;
+                    }
+                    written = write_svg_pages(&document, &dir, &written, &post_processors);
+                }
+            })
+            .unwrap();
+        Self { sender }
+    }
+
+    /// Queues a document to write, without waiting for the previous one to
+    /// be written.
+    pub fn write(&self, document: Arc<Document>) {
+        let _ = self.sender.send(document);
+    }
+}
+
+/// Encodes the pages in parallel, writing each page as soon as it is
+/// encoded, so that the first pages are available before the whole document
+/// is encoded.
+///
+/// Pages whose content hash is the same as the one of the page written at
+/// the same index are neither encoded nor written again. Returns the content
+/// hashes of the pages of `document`, or `None` for the pages which failed
+/// to be written, so that they are written again with the next document.
+fn write_svg_pages(
+    document: &Document,
+    dir: &Path,
+    written: &[Option<u128>],
+    post_processors: &PostProcessors,
+) -> Vec<Option<u128>> {
+    let mut hashes = document
+        .pages
+        .iter()
+        .map(|page| Some(page_hash(page)))
+        .collect::<Vec<_>>();
+
+    let workers = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let next = AtomicUsize::new(0);
+    let failed = std::thread::scope(|s| {
+        let workers = (0..workers.min(document.pages.len()))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut failed = vec![];
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(page) = document.pages.get(idx) else {
+                            break;
+                        };
+                        if written.get(idx) == Some(&hashes[idx]) {
+                            continue;
+                        }
+                        let single = Document {
+                            pages: vec![page.clone()],
+                            ..Document::default()
+                        };
+                        let svg = typst_ts_svg_exporter::render_svg(&single);
+                        let path = dir.join(format!("page-{}.svg", idx + 1));
+                        let artifact = Artifact {
+                            kind: ArtifactKind::Svg,
+                            path: Some(&path),
+                        };
+                        let svg = post_processors.run(artifact, svg.into_bytes());
+                        if let Err(err) = std::fs::write(&path, svg) {
+                            error!("SvgFilesWriter: failed to write {:?}: {}", path, err);
+                            failed.push(idx);
+                        }
+                    }
+                    failed
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_default())
+            .collect::<Vec<_>>()
+    });
+    for idx in failed {
+        hashes[idx] = None;
+    }
+
+    // removes the pages left by a longer revision of the document
+    for page_no in document.pages.len() + 1.. {
+        if std::fs::remove_file(dir.join(format!("page-{page_no}.svg"))).is_err() {
+            break;
+        }
+    }
+
+    hashes
+}
+
+/// Hashes the content of a page.
+pub(crate) fn page_hash(page: &Frame) -> u128 {
+    let mut hasher = SipHasher13::new();
+    page.hash(&mut hasher);
+    hasher.finish128().as_u128()
+}
+
+/// Creates the renderer of a webview.
+pub fn create_renderer() -> Box<dyn Renderer> {
+    let mut vector = IncrSvgDocServer::default();
+    vector.set_should_attach_debug_info(true);
+    Box::new(vector)
+}
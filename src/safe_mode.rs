@@ -0,0 +1,188 @@
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::debug_loc::project_id;
+
+/// The number of crashes in a row after which a document is previewed in
+/// safe mode.
+const SAFE_MODE_THRESHOLD: usize = 3;
+
+/// What is kept across the launches of the preview of a document to detect
+/// repeated crashes.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CrashState {
+    /// Whether a preview is running, which is still set after a crash.
+    running: bool,
+    /// The number of previews in a row which crashed or panicked.
+    crashes: usize,
+    /// Whether the running preview was already counted as a crash.
+    counted: bool,
+}
+
+struct StateFile {
+    /// Tells the previews apart, so that only the one which owns the file
+    /// records its exit.
+    id: u64,
+    path: PathBuf,
+    state: CrashState,
+}
+
+impl StateFile {
+    fn save(&self) {
+        let res = std::fs::create_dir_all(self.path.parent().unwrap())
+            .and_then(|_| std::fs::write(&self.path, serde_json::to_vec(&self.state).unwrap()));
+        if let Err(err) = res {
+            error!("SafeMode: failed to write {:?}: {}", self.path, err);
+        }
+    }
+}
+
+/// The state of the preview whose crashes are tracked. A panic can't be
+/// told apart between concurrent previews, so only one of them is tracked at
+/// a time.
+static STATE_FILE: Mutex<Option<StateFile>> = Mutex::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Whether the thread runs a step of a supervised actor, whose panics are
+    /// caught.
+    static SUPERVISED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The crash tracking of a preview, which ends with [`Self::clean_exit`].
+#[derive(Debug, Clone)]
+pub struct CrashTracking {
+    id: u64,
+}
+
+/// Why the preview runs in safe mode and what it disables, as reported to
+/// the editor and the webviews.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafeModeReport {
+    /// The number of previews of the document in a row which crashed.
+    crashes: usize,
+    /// Whether safe mode was asked for with `--safe-mode`.
+    forced: bool,
+    disabled: Vec<&'static str>,
+}
+
+/// Records the launch of a preview of `entry`, and decides whether it runs in
+/// safe mode, which it does after [`SAFE_MODE_THRESHOLD`] crashes in a row or
+/// if `force` is set.
+///
+/// A preview counts as crashed if it doesn't exit through
+/// [`CrashTracking::clean_exit`], or if a panic escapes supervision once
+/// [`record_panics`] is installed. Crashes are not tracked while another
+/// preview of the process is.
+pub fn start(
+    root: &Path,
+    entry: &Path,
+    force: bool,
+) -> (Option<SafeModeReport>, Option<CrashTracking>) {
+    let path = dirs::cache_dir().map(|dir| {
+        dir.join("typst-preview")
+            .join(project_id(root))
+            .join(format!("{}.state.json", project_id(entry)))
+    });
+    let mut file = lock();
+    let (Some(path), None) = (path, &*file) else {
+        return (force.then(|| report(0, true)), None);
+    };
+
+    let mut state: CrashState = std::fs::read(&path)
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default();
+    if state.running && !state.counted {
+        state.crashes += 1;
+    }
+    state.running = true;
+    state.counted = false;
+    let crashes = state.crashes;
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let new_file = file.insert(StateFile { id, path, state });
+    new_file.save();
+    let tracking = Some(CrashTracking { id });
+
+    if !force && crashes < SAFE_MODE_THRESHOLD {
+        return (None, tracking);
+    }
+    warn!(
+        "SafeMode: the preview crashed {} times in a row, starting in safe mode",
+        crashes
+    );
+    (Some(report(crashes, force)), tracking)
+}
+
+fn lock() -> MutexGuard<'static, Option<StateFile>> {
+    STATE_FILE.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn report(crashes: usize, forced: bool) -> SafeModeReport {
+    SafeModeReport {
+        crashes,
+        forced,
+        disabled: vec!["spanCache", "partialRendering", "customConfig"],
+    }
+}
+
+/// Installs a panic hook which counts the panics escaping the supervision of
+/// the actors as crashes of the tracked preview.
+pub fn record_panics() {
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if !SUPERVISED.with(Cell::get) {
+            record_panic();
+        }
+        hook(info)
+    }));
+}
+
+/// Runs `f` as a step of a supervised actor, whose panics are not counted as
+/// crashes.
+pub fn supervised<R>(f: impl FnOnce() -> R) -> R {
+    let outer = SUPERVISED.with(|supervised| supervised.replace(true));
+    let res = f();
+    SUPERVISED.with(|supervised| supervised.set(outer));
+    res
+}
+
+fn record_panic() {
+    // the panic may have happened while the state was saved
+    let Ok(mut file) = STATE_FILE.try_lock() else {
+        return;
+    };
+    let Some(file) = &mut *file else {
+        return;
+    };
+    if !file.state.counted {
+        file.state.crashes += 1;
+        file.state.counted = true;
+        file.save();
+    }
+}
+
+impl CrashTracking {
+    /// Records that the preview exits normally. The crash count starts over
+    /// unless the preview panicked meanwhile, and the crashes of the next
+    /// preview are tracked.
+    pub fn clean_exit(&self) {
+        let mut file = lock();
+        if !file.as_ref().is_some_and(|file| file.id == self.id) {
+            return;
+        }
+        let mut file = file.take().unwrap();
+        file.state.running = false;
+        if !file.state.counted {
+            file.state.crashes = 0;
+        }
+        file.save();
+    }
+}
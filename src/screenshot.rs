@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tiny_skia::IntRect;
+use typst::visualize::Color;
+use typst_ts_core::TypstDocument;
+
+use crate::postprocess::{Artifact, ArtifactKind, PostProcessors};
+
+fn default_ppi() -> f32 {
+    144.0
+}
+
+/// The resolution of the thumbnails, a quarter of a pixel per point.
+const THUMBNAIL_PPI: f32 = 18.0;
+
+/// The largest page to rasterize, in pixels, which takes 160 MB. An A4 page
+/// at 600 pixels per inch is 35 million pixels.
+const MAX_PIXELS: f64 = 40_000_000.0;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScreenshotRequest {
+    /// The 1-based page number, which defaults to the first page visible in
+    /// the webview.
+    pub page: Option<usize>,
+    /// `[x, y, width, height]` in points of the part of the page to take,
+    /// which defaults to the whole page.
+    pub rect: Option<[f64; 4]>,
+    /// The resolution in pixels per inch.
+    #[serde(default = "default_ppi")]
+    pub ppi: f32,
+    /// The PNG file to write, or `None` to return the PNG encoded in base64.
+    pub path: Option<PathBuf>,
+}
+
+/// A rasterized page, or why it couldn't be taken.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Screenshot {
+    /// The 1-based page number.
+    page: usize,
+    /// The size of the image in pixels.
+    width: u32,
+    height: u32,
+    /// The file the PNG was written to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+    /// The PNG encoded in base64, if it was not written to a file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Rasterizes a page of the document, or a part of it, as PNG.
+pub(crate) fn screenshot(
+    document: &TypstDocument,
+    page: usize,
+    req: &ScreenshotRequest,
+    post_processors: &PostProcessors,
+) -> Screenshot {
+    match take(document, page, req, post_processors) {
+        Ok(screenshot) => screenshot,
+        Err(error) => Screenshot {
+            page,
+            error: Some(error),
+            ..Screenshot::default()
+        },
+    }
+}
+
+/// Rasterizes every page of the document as a small PNG.
+pub fn thumbnails(document: &TypstDocument) -> Vec<Screenshot> {
+    let req = ScreenshotRequest {
+        page: None,
+        rect: None,
+        ppi: THUMBNAIL_PPI,
+        path: None,
+    };
+    let post_processors = PostProcessors::default();
+    (1..=document.pages.len())
+        .map(|page| screenshot(document, page, &req, &post_processors))
+        .collect()
+}
+
+fn take(
+    document: &TypstDocument,
+    page: usize,
+    req: &ScreenshotRequest,
+    post_processors: &PostProcessors,
+) -> Result<Screenshot, String> {
+    let frame = page
+        .checked_sub(1)
+        .and_then(|idx| document.pages.get(idx))
+        .ok_or_else(|| format!("page {page} doesn't exist"))?;
+    if !req.ppi.is_finite() || req.ppi <= 0.0 {
+        return Err(format!("invalid resolution: {}", req.ppi));
+    }
+    let pixel_per_pt = req.ppi / 72.0;
+    // the whole page is rasterized, even for a part of it
+    let size = frame.size();
+    let width = (size.x.to_pt() * pixel_per_pt as f64).ceil();
+    let height = (size.y.to_pt() * pixel_per_pt as f64).ceil();
+    if width * height > MAX_PIXELS {
+        return Err(format!(
+            "the page would be {width}x{height} pixels at {} pixels per inch, which is too large",
+            req.ppi
+        ));
+    }
+    let mut pixmap = typst_render::render(frame, pixel_per_pt, Color::WHITE);
+
+    if let Some([x, y, w, h]) = req.rect {
+        let scale = pixel_per_pt as f64;
+        let rect = IntRect::from_xywh(
+            (x * scale).round() as i32,
+            (y * scale).round() as i32,
+            (w * scale).round() as u32,
+            (h * scale).round() as u32,
+        )
+        .ok_or("the rectangle is empty")?;
+        pixmap = pixmap
+            .clone_rect(rect)
+            .ok_or("the rectangle is outside of the page")?;
+    }
+
+    let png = pixmap.encode_png().map_err(|err| err.to_string())?;
+    let png = post_processors.run(
+        Artifact {
+            kind: ArtifactKind::Png,
+            path: req.path.as_deref(),
+        },
+        png,
+    );
+    let mut screenshot = Screenshot {
+        page,
+        width: pixmap.width(),
+        height: pixmap.height(),
+        ..Screenshot::default()
+    };
+    match &req.path {
+        Some(path) => {
+            std::fs::write(path, png).map_err(|err| format!("failed to write {path:?}: {err}"))?;
+            screenshot.path = Some(path.clone());
+        }
+        None => {
+            screenshot.data = Some(base64::engine::general_purpose::STANDARD.encode(png));
+        }
+    }
+    Ok(screenshot)
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{error, info};
+use serde::Serialize;
+
+/// The number of files listed in the most edited files of a summary.
+const MOST_EDITED_FILES: usize = 10;
+
+/// Counts what happens during a preview session, to summarize it on exit.
+#[derive(Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+    /// Where to write the summary on exit, if anywhere.
+    summary_path: Option<PathBuf>,
+}
+
+struct SessionState {
+    started: Instant,
+    started_at: u64,
+    compiles: usize,
+    errors: usize,
+    compile_time: Duration,
+    edits: HashMap<PathBuf, usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSummary {
+    /// The start of the session in milliseconds since the Unix epoch.
+    started_at: u64,
+    /// The time since the start of the session in seconds.
+    preview_secs: f64,
+    compiles: usize,
+    /// The number of failed compilations.
+    errors: usize,
+    average_compile_ms: f64,
+    most_edited_files: Vec<EditedFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EditedFile {
+    path: PathBuf,
+    /// The number of edits received from the editor.
+    edits: usize,
+}
+
+impl Session {
+    pub fn new(summary_path: Option<PathBuf>) -> Self {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        Self {
+            state: Arc::new(Mutex::new(SessionState {
+                started: Instant::now(),
+                started_at,
+                compiles: 0,
+                errors: 0,
+                compile_time: Duration::ZERO,
+                edits: HashMap::new(),
+            })),
+            summary_path,
+        }
+    }
+
+    pub fn record_compile(&self, elapsed: Duration, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.compiles += 1;
+        state.compile_time += elapsed;
+        if !success {
+            state.errors += 1;
+        }
+    }
+
+    pub fn record_edits<'a>(&self, paths: impl IntoIterator<Item = &'a PathBuf>) {
+        let mut state = self.state.lock().unwrap();
+        for path in paths {
+            *state.edits.entry(path.clone()).or_default() += 1;
+        }
+    }
+
+    pub fn summary(&self) -> SessionSummary {
+        let state = self.state.lock().unwrap();
+        let mut most_edited_files = state
+            .edits
+            .iter()
+            .map(|(path, edits)| EditedFile {
+                path: path.clone(),
+                edits: *edits,
+            })
+            .collect::<Vec<_>>();
+        most_edited_files.sort_by(|a, b| b.edits.cmp(&a.edits).then(a.path.cmp(&b.path)));
+        most_edited_files.truncate(MOST_EDITED_FILES);
+
+        SessionSummary {
+            started_at: state.started_at,
+            preview_secs: state.started.elapsed().as_secs_f64(),
+            compiles: state.compiles,
+            errors: state.errors,
+            average_compile_ms: if state.compiles == 0 {
+                0.
+            } else {
+                state.compile_time.as_secs_f64() * 1000. / state.compiles as f64
+            },
+            most_edited_files,
+        }
+    }
+
+    /// Writes the summary of the session if asked for, returning the summary.
+    pub async fn finish(&self) -> SessionSummary {
+        let summary = self.summary();
+        if let Some(path) = &self.summary_path {
+            match write_summary(path, &summary).await {
+                Ok(()) => info!("Session: wrote the summary to {:?}", path),
+                Err(err) => error!(
+                    "Session: failed to write the summary to {:?}: {}",
+                    path, err
+                ),
+            }
+        }
+        summary
+    }
+}
+
+async fn write_summary(path: &Path, summary: &SessionSummary) -> std::io::Result<()> {
+    let json = serde_json::to_vec_pretty(summary).map_err(std::io::Error::other)?;
+    tokio::fs::write(path, json).await
+}
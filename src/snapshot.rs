@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use typst::syntax::{FileId, Source, Span};
+use typst::World;
+use typst_ts_core::TypstDocument;
+
+use crate::source_map::document_items;
+
+/// The latest compiled document together with the sources it was compiled
+/// from, so that read-only requests don't have to wait for the compiler,
+/// which may be busy with the next compilation.
+pub(crate) struct CompileSnapshot {
+    pub document: Arc<TypstDocument>,
+    sources: HashMap<FileId, Source>,
+}
+
+impl CompileSnapshot {
+    /// Takes the sources of the spans rendered in the document from the world
+    /// the document was compiled with.
+    ///
+    /// Sources are reference counted, so this doesn't copy their text.
+    pub fn new(world: &dyn World, document: Arc<TypstDocument>) -> Self {
+        let mut sources = HashMap::new();
+        sources.insert(world.main().id(), world.main());
+        for item in document_items(&document) {
+            let Some(id) = item.span.id() else {
+                continue;
+            };
+            if sources.contains_key(&id) {
+                continue;
+            }
+            if let Ok(source) = world.source(id) {
+                sources.insert(id, source);
+            }
+        }
+
+        Self { document, sources }
+    }
+
+    pub fn source(&self, id: FileId) -> Option<&Source> {
+        self.sources.get(&id)
+    }
+
+    /// Resolves a span to the 0-based line and column of the start and the
+    /// end of its range, where the start is moved by `offset` bytes if given.
+    pub fn resolve(
+        &self,
+        span: Span,
+        offset: Option<usize>,
+    ) -> Option<(Option<(usize, usize)>, Option<(usize, usize)>)> {
+        resolve_range(self.source(span.id()?)?, span, offset)
+    }
+}
+
+pub(crate) fn resolve_range(
+    source: &Source,
+    span: Span,
+    offset: Option<usize>,
+) -> Option<(Option<(usize, usize)>, Option<(usize, usize)>)> {
+    let range = source.range(span)?;
+    let start = offset.map_or(range.start, |offset| (range.start + offset).min(range.end));
+    let line_column =
+        |offset: usize| Some((source.byte_to_line(offset)?, source.byte_to_column(offset)?));
+    Some((line_column(start), line_column(range.end)))
+}
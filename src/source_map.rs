@@ -0,0 +1,231 @@
+use std::path::Path;
+
+use serde::Serialize;
+use typst::layout::{Abs, Frame, FrameItem, Point, Transform};
+use typst::syntax::Span;
+use typst::World;
+use typst_ts_core::debug_loc::DocumentPosition;
+use typst_ts_core::TypstDocument;
+
+/// A rendered element mapped back to its source.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceMapEntry {
+    /// The 1-based page number.
+    page: usize,
+    /// The bounding box of the element, `[x, y, width, height]` in points.
+    rect: [f64; 4],
+    /// The path of the source file relative to the root or the package.
+    file: String,
+    /// The package the source file belongs to, e.g. `@preview/cetz:0.1.0`.
+    package: Option<String>,
+    /// The 0-based line and column of the start of the source range.
+    start: (usize, usize),
+    /// The 0-based line and column of the end of the source range.
+    end: (usize, usize),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceMap {
+    entries: Vec<SourceMapEntry>,
+}
+
+/// Map the rendered elements of the document to their source ranges.
+pub fn source_map(world: &dyn World, document: &TypstDocument) -> SourceMap {
+    let entries = document_items(document)
+        .into_iter()
+        .filter_map(|item| {
+            let (file, package, start, end) = resolve(world, item.span)?;
+            Some(SourceMapEntry {
+                page: item.page,
+                rect: item.rect,
+                file,
+                package,
+                start,
+                end,
+            })
+        })
+        .collect();
+
+    SourceMap { entries }
+}
+
+/// Write the source map of the document to `path` as JSON.
+pub fn export_source_map(
+    world: &dyn World,
+    document: &TypstDocument,
+    path: &Path,
+) -> std::io::Result<()> {
+    let source_map = source_map(world, document);
+    let json = serde_json::to_vec(&source_map).map_err(std::io::Error::other)?;
+    std::fs::write(path, json)
+}
+
+/// A rectangle on a page of the document.
+#[derive(Debug, Clone, Serialize)]
+pub struct PageRect {
+    /// The 1-based page number.
+    page: usize,
+    /// `[x, y, width, height]` in points.
+    rect: [f64; 4],
+}
+
+/// Find the rectangles of the rendered elements whose spans match.
+pub(crate) fn rects_of(document: &TypstDocument, matches: impl Fn(Span) -> bool) -> Vec<PageRect> {
+    document_items(document)
+        .into_iter()
+        .filter(|item| matches(item.span))
+        .map(|item| PageRect {
+            page: item.page,
+            rect: item.rect,
+        })
+        .collect()
+}
+
+/// Find the span of the rendered element closest to a position in the
+/// document.
+pub(crate) fn span_at(document: &TypstDocument, position: &DocumentPosition) -> Option<Span> {
+    let page = document.pages.get(position.page_no.checked_sub(1)?)?;
+    let items = page_items(position.page_no, page);
+
+    let (x, y) = (position.x as f64, position.y as f64);
+    let distance = |item: &Item| {
+        let [left, top, width, height] = item.rect;
+        let dx = (left - x).max(x - left - width).max(0.);
+        let dy = (top - y).max(y - top - height).max(0.);
+        dx * dx + dy * dy
+    };
+    items
+        .iter()
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .map(|item| item.span)
+}
+
+/// A rendered element with a span, before its source is resolved.
+pub(crate) struct Item {
+    /// The 1-based page number.
+    pub page: usize,
+    /// The bounding box of the element, `[x, y, width, height]` in points.
+    pub rect: [f64; 4],
+    pub span: Span,
+    /// The y coordinate of the baseline of a text element.
+    pub baseline: Option<f64>,
+}
+
+/// Collect the rendered elements with spans on a page.
+pub(crate) fn page_items(page_no: usize, page: &Frame) -> Vec<Item> {
+    let mut items = vec![];
+    let mut collector = Collector {
+        page: page_no,
+        items: &mut items,
+    };
+    collector.frame(page, Transform::identity());
+    items
+}
+
+pub(crate) fn document_items(document: &TypstDocument) -> Vec<Item> {
+    document
+        .pages
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, page)| page_items(idx + 1, page))
+        .collect()
+}
+
+struct Collector<'a> {
+    page: usize,
+    items: &'a mut Vec<Item>,
+}
+
+impl Collector<'_> {
+    fn frame(&mut self, frame: &Frame, ts: Transform) {
+        for (pos, item) in frame.items() {
+            let ts = ts.pre_concat(Transform::translate(pos.x, pos.y));
+            match item {
+                FrameItem::Group(group) => {
+                    self.frame(&group.frame, ts.pre_concat(group.transform));
+                }
+                FrameItem::Text(text) => {
+                    let Some(span) = text
+                        .glyphs
+                        .iter()
+                        .map(|glyph| glyph.span.0)
+                        .find(|span| !span.is_detached())
+                    else {
+                        continue;
+                    };
+                    let width = text.width();
+                    // the position of a text item is its baseline
+                    self.push(span, ts, Abs::zero(), -text.size, width, text.size);
+                    if let Some(item) = self.items.last_mut() {
+                        item.baseline = Some(Point::zero().transform(ts).y.to_pt());
+                    }
+                }
+                FrameItem::Shape(shape, span) => {
+                    let size = shape.geometry.bbox_size();
+                    self.push(*span, ts, Abs::zero(), Abs::zero(), size.x, size.y);
+                }
+                FrameItem::Image(_, size, span) => {
+                    self.push(*span, ts, Abs::zero(), Abs::zero(), size.x, size.y);
+                }
+                FrameItem::Meta(..) => {}
+            }
+        }
+    }
+
+    fn push(&mut self, span: Span, ts: Transform, x: Abs, y: Abs, w: Abs, h: Abs) {
+        if span.is_detached() {
+            return;
+        }
+
+        // the bounding box of the transformed rectangle
+        let corners = [
+            Point::new(x, y),
+            Point::new(x + w, y),
+            Point::new(x, y + h),
+            Point::new(x + w, y + h),
+        ]
+        .map(|p| p.transform(ts));
+        let min_x = corners
+            .iter()
+            .map(|p| p.x.to_pt())
+            .fold(f64::INFINITY, f64::min);
+        let min_y = corners
+            .iter()
+            .map(|p| p.y.to_pt())
+            .fold(f64::INFINITY, f64::min);
+        let max_x = corners
+            .iter()
+            .map(|p| p.x.to_pt())
+            .fold(f64::NEG_INFINITY, f64::max);
+        let max_y = corners
+            .iter()
+            .map(|p| p.y.to_pt())
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        self.items.push(Item {
+            page: self.page,
+            rect: [min_x, min_y, max_x - min_x, max_y - min_y],
+            span,
+            baseline: None,
+        });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn resolve(
+    world: &dyn World,
+    span: Span,
+) -> Option<(String, Option<String>, (usize, usize), (usize, usize))> {
+    let id = span.id()?;
+    let source = world.source(id).ok()?;
+    let range = source.range(span)?;
+    let line_column =
+        |offset: usize| Some((source.byte_to_line(offset)?, source.byte_to_column(offset)?));
+
+    Some((
+        id.vpath().as_rootless_path().to_string_lossy().to_string(),
+        id.package().map(ToString::to_string),
+        line_column(range.start)?,
+        line_column(range.end)?,
+    ))
+}
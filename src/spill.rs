@@ -0,0 +1,237 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::{Read, Seek, SeekFrom};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use log::{error, info};
+use serde::Serialize;
+
+/// The number of spilled updates kept on disk. Older ones are removed, as
+/// the webviews fetch an update as soon as they are told about it.
+const KEPT_FILES: usize = 16;
+
+/// Writes the updates of huge documents to files, which the webviews fetch
+/// over HTTP in ranges, rather than sending them through the websockets.
+pub(crate) struct SpillStore {
+    dir: PathBuf,
+    /// Updates larger than this many bytes are spilled.
+    threshold: usize,
+    /// The url the files are served at, e.g. `http://127.0.0.1:4321`.
+    base_url: String,
+    next_id: AtomicU64,
+    /// The files written so far, oldest first.
+    files: Mutex<VecDeque<PathBuf>>,
+    /// The origins of the webviews, which are the only pages allowed to
+    /// fetch the updates.
+    origins: Arc<Mutex<HashSet<String>>>,
+}
+
+/// Sent to a webview in place of a spilled update.
+#[derive(Debug, Serialize)]
+struct SpilledUpdate {
+    url: String,
+    size: usize,
+}
+
+impl SpillStore {
+    /// Creates the directory of the spilled updates and starts serving it on
+    /// the given address.
+    pub async fn start(threshold: usize, host: &str) -> Result<Arc<Self>, String> {
+        // each preview of the process has a directory of its own
+        let dir = tempfile::Builder::new()
+            .prefix("typst-preview-")
+            .tempdir()
+            .map_err(|err| format!("failed to create the directory of spilled updates: {err}"))?
+            .into_path();
+
+        let addr = host
+            .parse::<SocketAddr>()
+            .map_err(|err| format!("invalid artifact host {host}: {err}"))?;
+        let served = dir.clone();
+        let origins = Arc::<Mutex<HashSet<String>>>::default();
+        let allowed = origins.clone();
+        let make_service = make_service_fn(move |_| {
+            let dir = served.clone();
+            let origins = allowed.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req| {
+                    let dir = dir.clone();
+                    let origins = origins.clone();
+                    async move {
+                        let res = tokio::task::spawn_blocking(move || serve(&dir, &origins, &req))
+                            .await
+                            .unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR));
+                        Ok::<_, hyper::Error>(res)
+                    }
+                }))
+            }
+        });
+        let server = hyper::Server::try_bind(&addr)
+            .map_err(|err| format!("failed to bind the artifact host {host}: {err}"))?
+            .serve(make_service);
+        let addr = server.local_addr();
+        info!("SpillStore: serving spilled updates on http://{}", addr);
+        tokio::spawn(async move {
+            if let Err(err) = server.await {
+                error!("SpillStore: server error: {}", err);
+            }
+        });
+
+        Ok(Arc::new(Self {
+            dir,
+            threshold,
+            base_url: format!("http://{addr}"),
+            next_id: AtomicU64::new(0),
+            files: Mutex::default(),
+            origins,
+        }))
+    }
+
+    /// Allows the page of a webview connected from `origin` to fetch the
+    /// updates.
+    pub fn allow_origin(&self, origin: &str) {
+        self.origins.lock().unwrap().insert(origin.to_owned());
+    }
+
+    /// Returns the message to send to a webview for an update, which is the
+    /// update itself unless it is spilled.
+    pub fn spill(&self, data: Vec<u8>) -> Vec<u8> {
+        if data.len() <= self.threshold {
+            return data;
+        }
+        // the name can't be guessed, as the server is reachable by any
+        // process of the machine
+        let name = format!(
+            "update-{}-{:032x}.bin",
+            self.next_id.fetch_add(1, Ordering::Relaxed),
+            rand::random::<u128>()
+        );
+        let path = self.dir.join(&name);
+        if let Err(err) = std::fs::write(&path, &data) {
+            error!("SpillStore: failed to write {:?}: {}", path, err);
+            return data;
+        }
+
+        let mut files = self.files.lock().unwrap();
+        files.push_back(path);
+        while files.len() > KEPT_FILES {
+            let _ = std::fs::remove_file(files.pop_front().unwrap());
+        }
+
+        let spilled = SpilledUpdate {
+            url: format!("{}/{name}", self.base_url),
+            size: data.len(),
+        };
+        format!("spilled,{}", serde_json::to_string(&spilled).unwrap()).into_bytes()
+    }
+
+    /// Removes the spilled updates, when the preview exits.
+    pub fn remove_all(&self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn serve(dir: &Path, origins: &Mutex<HashSet<String>>, req: &Request<Body>) -> Response<Body> {
+    // webviews are served from other origins, e.g. by the editor, while other
+    // pages of the browser must not read the updates
+    let origin = req.headers().get(header::ORIGIN);
+    let allowed = origin
+        .and_then(|origin| origin.to_str().ok())
+        .is_some_and(|origin| origins.lock().unwrap().contains(origin));
+    if origin.is_some() && !allowed {
+        return status(StatusCode::FORBIDDEN);
+    }
+
+    let mut res = match *req.method() {
+        Method::OPTIONS => status(StatusCode::NO_CONTENT),
+        Method::GET => serve_file(dir, req),
+        _ => status(StatusCode::METHOD_NOT_ALLOWED),
+    };
+    let Some(origin) = origin else {
+        return res;
+    };
+    let headers = res.headers_mut();
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+    headers.insert(header::VARY, header::HeaderValue::from_static("origin"));
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        header::HeaderValue::from_static("range"),
+    );
+    headers.insert(
+        header::ACCESS_CONTROL_EXPOSE_HEADERS,
+        header::HeaderValue::from_static("content-range"),
+    );
+    res
+}
+
+fn serve_file(dir: &Path, req: &Request<Body>) -> Response<Body> {
+    let name = req.uri().path().trim_start_matches('/');
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return status(StatusCode::NOT_FOUND);
+    }
+    let Ok(mut file) = std::fs::File::open(dir.join(name)) else {
+        return status(StatusCode::NOT_FOUND);
+    };
+    let Ok(size) = file.metadata().map(|meta| meta.len()) else {
+        return status(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+
+    let range = req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok());
+    let (start, end) = match range {
+        None => (0, size),
+        Some(range) => match parse_range(range, size) {
+            Some(range) => range,
+            None => {
+                let mut res = status(StatusCode::RANGE_NOT_SATISFIABLE);
+                res.headers_mut().insert(
+                    header::CONTENT_RANGE,
+                    header::HeaderValue::from_str(&format!("bytes */{size}")).unwrap(),
+                );
+                return res;
+            }
+        },
+    };
+
+    let mut data = Vec::with_capacity((end - start) as usize);
+    let read = file
+        .seek(SeekFrom::Start(start))
+        .and_then(|_| file.take(end - start).read_to_end(&mut data));
+    if read.is_err() {
+        return status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let mut res = Response::new(Body::from(data));
+    if range.is_some() {
+        *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+        res.headers_mut().insert(
+            header::CONTENT_RANGE,
+            header::HeaderValue::from_str(&format!("bytes {start}-{}/{size}", end - 1)).unwrap(),
+        );
+    }
+    res
+}
+
+/// Parses a single range of bytes, e.g. `bytes=0-1023`, into the start and
+/// the exclusive end.
+fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let start = start.parse::<u64>().ok()?;
+    let end = match end {
+        "" => size,
+        end => end.parse::<u64>().ok()?.saturating_add(1).min(size),
+    };
+    (start < end).then_some((start, end))
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = code;
+    res
+}
@@ -0,0 +1,137 @@
+use std::num::NonZeroUsize;
+use std::path::Path;
+
+use serde::Serialize;
+use typst::foundations::{Content, NativeElement, Selector, Value};
+use typst::introspection::Introspector;
+use typst::layout::TrackSizings;
+use typst::model::{FigureElem, HeadingElem, TableElem};
+use typst::visualize::ImageElem;
+use typst_ts_core::debug_loc::DocumentPosition;
+use typst_ts_core::TypstDocument;
+
+/// The logical structure of a document in reading order, for consumers
+/// which can't use the rendered pages, e.g. screen readers.
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentStructure {
+    nodes: Vec<StructureNode>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum StructureNode {
+    Heading {
+        level: usize,
+        title: String,
+        position: DocumentPosition,
+    },
+    Figure {
+        caption: Option<String>,
+        images: Vec<ImageInfo>,
+        tables: Vec<TableInfo>,
+        position: DocumentPosition,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageInfo {
+    alt: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableInfo {
+    columns: usize,
+    /// The text of the cells, row by row.
+    rows: Vec<Vec<String>>,
+}
+
+/// Extract the headings and figures of the document in reading order.
+///
+/// Only elements known to the introspector are visited, so images and tables
+/// are reported when they are placed in figures.
+pub fn structure(document: &TypstDocument) -> DocumentStructure {
+    let introspector = &document.introspector;
+    let selector = Selector::Or(
+        [HeadingElem::elem().select(), FigureElem::elem().select()]
+            .into_iter()
+            .collect(),
+    );
+
+    let nodes = introspector
+        .query(&selector)
+        .iter()
+        .filter_map(|elem| structure_node(introspector, elem))
+        .collect();
+
+    DocumentStructure { nodes }
+}
+
+/// Write the structure of the document to `path` as JSON.
+pub fn export_structure(document: &TypstDocument, path: &Path) -> std::io::Result<()> {
+    let json = serde_json::to_vec(&structure(document)).map_err(std::io::Error::other)?;
+    std::fs::write(path, json)
+}
+
+fn structure_node(introspector: &Introspector, elem: &Content) -> Option<StructureNode> {
+    let position = introspector.position(elem.location()?).into();
+
+    if elem.is::<HeadingElem>() {
+        let level = elem.expect_field_by_name::<NonZeroUsize>("level");
+        let body = elem.expect_field_by_name::<Content>("body");
+        return Some(StructureNode::Heading {
+            level: level.get(),
+            title: body.plain_text().trim().to_owned(),
+            position,
+        });
+    }
+
+    let body = elem.expect_field_by_name::<Content>("body");
+    let caption = match elem.get_by_name("caption") {
+        Some(Value::Content(caption)) => Some(caption.plain_text().trim().to_owned()),
+        _ => None,
+    };
+    let images = body
+        .query(ImageElem::elem().select())
+        .into_iter()
+        .map(|image| ImageInfo {
+            alt: match image.get_by_name("alt") {
+                Some(Value::Str(alt)) => Some(alt.to_string()),
+                _ => None,
+            },
+        })
+        .collect();
+    let tables = body
+        .query(TableElem::elem().select())
+        .into_iter()
+        .map(|table| table_info(&table))
+        .collect();
+
+    Some(StructureNode::Figure {
+        caption,
+        images,
+        tables,
+        position,
+    })
+}
+
+fn table_info(table: &Content) -> TableInfo {
+    let columns = table
+        .get_by_name("columns")
+        .and_then(|columns| columns.cast::<TrackSizings>().ok())
+        .map_or(1, |columns| columns.0.len().max(1));
+    let cells = match table.get_by_name("children") {
+        Some(Value::Array(children)) => children
+            .into_iter()
+            .map(|child| match child {
+                Value::Content(child) => child.plain_text().trim().to_owned(),
+                _ => String::new(),
+            })
+            .collect::<Vec<_>>(),
+        _ => vec![],
+    };
+
+    TableInfo {
+        columns,
+        rows: cells.chunks(columns).map(<[String]>::to_vec).collect(),
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use log::{info, warn};
+use typst::diag::SourceResult;
+use typst::model::Document;
+use typst::World;
+use typst_ts_compiler::service::{
+    CompileActor, CompileDriver, CompileEnv, CompileExporter, CompileMiddleware, Compiler,
+    WorldExporter,
+};
+
+use crate::diagnostics::file_diagnostics;
+use crate::export::export_document;
+use crate::WatchArgs;
+
+/// Prints the diagnostics of each compilation to stderr.
+struct PrintDiagnostics<C> {
+    inner: C,
+    root: PathBuf,
+}
+
+impl<C: Compiler> CompileMiddleware for PrintDiagnostics<C> {
+    type Compiler = C;
+
+    fn inner(&self) -> &Self::Compiler {
+        &self.inner
+    }
+
+    fn inner_mut(&mut self) -> &mut Self::Compiler {
+        &mut self.inner
+    }
+
+    fn wrap_compile(&mut self, env: &mut CompileEnv) -> SourceResult<Arc<Document>> {
+        let doc = self.inner_mut().compile(env);
+        match &doc {
+            Ok(_) => info!("Watch: compiled successfully"),
+            Err(errors) => {
+                for file in file_diagnostics(self.inner.world(), &self.root, errors) {
+                    eprint!("{file}");
+                }
+            }
+        }
+        doc
+    }
+}
+
+impl<C: Compiler + WorldExporter> WorldExporter for PrintDiagnostics<C> {
+    fn export(&mut self, output: Arc<Document>) -> SourceResult<()> {
+        self.inner.export(output)
+    }
+}
+
+/// Compiles the document whenever its files change and writes the exports,
+/// without serving a preview. Runs until the process is interrupted.
+pub async fn watch(compiler_driver: CompileDriver, args: WatchArgs) {
+    let root = compiler_driver.world.root.clone();
+    let export = args.export;
+    let driver = CompileExporter::new(compiler_driver).with_exporter(
+        move |world: &dyn World, doc: Arc<Document>| {
+            if !export_document(world, &doc, &export) {
+                warn!("Watch: some exports failed");
+            }
+            Ok(())
+        },
+    );
+    let driver = PrintDiagnostics {
+        inner: driver,
+        root: root.as_ref().to_owned(),
+    };
+
+    let (server, _client) = CompileActor::new(driver, root.as_ref().to_owned())
+        .with_watch(true)
+        .split();
+    server.spawn().await;
+
+    info!("Watch: watching {:?} for changes", root);
+    let _ = tokio::signal::ctrl_c().await;
+    info!("Ctrl-C received, exiting");
+}